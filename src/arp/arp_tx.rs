@@ -7,13 +7,13 @@ use pnet::util::MacAddr;
 
 use std::net::Ipv4Addr;
 
-pub struct ArpRequestTx<T: EthernetTx> {
+pub struct ArpTx<T: EthernetTx> {
     ethernet: T,
 }
 
-impl<T: EthernetTx> ArpRequestTx<T> {
+impl<T: EthernetTx> ArpTx<T> {
     pub fn new(ethernet: T) -> Self {
-        ArpRequestTx { ethernet: ethernet }
+        ArpTx { ethernet: ethernet }
     }
 
     /// Sends an Arp request packet to the network. More specifically Ipv4 to
@@ -22,6 +22,38 @@ impl<T: EthernetTx> ArpRequestTx<T> {
         let builder = ArpBuilder::new_request(self.ethernet.src(), sender_ip, target_ip);
         self.ethernet.send(1, ArpPacket::minimum_packet_size(), builder)
     }
+
+    /// Sends a gratuitous Arp announcing `ip` as ours. Both the sender and
+    /// target protocol address are set to `ip`, so every neighbor that
+    /// receives it, whether or not it already has an entry for `ip`,
+    /// updates its cache to point at our MAC. Meant to be sent right after
+    /// `StackInterface::add_ipv4` claims an address, so neighbors who cached
+    /// a stale mapping from a previous owner of the address notice the
+    /// change without having to wait for their entry to expire.
+    pub fn send_announcement(&mut self, ip: Ipv4Addr) -> TxResult {
+        let mac = self.ethernet.src();
+        let builder = ArpBuilder::new(ArpOperations::Request,
+                                       mac,
+                                       ip,
+                                       MacAddr::new(0, 0, 0, 0, 0, 0),
+                                       ip);
+        self.ethernet.send(1, ArpPacket::minimum_packet_size(), builder)
+    }
+
+    /// Sends an Arp probe for `ip`, the first step of RFC 5227 duplicate
+    /// address detection. Like `send`, except the sender protocol address
+    /// is `0.0.0.0` rather than one of our own addresses, since at probe
+    /// time we do not yet own `ip` and must not claim it as a source
+    /// address while checking whether another host already has it.
+    pub fn send_probe(&mut self, ip: Ipv4Addr) -> TxResult {
+        let mac = self.ethernet.src();
+        let builder = ArpBuilder::new(ArpOperations::Request,
+                                       mac,
+                                       Ipv4Addr::new(0, 0, 0, 0),
+                                       MacAddr::new(0, 0, 0, 0, 0, 0),
+                                       ip);
+        self.ethernet.send(1, ArpPacket::minimum_packet_size(), builder)
+    }
 }
 
 pub struct ArpReplyTx<T: EthernetTx> {
@@ -52,29 +84,42 @@ pub struct ArpBuilder {
 }
 
 impl ArpBuilder {
-    /// Constructs a new `ArpBuilder` able to construct Arp packets
-    pub fn new_request(sender_mac: MacAddr, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
+    /// Constructs a new `ArpBuilder` for the given operation and addresses,
+    /// with no field hardcoded. `new_request`/`new_reply` below are
+    /// convenience constructors for the two common cases; `send_announcement`/
+    /// `send_probe` on `ArpTx` go through this one directly since gratuitous
+    /// Arp and duplicate address probes need addresses neither convenience
+    /// constructor can express.
+    pub fn new(operation: ArpOperation,
+               sender_mac: MacAddr,
+               sender_ip: Ipv4Addr,
+               target_mac: MacAddr,
+               target_ip: Ipv4Addr)
+               -> Self {
         ArpBuilder {
-            operation: ArpOperations::Request,
+            operation: operation,
             sender_mac: sender_mac,
             sender_ip: sender_ip,
-            target_mac: MacAddr::new(0, 0, 0, 0, 0, 0),
+            target_mac: target_mac,
             target_ip: target_ip,
         }
     }
 
+    /// Constructs a new `ArpBuilder` able to construct Arp packets
+    pub fn new_request(sender_mac: MacAddr, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
+        Self::new(ArpOperations::Request,
+                  sender_mac,
+                  sender_ip,
+                  MacAddr::new(0, 0, 0, 0, 0, 0),
+                  target_ip)
+    }
+
     pub fn new_reply(sender_mac: MacAddr,
                      sender_ip: Ipv4Addr,
                      target_mac: MacAddr,
                      target_ip: Ipv4Addr)
                      -> Self {
-        ArpBuilder {
-            operation: ArpOperations::Reply,
-            sender_mac: sender_mac,
-            sender_ip: sender_ip,
-            target_mac: target_mac,
-            target_ip: target_ip,
-        }
+        Self::new(ArpOperations::Reply, sender_mac, sender_ip, target_mac, target_ip)
     }
 }
 
@@ -5,6 +5,7 @@ use stack::StackInterfaceMsg;
 use std::collections::HashMap;
 use std::net::Ipv4Addr;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use std::sync::mpsc::{self, Receiver, Sender};
 
@@ -12,12 +13,171 @@ mod arp_rx;
 mod arp_tx;
 
 pub use self::arp_rx::ArpRx;
-pub use self::arp_tx::{ArpBuilder, ArpTx};
+pub use self::arp_tx::{ArpBuilder, ArpReplyTx, ArpTx};
+
+/// Default time an Arp table entry is considered valid before it must be
+/// re-resolved.
+pub static DEFAULT_ENTRY_TTL_SECS: u64 = 60;
+
+/// Minimum time between two outgoing Arp requests for the same target,
+/// so a flood of cache misses for an unreachable host does not flood the
+/// network with requests.
+pub static MIN_REQUEST_INTERVAL_SECS: u64 = 1;
+
+/// Maximum number of live entries `TableData` keeps before evicting
+/// whichever one expires soonest to make room for a new IP, so a flood of
+/// spoofed Arp replies for distinct addresses cannot grow the table
+/// without bound.
+pub static MAX_TABLE_ENTRIES: usize = 1024;
+
+/// Abstraction over where `ArpTable` gets its notion of "now" from. Lets
+/// tests drive the passage of time deterministically instead of depending
+/// on the real system clock, the same way `MockPnet` decouples the crate
+/// from a real network device.
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+}
+
+/// `Clock` implementation backed by the real monotonic OS clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Minimal IP -> MAC lookup surface shared by every Arp cache
+/// implementation. Kept separate from `ArpTable` so targets without a
+/// global allocator (and without threads to block listeners on) can plug
+/// in their own storage while still working with the rest of the stack.
+pub trait Cache {
+    /// Records that `ip` resolves to `mac`.
+    fn fill(&mut self, ip: &Ipv4Addr, mac: &MacAddr);
+
+    /// Looks up the MAC currently cached for `ip`, if any.
+    fn lookup(&mut self, ip: &Ipv4Addr) -> Option<MacAddr>;
+}
+
+/// `Cache` backed by a `HashMap`, for code that only needs plain storage
+/// without `ArpTable`'s TTL expiry and blocking listeners. `ArpTable` itself
+/// does not go through this impl; it keeps its own map of `Entry` values.
+impl Cache for HashMap<Ipv4Addr, MacAddr> {
+    fn fill(&mut self, ip: &Ipv4Addr, mac: &MacAddr) {
+        self.insert(*ip, *mac);
+    }
+
+    fn lookup(&mut self, ip: &Ipv4Addr) -> Option<MacAddr> {
+        self.get(ip).cloned()
+    }
+}
+
+/// `Cache` backed by a caller-provided, fixed-size slice instead of a
+/// `HashMap`. Lets the stack run on targets with no global allocator, at
+/// the cost of a fixed memory budget for neighbor state.
+///
+/// Entries are `(Ipv4Addr, MacAddr, u64)` tuples, kept sorted by IP so
+/// `lookup` can binary search, with the `u64` acting as an access counter
+/// used to find the least-recently-used slot to evict when `fill` is
+/// called on a full slice.
+pub struct SliceCache<'a> {
+    entries: &'a mut [(Ipv4Addr, MacAddr, u64)],
+    len: usize,
+    clock: u64,
+}
+
+impl<'a> SliceCache<'a> {
+    /// Wraps `entries` as an empty cache. Every slot in `entries` is
+    /// overwritten as the cache fills up, so its initial contents do not
+    /// matter.
+    pub fn new(entries: &'a mut [(Ipv4Addr, MacAddr, u64)]) -> Self {
+        SliceCache {
+            entries: entries,
+            len: 0,
+            clock: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn find(&self, ip: &Ipv4Addr) -> Result<usize, usize> {
+        self.entries[..self.len].binary_search_by_key(ip, |&(ip, _, _)| ip)
+    }
+
+    fn least_recently_used(&self) -> usize {
+        self.entries[..self.len]
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &(_, _, last_used))| last_used)
+            .map(|(i, _)| i)
+            .expect("SliceCache must not be empty")
+    }
+}
+
+impl<'a> Cache for SliceCache<'a> {
+    fn fill(&mut self, ip: &Ipv4Addr, mac: &MacAddr) {
+        let now = self.tick();
+        match self.find(ip) {
+            Ok(i) => self.entries[i] = (*ip, *mac, now),
+            Err(i) if self.len < self.entries.len() => {
+                let mut j = self.len;
+                while j > i {
+                    self.entries[j] = self.entries[j - 1];
+                    j -= 1;
+                }
+                self.entries[i] = (*ip, *mac, now);
+                self.len += 1;
+            }
+            Err(_) => {
+                let victim = self.least_recently_used();
+                self.entries[victim] = (*ip, *mac, now);
+                self.entries[..self.len].sort_by_key(|&(ip, _, _)| ip);
+            }
+        }
+    }
+
+    fn lookup(&mut self, ip: &Ipv4Addr) -> Option<MacAddr> {
+        let now = self.tick();
+        match self.find(ip) {
+            Ok(i) => {
+                self.entries[i].2 = now;
+                Some(self.entries[i].1)
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+/// The state of a cache lookup that did not immediately resolve to a MAC.
+pub enum Miss {
+    /// No resolution is in flight for this IP, a request should be sent.
+    Unresolved(Receiver<MacAddr>),
+    /// A resolution is already in flight for this IP. The caller should
+    /// *not* send another request, just wait on the returned `Receiver`.
+    Pending(Receiver<MacAddr>),
+    /// Like `Unresolved`, the target had an entry that just expired rather
+    /// than never having been resolved, so a fresh request should be sent.
+    /// Unlike `Unresolved`, the caller must also bump its `VersionedTx`
+    /// revision before sending: any `Tx` created while the now-stale entry
+    /// was still valid may have baked in a MAC that is no longer correct.
+    Expired(Receiver<MacAddr>),
+}
+
+pub struct Entry {
+    pub mac: MacAddr,
+    pub expires_at: Instant,
+}
 
 #[derive(Default)]
 pub struct TableData {
-    pub table: HashMap<Ipv4Addr, MacAddr>,
-    pub listeners: HashMap<Ipv4Addr, Vec<Sender<MacAddr>>>,
+    pub table: HashMap<Ipv4Addr, Entry>,
+    pub listeners: HashMap<Ipv4Addr, Vec<(Instant, Sender<MacAddr>)>>,
+    /// When an outgoing Arp request was last sent for a given target, so
+    /// `add_listener` can rate limit how often a new one is sent.
+    pub last_request: HashMap<Ipv4Addr, Instant>,
 }
 
 impl TableData {
@@ -25,6 +185,39 @@ impl TableData {
         TableData {
             table: HashMap::new(),
             listeners: HashMap::new(),
+            last_request: HashMap::new(),
+        }
+    }
+
+    /// Inserts `mac` for `ip`, valid until `ttl` after `now`, and notifies
+    /// any listeners waiting for this IP to resolve. If this is a new key
+    /// and the table is already at `MAX_TABLE_ENTRIES`, evicts whichever
+    /// live entry is closest to expiring first. Returns `true` if this
+    /// changed the table.
+    pub fn insert(&mut self, ip: Ipv4Addr, mac: MacAddr, now: Instant, ttl: Duration) -> bool {
+        if !self.table.contains_key(&ip) && self.table.len() >= MAX_TABLE_ENTRIES {
+            self.evict_oldest();
+        }
+        let expires_at = now + ttl;
+        let old = self.table.insert(ip, Entry { mac: mac, expires_at: expires_at });
+        if let Some(listeners) = self.listeners.remove(&ip) {
+            for (_, listener) in listeners {
+                listener.send(mac).unwrap_or(());
+            }
+        }
+        self.last_request.remove(&ip);
+        old.map(|e| e.mac) != Some(mac)
+    }
+
+    /// Evicts whichever entry in `table` is closest to expiring, to make
+    /// room for a new key once `MAX_TABLE_ENTRIES` is reached.
+    fn evict_oldest(&mut self) {
+        let victim = self.table
+            .iter()
+            .min_by_key(|&(_, entry)| entry.expires_at)
+            .map(|(&ip, _)| ip);
+        if let Some(ip) = victim {
+            self.table.remove(&ip);
         }
     }
 }
@@ -35,13 +228,28 @@ impl TableData {
 #[derive(Clone)]
 pub struct ArpTable {
     data: Arc<Mutex<TableData>>,
+    clock: Arc<Clock>,
+    ttl: Duration,
 }
 
 impl ArpTable {
-    /// Creates a new `ArpTable` with no entries in it.
+    /// Creates a new `ArpTable` with no entries in it. Entries live for
+    /// `DEFAULT_ENTRY_TTL_SECS` seconds and time is read from the real OS
+    /// clock.
     pub fn new() -> ArpTable {
+        Self::with_clock(Arc::new(SystemClock), Duration::new(DEFAULT_ENTRY_TTL_SECS, 0))
+    }
+
+    /// Creates a new `ArpTable` using the given `clock` as its time source
+    /// and `ttl` as the lifetime of each entry. Mainly useful for tests that
+    /// want to control the passage of time.
+    pub fn with_clock(clock: Arc<Clock>, ttl: Duration) -> ArpTable {
         let data = Arc::new(Mutex::new(TableData::new()));
-        ArpTable { data: data }
+        ArpTable {
+            data: data,
+            clock: clock,
+            ttl: ttl,
+        }
     }
 
     pub fn data(&self) -> Arc<Mutex<TableData>> {
@@ -58,35 +266,104 @@ impl ArpTable {
         Box::new(ArpRx::new(listener)) as Box<EthernetListener>
     }
 
-    /// Queries the table for a MAC. If it does not exist a request is sent and
-    /// the call is blocked
-    /// until a reply has arrived
-    pub fn get(&mut self, target_ip: Ipv4Addr) -> Result<MacAddr, Receiver<MacAddr>> {
+    /// Queries the table for a MAC. If it does not exist, or the entry has
+    /// expired, `Err` is returned describing whether a fresh request should
+    /// be sent because this is the first miss (`Miss::Unresolved`) or
+    /// because the previous entry just expired (`Miss::Expired`), or
+    /// whether one is already pending for this IP (`Miss::Pending`), so the
+    /// caller can avoid flooding duplicate Arp requests for the same
+    /// target.
+    pub fn get(&mut self, target_ip: Ipv4Addr) -> Result<MacAddr, Miss> {
         let mut data = self.data.lock().unwrap();
-        if let Some(mac) = data.table.get(&target_ip) {
-            return Ok(*mac);
+        let now = self.clock.now();
+        let mut expired = false;
+        if let Some(entry) = data.table.get(&target_ip) {
+            if entry.expires_at > now {
+                return Ok(entry.mac);
+            }
+            expired = true;
+        }
+        if expired {
+            // The entry is stale, drop it so `Tx` instances that still
+            // have it cached can be told to re-resolve.
+            data.table.remove(&target_ip);
+        }
+        match Self::add_listener(&mut data, target_ip, now) {
+            Miss::Unresolved(rx) if expired => Err(Miss::Expired(rx)),
+            miss => Err(miss),
         }
-        Err(Self::add_listener(&mut data, target_ip))
     }
 
     /// Manually insert an IP -> MAC mapping into this Arp table and notify all
     /// listeners for that IP. Will return `true` if this insertion changed the
-    /// table.
+    /// table. Uses this table's default TTL.
     pub fn insert(&mut self, ip: Ipv4Addr, mac: MacAddr) -> bool {
+        let ttl = self.ttl;
+        self.insert_with_ttl(ip, mac, ttl)
+    }
+
+    /// Like `insert`, but lets the caller override how long the entry is
+    /// considered valid for.
+    pub fn insert_with_ttl(&mut self, ip: Ipv4Addr, mac: MacAddr, ttl: Duration) -> bool {
         let mut data = self.data.lock().expect("Unable to lock Arp::table for writing");
-        let old_mac = data.table.insert(ip, mac);
-        if let Some(listeners) = data.listeners.remove(&ip) {
-            for listener in listeners {
-                listener.send(mac).unwrap_or(());
-            }
-        }
-        old_mac.is_none() || old_mac != Some(mac)
+        let now = self.clock.now();
+        data.insert(ip, mac, now, ttl)
     }
 
-    fn add_listener(data: &mut TableData, ip: Ipv4Addr) -> Receiver<MacAddr> {
+    /// Drops every entry that has expired according to this table's clock.
+    /// Should be called periodically so a long lived stack does not keep
+    /// stale neighbors around forever. See also `sweep`, which does the
+    /// same and additionally prunes dead listener channels and rate
+    /// limiter bookkeeping.
+    pub fn flush_expired(&mut self) {
+        self.sweep();
+    }
+
+    /// Actively drops every entry that has expired, every pending
+    /// listener that has waited longer than this table's TTL without its
+    /// target ever resolving, and every `last_request` entry older than
+    /// the rate limiting window, since it can no longer throttle anything.
+    /// Should be called periodically so a long lived stack does not leak
+    /// memory on neighbors that never answer. Returns `true` if a table
+    /// entry was removed, so the caller can bump its `VersionedTx`
+    /// revision: any `Tx` created while that entry was still valid may
+    /// have baked in a MAC that is no longer correct.
+    pub fn sweep(&mut self) -> bool {
+        let mut data = self.data.lock().expect("Unable to lock Arp::table for writing");
+        let now = self.clock.now();
+        let ttl = self.ttl;
+        let min_interval = Duration::new(MIN_REQUEST_INTERVAL_SECS, 0);
+
+        let entries_before = data.table.len();
+        data.table.retain(|_, entry| entry.expires_at > now);
+        let removed_entries = data.table.len() != entries_before;
+
+        data.listeners.retain(|_, senders| {
+            senders.retain(|&(registered_at, _)| registered_at + ttl > now);
+            !senders.is_empty()
+        });
+        data.last_request.retain(|_, last| *last + min_interval > now);
+
+        removed_entries
+    }
+
+    fn add_listener(data: &mut TableData, ip: Ipv4Addr, now: Instant) -> Miss {
+        let pending = data.listeners.contains_key(&ip);
         let (tx, rx) = mpsc::channel();
-        data.listeners.entry(ip).or_insert_with(Vec::new).push(tx);
-        rx
+        data.listeners.entry(ip).or_insert_with(Vec::new).push((now, tx));
+        if pending {
+            return Miss::Pending(rx);
+        }
+        let min_interval = Duration::new(MIN_REQUEST_INTERVAL_SECS, 0);
+        let rate_limited = data.last_request
+            .get(&ip)
+            .map_or(false, |&last| last + min_interval > now);
+        if rate_limited {
+            Miss::Pending(rx)
+        } else {
+            data.last_request.insert(ip, now);
+            Miss::Unresolved(rx)
+        }
     }
 }
 
@@ -95,3 +372,229 @@ impl Default for ArpTable {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::util::MacAddr;
+    use std::cell::Cell;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    /// A `Clock` whose `now()` is driven manually by tests.
+    struct FakeClock {
+        now: Cell<Instant>,
+    }
+
+    impl FakeClock {
+        fn new() -> Arc<FakeClock> {
+            Arc::new(FakeClock { now: Cell::new(Instant::now()) })
+        }
+
+        fn advance(&self, by: Duration) {
+            self.now.set(self.now.get() + by);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.now.get()
+        }
+    }
+
+    fn ip(n: u8) -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, n)
+    }
+
+    fn mac(n: u8) -> MacAddr {
+        MacAddr::new(0, 0, 0, 0, 0, n)
+    }
+
+    #[test]
+    fn entry_expires() {
+        let clock = FakeClock::new();
+        let mut table = ArpTable::with_clock(clock.clone(), Duration::new(60, 0));
+
+        table.insert(ip(1), mac(1));
+        assert_eq!(mac(1), table.get(ip(1)).unwrap());
+
+        clock.advance(Duration::new(61, 0));
+        assert!(table.get(ip(1)).is_err());
+    }
+
+    #[test]
+    fn pending_miss_is_distinguished_from_first_miss() {
+        let clock = FakeClock::new();
+        let mut table = ArpTable::with_clock(clock, Duration::new(60, 0));
+
+        match table.get(ip(2)) {
+            Err(Miss::Unresolved(_)) => (),
+            _ => panic!("First miss for an IP should be Unresolved"),
+        }
+        match table.get(ip(2)) {
+            Err(Miss::Pending(_)) => (),
+            _ => panic!("Second miss for the same IP should be Pending"),
+        }
+    }
+
+    #[test]
+    fn expired_entry_is_distinguished_from_first_miss() {
+        let clock = FakeClock::new();
+        let mut table = ArpTable::with_clock(clock.clone(), Duration::new(60, 0));
+
+        table.insert(ip(1), mac(1));
+        clock.advance(Duration::new(61, 0));
+
+        match table.get(ip(1)) {
+            Err(Miss::Expired(_)) => (),
+            _ => panic!("Miss on a just-expired entry should be Expired"),
+        }
+    }
+
+    #[test]
+    fn repeated_misses_are_rate_limited_after_their_listener_is_swept() {
+        let clock = FakeClock::new();
+        // A short TTL so the pending listener below is eligible for
+        // sweeping well before the one second rate limit window closes,
+        // isolating the `last_request` throttle from listener bookkeeping.
+        let mut table = ArpTable::with_clock(clock.clone(), Duration::new(0, 500_000_000));
+
+        let rx = match table.get(ip(3)) {
+            Err(Miss::Unresolved(rx)) => rx,
+            _ => panic!("First miss for an IP should be Unresolved"),
+        };
+        drop(rx);
+
+        clock.advance(Duration::new(0, 600_000_000));
+        table.sweep();
+
+        // The dead listener was just swept, but we are still inside the
+        // one second rate limit window since the last request was sent,
+        // so this must not trigger a second outgoing Arp request.
+        match table.get(ip(3)) {
+            Err(Miss::Pending(_)) => (),
+            _ => panic!("A miss within the rate limit window should be Pending"),
+        }
+
+        // Sweep away the listener the rate-limited miss above queued, then
+        // move past the rate limit window entirely.
+        clock.advance(Duration::new(1, 0));
+        table.sweep();
+        match table.get(ip(3)) {
+            Err(Miss::Unresolved(_)) => (),
+            _ => panic!("A miss outside the rate limit window should be Unresolved"),
+        }
+    }
+
+    #[test]
+    fn sweep_drops_listeners_that_outlive_the_table_ttl() {
+        let clock = FakeClock::new();
+        let mut table = ArpTable::with_clock(clock.clone(), Duration::new(10, 0));
+
+        let rx = match table.get(ip(4)) {
+            Err(Miss::Unresolved(rx)) => rx,
+            _ => panic!("First miss for an IP should be Unresolved"),
+        };
+
+        clock.advance(Duration::new(11, 0));
+        assert!(!table.sweep());
+
+        // The listener registered above is now older than the table's TTL,
+        // so it must have been dropped, disconnecting `rx`.
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn sweep_reports_whether_a_table_entry_was_removed() {
+        let clock = FakeClock::new();
+        let mut table = ArpTable::with_clock(clock.clone(), Duration::new(10, 0));
+
+        table.insert(ip(5), mac(5));
+        assert!(!table.sweep());
+
+        clock.advance(Duration::new(11, 0));
+        assert!(table.sweep());
+        assert!(table.get(ip(5)).is_err());
+    }
+
+    #[test]
+    fn flush_expired_removes_stale_entries_only() {
+        let clock = FakeClock::new();
+        let mut table = ArpTable::with_clock(clock.clone(), Duration::new(10, 0));
+
+        table.insert(ip(1), mac(1));
+        clock.advance(Duration::new(5, 0));
+        table.insert_with_ttl(ip(2), mac(2), Duration::new(100, 0));
+        clock.advance(Duration::new(6, 0));
+
+        table.flush_expired();
+
+        assert!(table.get(ip(1)).is_err());
+        assert_eq!(mac(2), table.get(ip(2)).unwrap());
+    }
+
+    #[test]
+    fn insert_evicts_the_soonest_to_expire_entry_once_at_capacity() {
+        let clock = FakeClock::new();
+        // Long enough that no entry expires while the table is filled at one
+        // insert per second.
+        let mut table = ArpTable::with_clock(clock.clone(), Duration::new(3600, 0));
+
+        for i in 0..MAX_TABLE_ENTRIES {
+            clock.advance(Duration::new(1, 0));
+            table.insert(Ipv4Addr::from(i as u32), mac(1));
+        }
+        assert_eq!(mac(1), table.get(Ipv4Addr::from(0u32)).unwrap());
+
+        // The table is now full; inserting one more entry must evict the
+        // oldest (first inserted, so first to expire) one rather than
+        // growing past MAX_TABLE_ENTRIES.
+        clock.advance(Duration::new(1, 0));
+        table.insert(Ipv4Addr::from(MAX_TABLE_ENTRIES as u32), mac(2));
+
+        assert!(table.get(Ipv4Addr::from(0u32)).is_err());
+        assert_eq!(mac(2), table.get(Ipv4Addr::from(MAX_TABLE_ENTRIES as u32)).unwrap());
+    }
+
+    #[test]
+    fn slice_cache_lookup_and_fill() {
+        let mut storage = [(Ipv4Addr::new(0, 0, 0, 0), MacAddr::new(0, 0, 0, 0, 0, 0), 0); 2];
+        let mut cache = SliceCache::new(&mut storage);
+
+        assert_eq!(None, cache.lookup(&ip(1)));
+        cache.fill(&ip(1), &mac(1));
+        assert_eq!(Some(mac(1)), cache.lookup(&ip(1)));
+
+        cache.fill(&ip(2), &mac(2));
+        assert_eq!(Some(mac(1)), cache.lookup(&ip(1)));
+        assert_eq!(Some(mac(2)), cache.lookup(&ip(2)));
+    }
+
+    #[test]
+    fn slice_cache_overwrites_existing_entry() {
+        let mut storage = [(Ipv4Addr::new(0, 0, 0, 0), MacAddr::new(0, 0, 0, 0, 0, 0), 0); 2];
+        let mut cache = SliceCache::new(&mut storage);
+
+        cache.fill(&ip(1), &mac(1));
+        cache.fill(&ip(1), &mac(2));
+
+        assert_eq!(Some(mac(2)), cache.lookup(&ip(1)));
+    }
+
+    #[test]
+    fn slice_cache_evicts_least_recently_used_when_full() {
+        let mut storage = [(Ipv4Addr::new(0, 0, 0, 0), MacAddr::new(0, 0, 0, 0, 0, 0), 0); 2];
+        let mut cache = SliceCache::new(&mut storage);
+
+        cache.fill(&ip(1), &mac(1));
+        cache.fill(&ip(2), &mac(2));
+        // Touch ip(1) so ip(2) becomes the least recently used entry.
+        cache.lookup(&ip(1));
+        cache.fill(&ip(3), &mac(3));
+
+        assert_eq!(None, cache.lookup(&ip(2)));
+        assert_eq!(Some(mac(1)), cache.lookup(&ip(1)));
+        assert_eq!(Some(mac(3)), cache.lookup(&ip(3)));
+    }
+}
@@ -0,0 +1,104 @@
+//! Checksum offload capabilities, letting the upper layers skip checksum
+//! work a NIC already does in hardware. Modeled on smoltcp's
+//! `ChecksumCapabilities`/`DeviceCapabilities`.
+
+/// Whether a checksum is computed in software on tx, verified in software
+/// on rx, both or neither. Used on a per-protocol basis so e.g. a NIC that
+/// only offloads `Icmp` checksums doesn't force software `Ipv4` checksumming
+/// off too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// Compute the checksum on tx and verify it on rx. The default, and the
+    /// only safe choice unless the underlying device is known to handle it.
+    Both,
+    /// Compute the checksum on tx, but trust it on rx without verifying.
+    Tx,
+    /// Verify the checksum on rx, but leave it for the device to fill in on
+    /// tx.
+    Rx,
+    /// Neither compute it on tx nor verify it on rx.
+    None,
+}
+
+impl Checksum {
+    /// Whether this protocol's checksum should be computed in software
+    /// before a packet is sent.
+    pub fn tx(&self) -> bool {
+        match *self {
+            Checksum::Both | Checksum::Tx => true,
+            Checksum::Rx | Checksum::None => false,
+        }
+    }
+
+    /// Whether this protocol's checksum should be verified in software
+    /// after a packet is received.
+    pub fn rx(&self) -> bool {
+        match *self {
+            Checksum::Both | Checksum::Rx => true,
+            Checksum::Tx | Checksum::None => false,
+        }
+    }
+}
+
+impl Default for Checksum {
+    /// Defaults to `Both`, matching the behavior of always checksumming in
+    /// software.
+    fn default() -> Self {
+        Checksum::Both
+    }
+}
+
+/// Per-protocol checksum offload capabilities for one network interface.
+/// Carried from the datalink layer (`EthernetTx`/`EthernetListener`) down
+/// through `Ipv4Tx`/`Ipv4Rx` into the Icmp, Udp, Tcp and Ipv4
+/// builders/parsers, so they can skip software checksum work the NIC
+/// already does.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChecksumCapabilities {
+    /// Checksum handling for the Ipv4 header checksum.
+    pub ipv4: Checksum,
+    /// Checksum handling for the Icmp checksum.
+    pub icmpv4: Checksum,
+    /// Checksum handling for the Udp checksum.
+    pub udp: Checksum,
+    /// Checksum handling for the Tcp checksum.
+    pub tcp: Checksum,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_computes_and_verifies() {
+        assert!(Checksum::Both.tx());
+        assert!(Checksum::Both.rx());
+    }
+
+    #[test]
+    fn tx_only_computes() {
+        assert!(Checksum::Tx.tx());
+        assert!(!Checksum::Tx.rx());
+    }
+
+    #[test]
+    fn rx_only_verifies() {
+        assert!(!Checksum::Rx.tx());
+        assert!(Checksum::Rx.rx());
+    }
+
+    #[test]
+    fn none_does_neither() {
+        assert!(!Checksum::None.tx());
+        assert!(!Checksum::None.rx());
+    }
+
+    #[test]
+    fn default_is_both() {
+        let caps = ChecksumCapabilities::default();
+        assert_eq!(Checksum::Both, caps.ipv4);
+        assert_eq!(Checksum::Both, caps.icmpv4);
+        assert_eq!(Checksum::Both, caps.udp);
+        assert_eq!(Checksum::Both, caps.tcp);
+    }
+}
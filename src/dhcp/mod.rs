@@ -0,0 +1,435 @@
+//! DHCPv4 (RFC 2131) client, driving the Discover/Offer/Request/Ack
+//! handshake over the same `UdpListener` callback machinery
+//! `udp::UdpSocketListener` uses, and programming a granted lease into the
+//! owning `NetworkStack`'s interface address and `RoutingTable`.
+//!
+//! Like `icmp::PingSocket`, `DhcpClient` needs `local_ip` to already be a
+//! configured address on `interface` to register its reply listener; this
+//! crate has no support yet for binding a socket before any address
+//! exists (see `udp::UdpSocket::bind`'s refusal of `0.0.0.0`), so this is
+//! for acquiring or renewing a lease on an interface that already carries
+//! a provisional address (e.g. a link-local fallback), not zero-config
+//! bootstrap from a completely blank interface.
+
+mod packet;
+
+pub use self::packet::{DhcpMessageType, DhcpPacket, DhcpPacketBuilder};
+
+use ethernet::EthernetTxImpl;
+use ipv4::Ipv4TxImpl;
+use tx::TxImpl;
+use udp::{UdpListener, UdpTx};
+use {Interface, RxResult};
+#[cfg(not(feature = "unit-tests"))]
+use NetworkStack;
+
+use ipnetwork::Ipv4Network;
+
+use pnet::packet::Packet;
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::udp::UdpPacket;
+use pnet::util::MacAddr;
+
+use rand;
+
+use std::cmp;
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex, mpsc};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+/// Well known port a Dhcp client listens on.
+pub const CLIENT_PORT: u16 = 68;
+/// Well known port a Dhcp server listens on.
+pub const SERVER_PORT: u16 = 67;
+
+/// How many times in all a Discover/Request is sent, the first transmission
+/// included, before giving up if no reply arrives. RFC 2131 leaves the exact backoff up to the
+/// implementation; this retries on a fixed timeout rather than the RFC's
+/// suggested exponential one, for simplicity.
+const MAX_RETRANSMITS: u32 = 4;
+
+/// How long to wait for a reply before retransmitting.
+fn retransmit_timeout() -> Duration {
+    Duration::from_secs(4)
+}
+
+/// How long `DhcpClient::run` waits before retrying a failed `acquire`
+/// (e.g. no server answered any of the `MAX_RETRANSMITS` Discovers), the
+/// first time it fails.
+fn acquire_retry_initial_interval() -> Duration {
+    Duration::from_secs(4)
+}
+
+/// The cap `acquire_retry_initial_interval` is doubled up to between
+/// consecutive failed `acquire` attempts, the same doubling-backoff shape
+/// `StackInterface::resolve` uses for unanswered Arp requests.
+fn acquire_retry_max_interval() -> Duration {
+    Duration::from_secs(300)
+}
+
+fn broadcast() -> Ipv4Addr {
+    Ipv4Addr::new(255, 255, 255, 255)
+}
+
+/// A lease granted by a Dhcp server: the address to configure, the router
+/// to install as the default route, and the DNS servers to surface to the
+/// caller, per the options carried on the Ack that granted it.
+#[derive(Debug, Clone)]
+pub struct DhcpLease {
+    pub address: Ipv4Addr,
+    pub subnet_mask: Ipv4Addr,
+    pub router: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub lease_time: Duration,
+    pub server: Ipv4Addr,
+}
+
+impl DhcpLease {
+    /// When a renewal `Request` should be sent to `server`, per RFC 2131's
+    /// default T1 of half the lease time.
+    pub fn renewal_time(&self) -> Duration {
+        self.lease_time / 2
+    }
+
+    /// When this client should fall back to broadcasting a renewal
+    /// `Request` to any server, per RFC 2131's default T2 of 7/8 of the
+    /// lease time.
+    pub fn rebinding_time(&self) -> Duration {
+        self.lease_time * 7 / 8
+    }
+}
+
+/// Forwards every Udp datagram delivered to the bound reply port onto an
+/// internal channel `DhcpClient` reads from, the same role
+/// `udp::UdpSocketListener` plays for `UdpSocket`.
+#[derive(Clone)]
+struct DhcpReplyListener {
+    chan: mpsc::Sender<(SystemTime, Box<[u8]>)>,
+}
+
+impl UdpListener for DhcpReplyListener {
+    fn recv(&mut self, time: SystemTime, packet: &Ipv4Packet) -> (RxResult, bool) {
+        let data = packet.packet().to_vec().into_boxed_slice();
+        let resume = self.chan.send((time, data)).is_ok();
+        (Ok(()), resume)
+    }
+}
+
+/// A configuration change `DhcpClient::poll` reports, for callers driving
+/// the client from their own event loop instead of handing it off to the
+/// dedicated thread `run` spawns.
+#[derive(Debug, Clone)]
+pub enum DhcpConfigEvent {
+    /// A new lease was acquired, or the held one renewed/rebound.
+    Leased(DhcpLease),
+    /// The held lease could be neither renewed nor rebound before its T2
+    /// deadline, and its address has been released from the interface.
+    Released,
+}
+
+/// Dhcp client for `interface`, listening on `local_ip:68` for replies.
+#[cfg(not(feature = "unit-tests"))]
+pub struct DhcpClient {
+    interface: Interface,
+    local_ip: Ipv4Addr,
+    mac: MacAddr,
+    stack: Arc<Mutex<NetworkStack>>,
+    replies: mpsc::Receiver<(SystemTime, Box<[u8]>)>,
+    lease: Option<DhcpLease>,
+    granted_at: Option<SystemTime>,
+}
+
+#[cfg(not(feature = "unit-tests"))]
+impl DhcpClient {
+    /// Registers a reply listener on `local_ip:68` of `interface`.
+    pub fn new(interface: Interface,
+               local_ip: Ipv4Addr,
+               stack: Arc<Mutex<NetworkStack>>)
+               -> io::Result<DhcpClient> {
+        let (chan, replies) = mpsc::channel();
+        let listener = DhcpReplyListener { chan: chan };
+        let addr = (local_ip, CLIENT_PORT);
+        stack.lock().expect("Unable to lock stack").udp_listen(addr, listener)?;
+        let mac = interface.mac;
+        Ok(DhcpClient {
+            interface: interface,
+            local_ip: local_ip,
+            mac: mac,
+            stack: stack,
+            replies: replies,
+            lease: None,
+            granted_at: None,
+        })
+    }
+
+    /// The address this client's reply listener is bound to.
+    pub fn local_addr(&self) -> Ipv4Addr {
+        self.local_ip
+    }
+
+    /// The lease this client currently holds, if any.
+    pub fn lease(&self) -> Option<&DhcpLease> {
+        self.lease.as_ref()
+    }
+
+    /// Runs a full Discover -> Offer -> Request -> Ack handshake, sending
+    /// the Discover up to `MAX_RETRANSMITS` times in all if no Offer
+    /// arrives. On success, programs the granted address onto
+    /// `interface` via `NetworkStack::add_ipv4` and, if the Ack carried a
+    /// router option, installs it as the default route (`0.0.0.0/0`).
+    pub fn acquire(&mut self) -> io::Result<DhcpLease> {
+        let xid = rand::random();
+        let offer = self.exchange(xid, &DhcpPacketBuilder::new(DhcpMessageType::Discover, xid, self.mac))?;
+        let request = DhcpPacketBuilder::new(DhcpMessageType::Request, xid, self.mac)
+            .requested_ip(offer.your_ip)
+            .server_id(offer.server_id.unwrap_or(offer.your_ip));
+        let ack = self.exchange(xid, &request)?;
+        if ack.message_type != DhcpMessageType::Ack {
+            let msg = "Dhcp server replied with Nak".to_owned();
+            return Err(io::Error::new(io::ErrorKind::Other, msg));
+        }
+        let lease = self.lease_from_ack(&ack)?;
+        self.apply_lease(&lease)?;
+        self.lease = Some(lease.clone());
+        self.granted_at = Some(SystemTime::now());
+        Ok(lease)
+    }
+
+    /// Sends a unicast Request renewing the current lease and applies the
+    /// refreshed lease time from the Ack. Meant to be called once
+    /// `DhcpLease::renewal_time` has elapsed since `acquire`/the previous
+    /// `renew`, the same externally-driven convention
+    /// `IgmpTable::due_reports` uses for its own timers.
+    pub fn renew(&mut self) -> io::Result<DhcpLease> {
+        let (address, server) = match self.lease {
+            Some(ref lease) => (lease.address, lease.server),
+            None => {
+                let msg = "No lease to renew".to_owned();
+                return Err(io::Error::new(io::ErrorKind::Other, msg));
+            }
+        };
+        let xid = rand::random();
+        let request = DhcpPacketBuilder::new(DhcpMessageType::Request, xid, self.mac).ciaddr(address);
+        let ack = self.exchange_with(xid, &request, server)?;
+        if ack.message_type != DhcpMessageType::Ack {
+            let msg = "Dhcp server replied with Nak".to_owned();
+            return Err(io::Error::new(io::ErrorKind::Other, msg));
+        }
+        let lease = self.lease_from_ack(&ack)?;
+        self.lease = Some(lease.clone());
+        self.granted_at = Some(SystemTime::now());
+        Ok(lease)
+    }
+
+    /// Broadcasts a Request renewing the current lease to any server
+    /// willing to answer, the fallback `run` takes once a unicast `renew`
+    /// to the original server has gone unanswered. Like `renew`, it carries
+    /// `ciaddr` and no `server_id`; unlike `renew`, it is broadcast rather
+    /// than sent to the server that granted the lease.
+    pub fn rebind(&mut self) -> io::Result<DhcpLease> {
+        let address = match self.lease {
+            Some(ref lease) => lease.address,
+            None => {
+                let msg = "No lease to rebind".to_owned();
+                return Err(io::Error::new(io::ErrorKind::Other, msg));
+            }
+        };
+        let xid = rand::random();
+        let request = DhcpPacketBuilder::new(DhcpMessageType::Request, xid, self.mac).ciaddr(address);
+        let ack = self.exchange(xid, &request)?;
+        if ack.message_type != DhcpMessageType::Ack {
+            let msg = "Dhcp server replied with Nak".to_owned();
+            return Err(io::Error::new(io::ErrorKind::Other, msg));
+        }
+        let lease = self.lease_from_ack(&ack)?;
+        self.lease = Some(lease.clone());
+        self.granted_at = Some(SystemTime::now());
+        Ok(lease)
+    }
+
+    /// Drops the currently held lease's address from `interface`, e.g.
+    /// because `run` could neither renew nor rebind it before it expired.
+    fn release(&mut self) {
+        if let Some(lease) = self.lease.take() {
+            let mut stack = self.stack.lock().expect("Unable to lock stack");
+            if let Ok(stack_interface) = stack.interface(&self.interface) {
+                stack_interface.remove_ipv4(lease.address).unwrap_or(());
+            }
+        }
+        self.granted_at = None;
+    }
+
+    /// Non-blocking, poll-style alternative to `run`, for callers that
+    /// already drive their own event loop instead of handing `self` off to
+    /// a dedicated thread. Acquires a lease if none is held yet; otherwise
+    /// checks whether `DhcpLease::renewal_time`/`rebinding_time` has
+    /// elapsed since it was granted and, if so, performs the corresponding
+    /// `renew`/`rebind`/`release` step. Returns `None` when there is
+    /// nothing to report yet.
+    pub fn poll(&mut self) -> io::Result<Option<DhcpConfigEvent>> {
+        if self.lease.is_none() {
+            let lease = self.acquire()?;
+            return Ok(Some(DhcpConfigEvent::Leased(lease)));
+        }
+        let lease = self.lease.clone().unwrap();
+        let granted_at = self.granted_at.unwrap_or_else(SystemTime::now);
+        let elapsed = SystemTime::now().duration_since(granted_at).unwrap_or(Duration::new(0, 0));
+        if elapsed < lease.renewal_time() {
+            return Ok(None);
+        }
+        if let Ok(lease) = self.renew() {
+            return Ok(Some(DhcpConfigEvent::Leased(lease)));
+        }
+        if elapsed < lease.rebinding_time() {
+            return Ok(None);
+        }
+        if let Ok(lease) = self.rebind() {
+            return Ok(Some(DhcpConfigEvent::Leased(lease)));
+        }
+        self.release();
+        Ok(Some(DhcpConfigEvent::Released))
+    }
+
+    /// Runs the full lifetime of a lease on a background thread: acquires
+    /// one if `self` does not already hold one, then sleeps until
+    /// `DhcpLease::renewal_time` and sends a unicast `renew`; if that goes
+    /// unanswered (or the server replies with a Nak), sleeps until
+    /// `DhcpLease::rebinding_time` and broadcasts a `rebind` instead; if
+    /// that also fails, `release`s the address and falls back to a fresh
+    /// `acquire` (another Discover) rather than giving up. A failed
+    /// `acquire` never kills the thread either: it is retried after
+    /// `acquire_retry_initial_interval`, doubling up to
+    /// `acquire_retry_max_interval` on each consecutive failure, so a
+    /// server that is merely down or unreachable for a while is retried
+    /// forever instead of silently leaving the interface unconfigured.
+    /// Mirrors `StackInterfaceThread::spawn` in shape, but is driven by
+    /// lease timers rather than an incoming message queue.
+    pub fn run(mut self) -> io::Result<JoinHandle<()>> {
+        if self.lease.is_none() {
+            self.acquire()?;
+        }
+        Ok(thread::spawn(move || {
+            let mut retry_interval = acquire_retry_initial_interval();
+            loop {
+                let lease = match self.lease.clone() {
+                    Some(lease) => lease,
+                    None => {
+                        if self.acquire().is_ok() {
+                            retry_interval = acquire_retry_initial_interval();
+                        } else {
+                            thread::sleep(retry_interval);
+                            retry_interval = cmp::min(retry_interval * 2, acquire_retry_max_interval());
+                        }
+                        continue;
+                    }
+                };
+                thread::sleep(lease.renewal_time());
+                if self.renew().is_ok() {
+                    continue;
+                }
+                let rebind_wait = lease.rebinding_time()
+                    .checked_sub(lease.renewal_time())
+                    .unwrap_or(Duration::new(0, 0));
+                thread::sleep(rebind_wait);
+                if self.rebind().is_ok() {
+                    continue;
+                }
+                self.release();
+            }
+        }))
+    }
+
+    fn lease_from_ack(&self, ack: &DhcpPacket) -> io::Result<DhcpLease> {
+        let server = match ack.server_id {
+            Some(server) => server,
+            None => {
+                let msg = "Ack carried no server identifier".to_owned();
+                return Err(io::Error::new(io::ErrorKind::Other, msg));
+            }
+        };
+        Ok(DhcpLease {
+            address: ack.your_ip,
+            subnet_mask: ack.subnet_mask.unwrap_or(Ipv4Addr::new(255, 255, 255, 0)),
+            router: ack.router,
+            dns_servers: ack.dns_servers.clone(),
+            lease_time: Duration::from_secs(ack.lease_time_secs.unwrap_or(3600) as u64),
+            server: server,
+        })
+    }
+
+    /// Adds `lease.address` to `interface` and, if a router was offered,
+    /// installs it as the default route.
+    fn apply_lease(&self, lease: &DhcpLease) -> io::Result<()> {
+        let prefix = u32::from(lease.subnet_mask).count_ones() as u8;
+        let net = Ipv4Network::new(lease.address, prefix).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
+        })?;
+        let mut stack = self.stack.lock().expect("Unable to lock stack");
+        stack.add_ipv4(&self.interface, net)?;
+        stack.interface(&self.interface)?.invalidate_tx();
+        if let Some(router) = lease.router {
+            let default_net = Ipv4Network::from_cidr("0.0.0.0/0").unwrap();
+            stack.routing_table().add_route(default_net, Some(router), self.interface.clone());
+        }
+        Ok(())
+    }
+
+    /// Broadcasts `request` and waits for a matching reply, retransmitting
+    /// on timeout.
+    fn exchange(&mut self, xid: u32, request: &DhcpPacketBuilder) -> io::Result<DhcpPacket> {
+        self.exchange_with(xid, request, broadcast())
+    }
+
+    /// Sends `request` to `dst` and waits for a matching reply,
+    /// retransmitting on timeout, up to `MAX_RETRANSMITS` sends in all.
+    fn exchange_with(&mut self,
+                      xid: u32,
+                      request: &DhcpPacketBuilder,
+                      dst: Ipv4Addr)
+                      -> io::Result<DhcpPacket> {
+        let payload = request.build();
+        for _ in 0..MAX_RETRANSMITS {
+            self.send_to(&payload, dst)?;
+            let deadline = SystemTime::now() + retransmit_timeout();
+            loop {
+                let timeout = deadline.duration_since(SystemTime::now()).unwrap_or(Duration::new(0, 0));
+                match self.replies.recv_timeout(timeout) {
+                    Ok((_time, data)) => {
+                        if let Some(reply) = parse_udp_payload(&data).and_then(DhcpPacket::parse) {
+                            if reply.xid == xid {
+                                return Ok(reply);
+                            }
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        let msg = "Stack is gone".to_owned();
+                        return Err(io::Error::new(io::ErrorKind::Other, msg));
+                    }
+                }
+            }
+        }
+        let msg = "No reply from any Dhcp server".to_owned();
+        Err(io::Error::new(io::ErrorKind::TimedOut, msg))
+    }
+
+    fn send_to(&self, payload: &[u8], dst: Ipv4Addr) -> io::Result<()> {
+        let ipv4_tx = {
+            let mut stack = self.stack.lock().expect("Unable to lock stack");
+            stack.interface(&self.interface)?.ipv4_tx(dst, None)?
+        };
+        let mut udp_tx: UdpTx<Ipv4TxImpl<EthernetTxImpl<TxImpl>>> = UdpTx::new(ipv4_tx, CLIENT_PORT, SERVER_PORT);
+        Ok(udp_tx.send(payload)?)
+    }
+}
+
+/// Pulls the Udp payload out of a raw Ipv4 datagram, the same way
+/// `udp::UdpSocketReader::recv_from` does.
+fn parse_udp_payload(data: &[u8]) -> Option<Vec<u8>> {
+    let ip_pkg = Ipv4Packet::new(data)?;
+    let udp_pkg = UdpPacket::new(ip_pkg.payload())?;
+    Some(udp_pkg.payload().to_vec())
+}
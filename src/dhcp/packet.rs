@@ -0,0 +1,363 @@
+//! Wire format for DHCPv4 (RFC 2131) messages. Hand-rolled since `pnet` has
+//! no dedicated Dhcp packet type, the same reason `igmp::igmp_tx` computes
+//! its own checksum instead of going through `pnet`.
+
+use pnet::util::MacAddr;
+
+use std::net::Ipv4Addr;
+
+const OP_BOOTREQUEST: u8 = 1;
+const OP_BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const HLEN_ETHERNET: u8 = 6;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// Broadcast flag, set in every message this client sends since it has no
+/// address of its own to receive a unicast reply on yet.
+const FLAG_BROADCAST: u16 = 0x8000;
+
+/// Length of the fixed portion of a message (op through the magic cookie),
+/// before the variable-length options.
+const FIXED_LEN: usize = 236 + MAGIC_COOKIE.len();
+
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS_SERVERS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETER_REQUEST_LIST: u8 = 55;
+const OPT_END: u8 = 255;
+
+/// The Dhcp message types this client sends (`Discover`/`Request`) or
+/// understands on a reply (`Offer`/`Ack`/`Nak`), carried in option 53.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpMessageType {
+    Discover,
+    Offer,
+    Request,
+    Decline,
+    Ack,
+    Nak,
+    Release,
+    Inform,
+}
+
+impl DhcpMessageType {
+    fn value(&self) -> u8 {
+        match *self {
+            DhcpMessageType::Discover => 1,
+            DhcpMessageType::Offer => 2,
+            DhcpMessageType::Request => 3,
+            DhcpMessageType::Decline => 4,
+            DhcpMessageType::Ack => 5,
+            DhcpMessageType::Nak => 6,
+            DhcpMessageType::Release => 7,
+            DhcpMessageType::Inform => 8,
+        }
+    }
+
+    fn from_value(value: u8) -> Option<DhcpMessageType> {
+        match value {
+            1 => Some(DhcpMessageType::Discover),
+            2 => Some(DhcpMessageType::Offer),
+            3 => Some(DhcpMessageType::Request),
+            4 => Some(DhcpMessageType::Decline),
+            5 => Some(DhcpMessageType::Ack),
+            6 => Some(DhcpMessageType::Nak),
+            7 => Some(DhcpMessageType::Release),
+            8 => Some(DhcpMessageType::Inform),
+            _ => None,
+        }
+    }
+}
+
+/// A Dhcp message parsed out of a server reply (Offer/Ack/Nak), with every
+/// option `DhcpClient` understands already decoded.
+#[derive(Debug, Clone)]
+pub struct DhcpPacket {
+    pub message_type: DhcpMessageType,
+    pub xid: u32,
+    pub your_ip: Ipv4Addr,
+    pub server_id: Option<Ipv4Addr>,
+    pub subnet_mask: Option<Ipv4Addr>,
+    pub router: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub lease_time_secs: Option<u32>,
+}
+
+impl DhcpPacket {
+    /// Parses `buf`, the Udp payload of a reply from a Dhcp server.
+    /// Returns `None` if it is too short, carries the wrong magic cookie,
+    /// isn't a `BOOTREPLY`, or has no (or an unrecognized) message type
+    /// option -- the same "garbage in, garbage dropped" treatment
+    /// `raw::RawTx::send` gives a malformed datagram.
+    pub fn parse(buf: &[u8]) -> Option<DhcpPacket> {
+        if buf.len() < FIXED_LEN || buf[0] != OP_BOOTREPLY || buf[236..240] != MAGIC_COOKIE[..] {
+            return None;
+        }
+        let xid = read_u32(&buf[4..8]);
+        let your_ip = read_ipv4(&buf[16..20]);
+
+        let mut message_type = None;
+        let mut server_id = None;
+        let mut subnet_mask = None;
+        let mut router = None;
+        let mut dns_servers = Vec::new();
+        let mut lease_time_secs = None;
+
+        for (code, data) in options(&buf[FIXED_LEN..]) {
+            match code {
+                OPT_MESSAGE_TYPE if data.len() == 1 => {
+                    message_type = DhcpMessageType::from_value(data[0]);
+                }
+                OPT_SERVER_ID if data.len() == 4 => server_id = Some(read_ipv4(data)),
+                OPT_SUBNET_MASK if data.len() == 4 => subnet_mask = Some(read_ipv4(data)),
+                OPT_ROUTER if data.len() >= 4 => router = Some(read_ipv4(&data[..4])),
+                OPT_DNS_SERVERS => {
+                    dns_servers = data.chunks(4).filter(|c| c.len() == 4).map(read_ipv4).collect();
+                }
+                OPT_LEASE_TIME if data.len() == 4 => lease_time_secs = Some(read_u32(data)),
+                _ => (),
+            }
+        }
+
+        Some(DhcpPacket {
+            message_type: match message_type {
+                Some(message_type) => message_type,
+                None => return None,
+            },
+            xid: xid,
+            your_ip: your_ip,
+            server_id: server_id,
+            subnet_mask: subnet_mask,
+            router: router,
+            dns_servers: dns_servers,
+            lease_time_secs: lease_time_secs,
+        })
+    }
+}
+
+/// Builds a `BOOTREQUEST` (Discover/Request/Decline/Release) to send to a
+/// Dhcp server.
+pub struct DhcpPacketBuilder {
+    message_type: DhcpMessageType,
+    xid: u32,
+    client_mac: MacAddr,
+    ciaddr: Ipv4Addr,
+    requested_ip: Option<Ipv4Addr>,
+    server_id: Option<Ipv4Addr>,
+}
+
+impl DhcpPacketBuilder {
+    pub fn new(message_type: DhcpMessageType, xid: u32, client_mac: MacAddr) -> Self {
+        DhcpPacketBuilder {
+            message_type: message_type,
+            xid: xid,
+            client_mac: client_mac,
+            ciaddr: Ipv4Addr::new(0, 0, 0, 0),
+            requested_ip: None,
+            server_id: None,
+        }
+    }
+
+    /// Sets `ciaddr`, the client's current address. Only meaningful once a
+    /// lease has already been assigned (renewal requests send a unicast
+    /// Request with this set instead of the `requested_ip` option).
+    pub fn ciaddr(mut self, ciaddr: Ipv4Addr) -> Self {
+        self.ciaddr = ciaddr;
+        self
+    }
+
+    /// Sets option 50, the address being requested in a Request following
+    /// an Offer.
+    pub fn requested_ip(mut self, requested_ip: Ipv4Addr) -> Self {
+        self.requested_ip = Some(requested_ip);
+        self
+    }
+
+    /// Sets option 54, identifying which server's Offer is being accepted.
+    pub fn server_id(mut self, server_id: Ipv4Addr) -> Self {
+        self.server_id = Some(server_id);
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; FIXED_LEN];
+        buf[0] = OP_BOOTREQUEST;
+        buf[1] = HTYPE_ETHERNET;
+        buf[2] = HLEN_ETHERNET;
+        write_u32(&mut buf[4..8], self.xid);
+        write_u16(&mut buf[10..12], FLAG_BROADCAST);
+        buf[12..16].copy_from_slice(&self.ciaddr.octets());
+        buf[28..34].copy_from_slice(&mac_octets(self.client_mac));
+        buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut options = vec![OPT_MESSAGE_TYPE, 1, self.message_type.value()];
+        if let Some(ip) = self.requested_ip {
+            options.push(OPT_REQUESTED_IP);
+            options.push(4);
+            options.extend_from_slice(&ip.octets());
+        }
+        if let Some(ip) = self.server_id {
+            options.push(OPT_SERVER_ID);
+            options.push(4);
+            options.extend_from_slice(&ip.octets());
+        }
+        options.extend_from_slice(&[OPT_PARAMETER_REQUEST_LIST, 3, OPT_SUBNET_MASK, OPT_ROUTER,
+                                     OPT_DNS_SERVERS]);
+        options.push(OPT_END);
+
+        buf.extend_from_slice(&options);
+        buf
+    }
+}
+
+/// Iterates the code/data pairs of a Dhcp option list, stopping at the end
+/// option, a pad byte where a code was expected, or a truncated length.
+fn options(buf: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < buf.len() {
+        let code = buf[i];
+        if code == OPT_END || code == 0 {
+            break;
+        }
+        if i + 1 >= buf.len() {
+            break;
+        }
+        let len = buf[i + 1] as usize;
+        if i + 2 + len > buf.len() {
+            break;
+        }
+        result.push((code, &buf[i + 2..i + 2 + len]));
+        i += 2 + len;
+    }
+    result
+}
+
+fn mac_octets(mac: MacAddr) -> [u8; 6] {
+    [mac.0, mac.1, mac.2, mac.3, mac.4, mac.5]
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    ((buf[0] as u32) << 24) | ((buf[1] as u32) << 16) | ((buf[2] as u32) << 8) | (buf[3] as u32)
+}
+
+fn write_u32(buf: &mut [u8], value: u32) {
+    buf[0] = (value >> 24) as u8;
+    buf[1] = (value >> 16) as u8;
+    buf[2] = (value >> 8) as u8;
+    buf[3] = value as u8;
+}
+
+fn write_u16(buf: &mut [u8], value: u16) {
+    buf[0] = (value >> 8) as u8;
+    buf[1] = value as u8;
+}
+
+fn read_ipv4(buf: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(buf[0], buf[1], buf[2], buf[3])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Turns a `DhcpPacketBuilder`'s own output (a `BOOTREQUEST`) into the
+    /// `BOOTREPLY` shape `DhcpPacket::parse` understands, patching in the
+    /// fields only a server would set. Lets the round-trip tests below reuse
+    /// the builder instead of hand-writing a whole message byte by byte.
+    fn request_into_reply(mut buf: Vec<u8>, your_ip: Ipv4Addr) -> Vec<u8> {
+        buf[0] = OP_BOOTREPLY;
+        buf[16..20].copy_from_slice(&your_ip.octets());
+        buf
+    }
+
+    #[test]
+    fn build_then_parse_round_trips_discover_fields() {
+        let mac = MacAddr::new(1, 2, 3, 4, 5, 6);
+        let builder = DhcpPacketBuilder::new(DhcpMessageType::Discover, 0xdeadbeef, mac);
+        let buf = request_into_reply(builder.build(), Ipv4Addr::new(192, 168, 1, 42));
+
+        let pkg = DhcpPacket::parse(&buf).unwrap();
+        assert_eq!(DhcpMessageType::Discover, pkg.message_type);
+        assert_eq!(0xdeadbeef, pkg.xid);
+        assert_eq!(Ipv4Addr::new(192, 168, 1, 42), pkg.your_ip);
+        assert_eq!(None, pkg.server_id);
+    }
+
+    #[test]
+    fn build_then_parse_round_trips_request_options() {
+        let mac = MacAddr::new(1, 2, 3, 4, 5, 6);
+        let builder = DhcpPacketBuilder::new(DhcpMessageType::Request, 7, mac)
+            .requested_ip(Ipv4Addr::new(10, 0, 0, 5))
+            .server_id(Ipv4Addr::new(10, 0, 0, 1));
+        let buf = request_into_reply(builder.build(), Ipv4Addr::new(10, 0, 0, 5));
+
+        let pkg = DhcpPacket::parse(&buf).unwrap();
+        assert_eq!(DhcpMessageType::Request, pkg.message_type);
+        assert_eq!(Some(Ipv4Addr::new(10, 0, 0, 1)), pkg.server_id);
+    }
+
+    #[test]
+    fn parse_decodes_subnet_router_dns_and_lease_time_options() {
+        let mut buf = vec![0u8; FIXED_LEN];
+        buf[0] = OP_BOOTREPLY;
+        buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+        buf[16..20].copy_from_slice(&Ipv4Addr::new(192, 168, 1, 42).octets());
+
+        let mut options = vec![OPT_MESSAGE_TYPE, 1, DhcpMessageType::Offer.value()];
+        options.extend_from_slice(&[OPT_SUBNET_MASK, 4, 255, 255, 255, 0]);
+        options.extend_from_slice(&[OPT_ROUTER, 4, 192, 168, 1, 1]);
+        options.extend_from_slice(&[OPT_DNS_SERVERS, 8, 8, 8, 8, 8, 8, 8, 4, 4]);
+        options.extend_from_slice(&[OPT_LEASE_TIME, 4, 0, 0, 0x0e, 0x10]); // 3600s
+        options.push(OPT_END);
+        buf.extend_from_slice(&options);
+
+        let pkg = DhcpPacket::parse(&buf).unwrap();
+        assert_eq!(DhcpMessageType::Offer, pkg.message_type);
+        assert_eq!(Some(Ipv4Addr::new(255, 255, 255, 0)), pkg.subnet_mask);
+        assert_eq!(Some(Ipv4Addr::new(192, 168, 1, 1)), pkg.router);
+        assert_eq!(vec![Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(8, 8, 4, 4)], pkg.dns_servers);
+        assert_eq!(Some(3600), pkg.lease_time_secs);
+    }
+
+    #[test]
+    fn parse_rejects_buffer_shorter_than_the_fixed_header() {
+        let buf = vec![0u8; FIXED_LEN - 1];
+        assert!(DhcpPacket::parse(&buf).is_none());
+    }
+
+    #[test]
+    fn parse_rejects_wrong_magic_cookie() {
+        let mut buf = vec![0u8; FIXED_LEN];
+        buf[0] = OP_BOOTREPLY;
+        buf[236..240].copy_from_slice(&[1, 2, 3, 4]);
+        assert!(DhcpPacket::parse(&buf).is_none());
+    }
+
+    #[test]
+    fn parse_rejects_a_bootrequest_message() {
+        let mut buf = vec![0u8; FIXED_LEN];
+        buf[0] = OP_BOOTREQUEST;
+        buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+        assert!(DhcpPacket::parse(&buf).is_none());
+    }
+
+    #[test]
+    fn parse_rejects_a_message_with_no_message_type_option() {
+        let mut buf = vec![0u8; FIXED_LEN];
+        buf[0] = OP_BOOTREPLY;
+        buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+        buf.push(OPT_END);
+        assert!(DhcpPacket::parse(&buf).is_none());
+    }
+
+    #[test]
+    fn options_stops_at_a_truncated_length() {
+        let buf = [OPT_SUBNET_MASK, 4, 255, 255]; // claims 4 bytes, only 2 follow
+        assert!(options(&buf).is_empty());
+    }
+}
@@ -0,0 +1,40 @@
+use std::io;
+use std::time::SystemTime;
+
+/// A single receive operation, yielded by `EthernetDevice::receive`. Must be
+/// consumed exactly once to read the frame it carries.
+pub trait RxToken {
+    /// Consumes the token, handing `f` the timestamp the frame arrived at
+    /// (needed to age Arp cache entries correctly) together with a slice
+    /// borrowed straight from the device, without copying it into a fresh
+    /// `Vec` first.
+    fn consume<R, F>(self, f: F) -> io::Result<R> where F: FnOnce(SystemTime, &[u8]) -> io::Result<R>;
+}
+
+/// A single transmit operation, yielded by `EthernetDevice::transmit`. Must
+/// be consumed exactly once to fill in and send the frame it reserved room
+/// for.
+pub trait TxToken {
+    /// Consumes the token, handing `f` a buffer sized to fit the frame
+    /// requested from `transmit`.
+    fn consume<R, F>(self, f: F) -> io::Result<R> where F: FnOnce(&mut [u8]) -> io::Result<R>;
+}
+
+/// Zero-copy replacement for driving an Ethernet link through
+/// `pnet::datalink::EthernetDataLinkSender`/`EthernetDataLinkReceiver`'s
+/// closure-based `build_and_send`. Implementations hand back a token that
+/// borrows directly into a kernel-owned ring buffer, or a reused scratch
+/// buffer for `MockEthernetDevice`, instead of allocating a fresh `Vec` for
+/// every packet.
+pub trait EthernetDevice<'a> {
+    type RxToken: RxToken + 'a;
+    type TxToken: TxToken + 'a;
+
+    /// Returns a token for the next received frame, if one is available
+    /// without blocking.
+    fn receive(&'a mut self) -> Option<Self::RxToken>;
+
+    /// Returns a token for sending a single frame of `len` bytes, if the
+    /// device currently has room to accept one.
+    fn transmit(&'a mut self, len: usize) -> Option<Self::TxToken>;
+}
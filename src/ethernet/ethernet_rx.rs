@@ -3,8 +3,11 @@ use pnet::packet::Packet;
 use pnet::packet::ethernet::{EtherType, EthernetPacket};
 use ::rx::RxListener;
 
+use arc_swap::ArcSwap;
+
 use std::collections::HashMap;
-use std::collections::hash_map::Entry;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
 use std::time::SystemTime;
 
@@ -40,9 +43,14 @@ impl EthernetListener for BasicEthernetListener {
     fn recv(&mut self, time: SystemTime, packet: &EthernetPacket) -> RxResult {
         let data = packet.packet().to_vec();
         let owned_packet = EthernetPacket::owned(data).unwrap();
+        // Not `RxError::NoListener`: the remote end closing its receiving
+        // end is not "nobody wanted this particular packet", it means this
+        // listener itself is no longer any good, which `EthernetRx::recv`
+        // needs to be able to tell apart from a packet simply going
+        // unclaimed.
         self.tx
             .send((time, owned_packet))
-            .map_err(|_| RxError::NoListener("Remote end closed".to_owned()))
+            .map_err(|_| RxError::Other("Remote end closed".to_owned()))
     }
 
     fn ether_type(&self) -> EtherType {
@@ -50,59 +58,152 @@ impl EthernetListener for BasicEthernetListener {
     }
 }
 
+/// Type binding for how the listeners in `EthernetRx` are structured. Each
+/// listener is individually wrapped in its own `Mutex` so that `recv` can
+/// dispatch via a lock-free `ArcSwap::load` and only ever has to take a fine
+/// grained, almost never contended, per-listener lock. Several listeners may
+/// share the same `EtherType`, e.g. to bring a freshly created `Ipv4Rx`
+/// online for a new interface address without disturbing whatever else is
+/// already registered.
+pub type EthernetListenerLookup = HashMap<EtherType, Vec<Arc<Mutex<Box<EthernetListener>>>>>;
+
+/// Per-reason counts of frames `EthernetRx::recv` dropped silently rather
+/// than propagating an `Err` up to the receive loop: no listener registered
+/// for the frame's `EtherType` (or, once dispatched, for whatever a listener
+/// like `Ipv4Rx` parsed out of it), a bad checksum, or a too-short/malformed
+/// packet. Kept behind an `Arc` the same way `listeners` is, so a caller can
+/// keep its own clone to observe how much unsolicited or malformed traffic
+/// an interface is seeing, and why, after handing `EthernetRx` off to
+/// `rx::spawn`.
+#[derive(Debug, Default)]
+pub struct DropCounts {
+    pub no_listener: AtomicUsize,
+    pub invalid_checksum: AtomicUsize,
+    pub invalid_length: AtomicUsize,
+    pub invalid_content: AtomicUsize,
+}
+
+impl DropCounts {
+    fn record(&self, error: &RxError) {
+        let counter = match *error {
+            RxError::NoListener(_) => &self.no_listener,
+            RxError::InvalidChecksum => &self.invalid_checksum,
+            RxError::InvalidLength => &self.invalid_length,
+            RxError::InvalidContent => &self.invalid_content,
+            RxError::PoisonedLock | RxError::Other(_) => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 /// Receiver and parser of ethernet frames. Distributes them to
 /// `EthernetListener`s based on `EtherType` in the frame.
 /// This is the lowest level *Rx* type.
 pub struct EthernetRx {
-    listeners: HashMap<EtherType, Box<EthernetListener>>,
+    listeners: Arc<ArcSwap<EthernetListenerLookup>>,
+    drop_counts: Arc<DropCounts>,
 }
 
 impl EthernetRx {
-    /// Constructs a new `EthernetRx` with the given listeners. Listeners can
-    /// only be given to the constructor, so they can't be changed later.
-    ///
-    /// # Panics
-    ///
-    /// Panics if `listeners` contain multiple listeners that listens to the
-    /// same ether type.
-    pub fn new(listeners: Vec<Box<EthernetListener>>) -> EthernetRx {
-        let map_listeners = Self::expand_listeners(listeners);
-        EthernetRx { listeners: map_listeners }
-    }
-
-    fn expand_listeners(listeners: Vec<Box<EthernetListener>>)
-                        -> HashMap<EtherType, Box<EthernetListener>> {
-        let mut map_listeners = HashMap::new();
-        for listener in listeners {
-            let ethertype = listener.ether_type();
-            match map_listeners.entry(ethertype) {
-                Entry::Occupied(..) => panic!("Multiple listeners for EtherType {}", ethertype),
-                Entry::Vacant(entry) => entry.insert(listener),
-            };
+    /// Constructs a new `EthernetRx` dispatching to `listeners`. The caller
+    /// keeps its own clone of the `Arc`, so listeners can be registered or
+    /// removed at runtime with `add_listener`/`remove_listener` without
+    /// tearing down and respawning the capture thread this `EthernetRx` is
+    /// handed to.
+    pub fn new(listeners: Arc<ArcSwap<EthernetListenerLookup>>) -> EthernetRx {
+        EthernetRx {
+            listeners: listeners,
+            drop_counts: Arc::new(DropCounts::default()),
         }
-        map_listeners
+    }
+
+    /// Returns the shared drop counters for this `EthernetRx`. Call this
+    /// before handing the instance to `rx::spawn` if the counts need to be
+    /// observed afterwards, the same way a caller keeps its own clone of
+    /// `listeners` to register listeners at runtime.
+    pub fn drop_counts(&self) -> Arc<DropCounts> {
+        self.drop_counts.clone()
+    }
+
+    /// Registers `listener` for its own `EthernetListener::ether_type`, on
+    /// top of whatever is already registered for that `EtherType`. Returns
+    /// the `Arc` wrapping it so it can later be handed to `remove_listener`.
+    pub fn add_listener(listeners: &Arc<ArcSwap<EthernetListenerLookup>>,
+                        listener: Box<EthernetListener>)
+                        -> Arc<Mutex<Box<EthernetListener>>> {
+        let ether_type = listener.ether_type();
+        let listener = Arc::new(Mutex::new(listener));
+        listeners.rcu(|current| {
+            let mut new_listeners = (**current).clone();
+            new_listeners.entry(ether_type).or_insert_with(Vec::new).push(listener.clone());
+            new_listeners
+        });
+        listener
+    }
+
+    /// Unregisters `listener`, previously returned by `add_listener`, from
+    /// `ether_type`. A no-op if it is not (or no longer) registered there.
+    pub fn remove_listener(listeners: &Arc<ArcSwap<EthernetListenerLookup>>,
+                           ether_type: EtherType,
+                           listener: &Arc<Mutex<Box<EthernetListener>>>) {
+        listeners.rcu(|current| {
+            let mut new_listeners = (**current).clone();
+            if let Some(list) = new_listeners.get_mut(&ether_type) {
+                list.retain(|candidate| !Arc::ptr_eq(candidate, listener));
+            }
+            new_listeners
+        });
     }
 }
 
 impl RxListener for EthernetRx {
+    /// Top level receive entry point driving `rx::spawn`'s background
+    /// thread. A packet nobody was listening for, or that a listener
+    /// rejected as malformed, is an expected outcome, not a failure of the
+    /// receive pipeline: such results are counted in `drop_counts` and
+    /// swallowed here rather than propagated. Only a listener's genuinely
+    /// fatal error (e.g. `BasicEthernetListener`'s channel having been
+    /// closed on the other end) is returned to the caller.
     fn recv(&mut self, time: SystemTime, packet: &EthernetPacket) -> RxResult {
         let ethertype = packet.get_ethertype();
-        match self.listeners.get_mut(&ethertype) {
-            Some(listener) => listener.recv(time, packet),
-            None => Err(RxError::NoListener(format!("Ethernet: No listener for {}", ethertype))),
+        let listeners = self.listeners.load();
+        match listeners.get(&ethertype) {
+            Some(list) if !list.is_empty() => {
+                let mut fatal = None;
+                for listener in list {
+                    if let Err(e) = listener.lock().unwrap().recv(time, packet) {
+                        if e.is_recoverable() {
+                            self.drop_counts.record(&e);
+                            warn!("Ethernet: listener for {} dropped a packet: {:?}", ethertype, e);
+                        } else {
+                            warn!("Ethernet: listener for {} failed: {:?}", ethertype, e);
+                            fatal = Some(e);
+                        }
+                    }
+                }
+                match fatal {
+                    Some(e) => Err(e),
+                    None => Ok(()),
+                }
+            }
+            _ => {
+                self.drop_counts.no_listener.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use RxError;
-
     use pnet::packet::Packet;
     use pnet::packet::ethernet::{EtherType, EtherTypes, EthernetPacket, MutableEthernetPacket};
 
     use rx::RxListener;
 
+    use arc_swap::ArcSwap;
+
+    use std::sync::Arc;
     use std::sync::mpsc::{self, Receiver};
     use std::time::SystemTime;
 
@@ -132,29 +233,22 @@ mod tests {
         assert!(testee.recv(SystemTime::now(), &create_arp_packet()).is_err());
     }
 
-
-    #[test]
-    #[should_panic]
-    fn ethernet_rx_multiple_listener_panic() {
-        let (listener1, _) = create_listener(EtherTypes::Arp);
-        let (listener2, _) = create_listener(EtherTypes::Arp);
-        let _testee = EthernetRx::new(vec![listener1, listener2]);
-    }
-
     #[test]
     fn ethernet_rx_recv_no_listener() {
-        let mut testee = EthernetRx::new(vec![]);
-        match testee.recv(SystemTime::now(), &create_arp_packet()) {
-            Err(RxError::NoListener(_)) => (),
-            _ => panic!("Expected NoListener error"),
-        }
+        let mut testee = EthernetRx::new(empty_listeners());
+        let drop_counts = testee.drop_counts();
+        assert!(testee.recv(SystemTime::now(), &create_arp_packet()).is_ok());
+        assert_eq!(1, drop_counts.no_listener.load(Ordering::Relaxed));
     }
 
     #[test]
     fn ethernet_rx_recv() {
+        let listeners = empty_listeners();
         let (listener1, rx1) = create_listener(EtherTypes::Arp);
         let (listener2, rx2) = create_listener(EtherTypes::Ipv4);
-        let mut testee = EthernetRx::new(vec![listener1, listener2]);
+        EthernetRx::add_listener(&listeners, listener1);
+        EthernetRx::add_listener(&listeners, listener2);
+        let mut testee = EthernetRx::new(listeners);
         let time = SystemTime::now();
         testee.recv(time, &create_arp_packet()).unwrap();
 
@@ -165,6 +259,52 @@ mod tests {
         assert_eq!([56], output_packet.payload());
     }
 
+    #[test]
+    fn ethernet_rx_dispatches_to_every_listener_for_the_ether_type() {
+        let listeners = empty_listeners();
+        let (listener1, rx1) = create_listener(EtherTypes::Arp);
+        let (listener2, rx2) = create_listener(EtherTypes::Arp);
+        EthernetRx::add_listener(&listeners, listener1);
+        EthernetRx::add_listener(&listeners, listener2);
+        let mut testee = EthernetRx::new(listeners);
+        testee.recv(SystemTime::now(), &create_arp_packet()).unwrap();
+
+        assert!(rx1.try_recv().is_ok());
+        assert!(rx2.try_recv().is_ok());
+    }
+
+    #[test]
+    fn ethernet_rx_remove_listener() {
+        let listeners = empty_listeners();
+        let (listener, rx) = create_listener(EtherTypes::Arp);
+        let handle = EthernetRx::add_listener(&listeners, listener);
+        EthernetRx::remove_listener(&listeners, EtherTypes::Arp, &handle);
+        let mut testee = EthernetRx::new(listeners);
+        let drop_counts = testee.drop_counts();
+
+        assert!(testee.recv(SystemTime::now(), &create_arp_packet()).is_ok());
+        assert_eq!(1, drop_counts.no_listener.load(Ordering::Relaxed));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn ethernet_rx_recv_propagates_a_fatal_listener_error_but_still_counts_nothing() {
+        // Dropping `rx` makes `listener`'s channel send fail, which is a
+        // genuinely fatal `RxError::Other`, not a recoverable one.
+        let listeners = empty_listeners();
+        let (listener, rx) = create_listener(EtherTypes::Arp);
+        EthernetRx::add_listener(&listeners, listener);
+        drop(rx);
+        let mut testee = EthernetRx::new(listeners);
+        let drop_counts = testee.drop_counts();
+
+        assert!(testee.recv(SystemTime::now(), &create_arp_packet()).is_err());
+        assert_eq!(0, drop_counts.no_listener.load(Ordering::Relaxed));
+    }
+
+    fn empty_listeners() -> Arc<ArcSwap<EthernetListenerLookup>> {
+        Arc::new(ArcSwap::new(Arc::new(HashMap::new())))
+    }
 
     fn create_listener
         (ether_type: EtherType)
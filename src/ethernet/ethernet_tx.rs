@@ -1,4 +1,5 @@
 use {Payload, HasPayload, BasicPayload, Tx, TxResult};
+use checksum::ChecksumCapabilities;
 
 use pnet::packet::MutablePacket;
 use pnet::packet::ethernet::{EtherType, EthernetPacket, MutableEthernetPacket};
@@ -107,6 +108,15 @@ mod basic_ethernet_payload_tests {
 pub trait EthernetTx {
     fn src(&self) -> MacAddr;
     fn dst(&self) -> MacAddr;
+
+    /// The checksum offload capabilities of the underlying device. Layers
+    /// built on top use this to skip software checksum work the device
+    /// already does in hardware. Defaults to computing every checksum in
+    /// software unless overridden.
+    fn checksums(&self) -> ChecksumCapabilities {
+        ChecksumCapabilities::default()
+    }
+
     fn send<P>(&mut self, packets: usize, size: usize, payload: P) -> TxResult
         where P: EthernetPayload;
 }
@@ -115,6 +125,7 @@ pub struct EthernetTxImpl<T: Tx> {
     src: MacAddr,
     dst: MacAddr,
     tx: T,
+    checksums: ChecksumCapabilities,
 }
 
 impl<T: Tx> EthernetTxImpl<T> {
@@ -123,8 +134,16 @@ impl<T: Tx> EthernetTxImpl<T> {
             src: src,
             dst: dst,
             tx: tx,
+            checksums: ChecksumCapabilities::default(),
         }
     }
+
+    /// Overrides the checksum offload capabilities reported by
+    /// `EthernetTx::checksums`. Used to tell the upper layers a NIC handles
+    /// some checksums in hardware.
+    pub fn set_checksums(&mut self, checksums: ChecksumCapabilities) {
+        self.checksums = checksums;
+    }
 }
 
 impl<T: Tx> EthernetTx for EthernetTxImpl<T> {
@@ -136,6 +155,10 @@ impl<T: Tx> EthernetTx for EthernetTxImpl<T> {
         self.dst
     }
 
+    fn checksums(&self) -> ChecksumCapabilities {
+        self.checksums
+    }
+
     /// Send ethernet packets to the network.
     ///
     /// For every packet, all `header_size+size` bytes will be sent, no
@@ -2,9 +2,54 @@
 //! an underlying
 //! network adapter.
 
+use std::io;
+use std::os::unix::io::RawFd;
+
+use pnet::datalink::{Config, EthernetDataLinkReceiver, EthernetDataLinkSender};
+use pnet::util::NetworkInterface;
+
+mod device;
 mod ethernet_rx;
 mod ethernet_tx;
+#[cfg(target_os = "linux")]
+pub mod tap;
+pub mod udp_tunnel;
 
-pub use self::ethernet_rx::{BasicEthernetListener, EthernetListener, EthernetRx};
+pub use self::device::{EthernetDevice, RxToken, TxToken};
+pub use self::ethernet_rx::{BasicEthernetListener, DropCounts, EthernetListener,
+                            EthernetListenerLookup, EthernetRx};
 pub use self::ethernet_tx::{BasicEthernetPayload, EthernetBuilder, EthernetPayload, EthernetTx,
                             EthernetTxImpl};
+
+/// A single classic BPF instruction, laid out exactly like the kernel's
+/// `struct sock_filter` so a program assembled from these can be attached to
+/// a raw socket with `SO_ATTACH_FILTER` before any packets start flowing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BpfInstruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+/// Something able to hand out a sending/receiving pair for an Ethernet link.
+/// `PnetEthernetProvider` backs this with a real network adapter, while
+/// `MockPnet` and `udp_tunnel::UdpTunnelProvider` back it with something
+/// else entirely for testing or overlay purposes.
+pub trait EthernetProvider {
+    /// Opens a channel on `iface`. `socket_fd`, if given, is a raw socket the
+    /// caller already created and the provider should use instead of opening
+    /// its own, and `filter`, if given, is a classic BPF program to attach to
+    /// that socket before any packets are read. Doing the filtering in the
+    /// kernel this way means frames `EthernetRx` never cares about (e.g.
+    /// anything but IPv4 to this host) never reach its dispatch loop at all,
+    /// instead of being read and thrown away one by one.
+    fn channel(&mut self,
+               iface: &NetworkInterface,
+               config: &Config,
+               socket_fd: Option<RawFd>,
+               filter: Option<&[BpfInstruction]>)
+               -> io::Result<(Box<EthernetDataLinkSender>, Box<EthernetDataLinkReceiver>)>;
+
+    fn get_network_interfaces(&self) -> Vec<NetworkInterface>;
+}
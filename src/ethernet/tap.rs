@@ -0,0 +1,260 @@
+//! A TAP backend for `EthernetDataLinkSender`/`EthernetDataLinkReceiver`,
+//! opened straight on `/dev/net/tun`, so the stack can run entirely
+//! unprivileged against a virtual interface instead of `pnet::datalink`'s
+//! raw, root-requiring sockets. Handy for CI, fuzzing, and connecting two
+//! stacks back to back without any real hardware. Makes concrete the
+//! pluggable-`Datalink` design sketched (and commented out) at the bottom
+//! of `lib.rs`.
+//!
+//! Every `read`/`write` on the device file is exactly one Ethernet frame,
+//! since the interface is brought up with `IFF_NO_PI` so no additional
+//! framing header is prepended by the kernel. `open` also administratively
+//! brings the new device up (`IFF_UP`), so two stacks can be connected
+//! back to back purely from Rust, without a separate `ip link set up`.
+
+use pnet::datalink::{EthernetDataLinkChannelIterator, EthernetDataLinkReceiver,
+                     EthernetDataLinkSender};
+use pnet::packet::ethernet::{EthernetPacket, MutableEthernetPacket};
+use pnet::util::MacAddr;
+
+use libc;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+use {EthernetChannel, DEFAULT_BUFFER_SIZE};
+
+const IFNAMSIZ: usize = 16;
+/// Size of the anonymous union inside the kernel's `struct ifreq`. Large
+/// enough to hold every member the real struct defines (`ifr_flags`,
+/// `ifr_hwaddr`, `ifr_map`, ...); this backend only ever reads or writes
+/// the first few bytes of it.
+const IFREQ_UNION_LEN: usize = 24;
+
+/// `TUNSETIFF`, as defined by `<linux/if_tun.h>` for x86/x86_64.
+const TUNSETIFF: libc::c_ulong = 0x400454ca;
+/// `SIOCGIFHWADDR`, as defined by `<linux/sockios.h>`.
+const SIOCGIFHWADDR: libc::c_ulong = 0x8927;
+/// `SIOCGIFFLAGS`, as defined by `<linux/sockios.h>`.
+const SIOCGIFFLAGS: libc::c_ulong = 0x8913;
+/// `SIOCSIFFLAGS`, as defined by `<linux/sockios.h>`.
+const SIOCSIFFLAGS: libc::c_ulong = 0x8914;
+/// `SIOCGIFMTU`, as defined by `<linux/sockios.h>`.
+const SIOCGIFMTU: libc::c_ulong = 0x8921;
+const IFF_TAP: i16 = 0x0002;
+const IFF_NO_PI: i16 = 0x1000;
+/// `IFF_UP`/`IFF_RUNNING`, as defined by `<linux/if.h>`. A freshly created
+/// TAP device comes up administratively down, so no frame written to it
+/// would actually reach the other end (e.g. a second `rips` stack) until
+/// something brings it up; `open` does so itself rather than requiring the
+/// caller to shell out to `ip link set <ifname> up` first.
+const IFF_UP: i16 = 0x1;
+const IFF_RUNNING: i16 = 0x40;
+
+/// Mirrors the kernel's `struct ifreq`, laid out exactly like it, the same
+/// way `BpfInstruction` mirrors `struct sock_filter`, so it can be handed
+/// straight to `ioctl`.
+#[repr(C)]
+struct IfReq {
+    ifr_name: [u8; IFNAMSIZ],
+    ifr_union: [u8; IFREQ_UNION_LEN],
+}
+
+impl IfReq {
+    fn new(name: &str) -> IfReq {
+        let mut ifr_name = [0u8; IFNAMSIZ];
+        let bytes = name.as_bytes();
+        let len = ::std::cmp::min(bytes.len(), IFNAMSIZ - 1);
+        ifr_name[..len].copy_from_slice(&bytes[..len]);
+        IfReq {
+            ifr_name: ifr_name,
+            ifr_union: [0u8; IFREQ_UNION_LEN],
+        }
+    }
+
+    fn set_flags(&mut self, flags: i16) {
+        self.ifr_union[0] = (flags & 0xff) as u8;
+        self.ifr_union[1] = ((flags >> 8) & 0xff) as u8;
+    }
+
+    /// Reads back `ifr_flags` written by `SIOCGIFFLAGS`, at the same offset
+    /// `set_flags` writes to.
+    fn flags(&self) -> i16 {
+        (self.ifr_union[0] as i16) | ((self.ifr_union[1] as i16) << 8)
+    }
+
+    /// Reads back `ifr_mtu` written by `SIOCGIFMTU`, a plain `c_int` at the
+    /// front of the union.
+    fn mtu(&self) -> usize {
+        let d = &self.ifr_union[..4];
+        (d[0] as u32 | (d[1] as u32) << 8 | (d[2] as u32) << 16 | (d[3] as u32) << 24) as usize
+    }
+
+    /// Reads the 6 byte hardware address `SIOCGIFHWADDR` writes into
+    /// `ifr_hwaddr.sa_data`, which starts right after the 2 byte
+    /// `sa_family` at the front of the union.
+    fn hwaddr(&self) -> MacAddr {
+        let d = &self.ifr_union[2..8];
+        MacAddr::new(d[0], d[1], d[2], d[3], d[4], d[5])
+    }
+
+    /// The interface name the kernel assigned, which may differ from the
+    /// one requested (e.g. when it was a pattern like `tap%d`).
+    fn name(&self) -> String {
+        let end = self.ifr_name.iter().position(|&b| b == 0).unwrap_or(IFNAMSIZ);
+        String::from_utf8_lossy(&self.ifr_name[..end]).into_owned()
+    }
+}
+
+fn last_error() -> io::Error {
+    io::Error::last_os_error()
+}
+
+/// Opens `/dev/net/tun`, attaches it to `ifname` in TAP mode without the
+/// packet information header (`IFF_TAP | IFF_NO_PI`), and reads back the
+/// MAC address and MTU the kernel assigned the new interface.
+fn open_tap_device(ifname: &str) -> io::Result<(File, MacAddr, usize)> {
+    let file = OpenOptions::new().read(true).write(true).open("/dev/net/tun")?;
+
+    let mut ifr = IfReq::new(ifname);
+    ifr.set_flags(IFF_TAP | IFF_NO_PI);
+    if unsafe { libc::ioctl(file.as_raw_fd(), TUNSETIFF, &mut ifr) } < 0 {
+        return Err(last_error());
+    }
+
+    let mac = read_hwaddr(&ifr.name())?;
+    let mtu = read_mtu(&ifr.name())?;
+    bring_up(&ifr.name())?;
+    Ok((file, mac, mtu))
+}
+
+/// Sets `IFF_UP | IFF_RUNNING` on `ifname`, preserving whatever other flags
+/// the kernel already had set, the same way `ip link set <ifname> up` does.
+fn bring_up(ifname: &str) -> io::Result<()> {
+    let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if sock < 0 {
+        return Err(last_error());
+    }
+    let mut ifr = IfReq::new(ifname);
+    let result = unsafe {
+        if libc::ioctl(sock, SIOCGIFFLAGS, &mut ifr) < 0 {
+            -1
+        } else {
+            ifr.set_flags(ifr.flags() | IFF_UP | IFF_RUNNING);
+            libc::ioctl(sock, SIOCSIFFLAGS, &mut ifr)
+        }
+    };
+    unsafe { libc::close(sock) };
+    if result < 0 {
+        return Err(last_error());
+    }
+    Ok(())
+}
+
+/// Looks up the MAC address of an already-existing interface through a
+/// throwaway `AF_INET`/`SOCK_DGRAM` socket, the usual way to issue
+/// interface ioctls that are not specific to any particular socket type.
+fn read_hwaddr(ifname: &str) -> io::Result<MacAddr> {
+    let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if sock < 0 {
+        return Err(last_error());
+    }
+    let mut ifr = IfReq::new(ifname);
+    let result = unsafe { libc::ioctl(sock, SIOCGIFHWADDR, &mut ifr) };
+    unsafe { libc::close(sock) };
+    if result < 0 {
+        return Err(last_error());
+    }
+    Ok(ifr.hwaddr())
+}
+
+/// Looks up the MTU of an already-existing interface the same way
+/// `read_hwaddr` looks up its MAC address, so `StackInterface` can be
+/// built with the TAP device's real MTU instead of assuming
+/// `stack::DEFAULT_MTU`.
+fn read_mtu(ifname: &str) -> io::Result<usize> {
+    let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if sock < 0 {
+        return Err(last_error());
+    }
+    let mut ifr = IfReq::new(ifname);
+    let result = unsafe { libc::ioctl(sock, SIOCGIFMTU, &mut ifr) };
+    unsafe { libc::close(sock) };
+    if result < 0 {
+        return Err(last_error());
+    }
+    Ok(ifr.mtu())
+}
+
+/// `EthernetDataLinkSender` writing each frame straight to the tap device
+/// file; the kernel hands it to whatever reads the other end (a bridge, a
+/// second `rips` stack, tcpdump, ...).
+pub struct TapSender {
+    file: File,
+}
+
+impl EthernetDataLinkSender for TapSender {
+    fn build_and_send(&mut self,
+                       num_packets: usize,
+                       packet_size: usize,
+                       func: &mut FnMut(MutableEthernetPacket))
+                       -> Option<io::Result<()>> {
+        for _ in 0..num_packets {
+            let mut buffer = vec![0; packet_size];
+            {
+                let packet = MutableEthernetPacket::new(&mut buffer).unwrap();
+                func(packet);
+            }
+            if let Err(e) = self.file.write_all(&buffer) {
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(()))
+    }
+}
+
+/// `EthernetDataLinkReceiver` reading one frame per `read()` from the tap
+/// device file.
+pub struct TapReceiver {
+    file: File,
+    buffer: Vec<u8>,
+}
+
+impl EthernetDataLinkReceiver for TapReceiver {
+    fn iter<'a>(&'a mut self) -> Box<EthernetDataLinkChannelIterator<'a> + 'a> {
+        Box::new(TapIter { receiver: self })
+    }
+}
+
+struct TapIter<'a> {
+    receiver: &'a mut TapReceiver,
+}
+
+impl<'a> EthernetDataLinkChannelIterator<'a> for TapIter<'a> {
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        let len = self.receiver.file.read(&mut self.receiver.buffer)?;
+        Ok(EthernetPacket::owned(self.receiver.buffer[..len].to_vec()).unwrap())
+    }
+}
+
+/// Opens (creating if necessary) the TAP interface `ifname`, returning its
+/// MAC address and MTU together with the `EthernetChannel` the rest of the
+/// stack drives it through. The MTU is read back from the kernel rather
+/// than assumed, so callers can build a `StackInterface` that reflects the
+/// device's actual configuration instead of `stack::DEFAULT_MTU`.
+pub fn open(ifname: &str) -> io::Result<(MacAddr, usize, EthernetChannel)> {
+    let (file, mac, mtu) = open_tap_device(ifname)?;
+    let rx_fd = unsafe { libc::dup(file.as_raw_fd()) };
+    if rx_fd < 0 {
+        return Err(last_error());
+    }
+    let rx_file = unsafe { File::from_raw_fd(rx_fd) };
+
+    let sender = Box::new(TapSender { file: file });
+    let receiver = Box::new(TapReceiver {
+        file: rx_file,
+        buffer: vec![0; DEFAULT_BUFFER_SIZE],
+    });
+    Ok((mac, mtu, EthernetChannel(sender, receiver)))
+}
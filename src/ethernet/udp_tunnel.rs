@@ -0,0 +1,394 @@
+//! An `EthernetProvider` that bridges the stack onto one or more remote
+//! peers over UDP instead of a physical NIC: a software L2 overlay built
+//! entirely on the crate's own `EthernetProvider`/`EthernetDataLinkSender`/
+//! `EthernetDataLinkReceiver` plumbing.
+//!
+//! Every frame is sent wrapped in a small header (currently just the
+//! network id, so several independent overlays can share one UDP port)
+//! followed by the raw frame bytes, as the payload of a UDP datagram to
+//! one or more peers. `MacTable` learns which peer a source MAC was last
+//! seen arrive from, the same way a real switch learns its forwarding
+//! table, so unicast frames for a known MAC go to exactly the peer behind
+//! it while unknown and broadcast destinations are flooded to everyone.
+
+use arp::{Clock, SystemClock};
+
+use pnet::datalink::{Config, EthernetDataLinkChannelIterator, EthernetDataLinkReceiver,
+                      EthernetDataLinkSender};
+use pnet::packet::Packet;
+use pnet::packet::ethernet::{EthernetPacket, MutableEthernetPacket};
+use pnet::util::{MacAddr, NetworkInterface};
+
+use ethernet::{BpfInstruction, EthernetProvider};
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use DEFAULT_BUFFER_SIZE;
+
+/// Size in bytes of the header prepended to every frame before it is put in
+/// a UDP datagram: just the 4 byte network id for now.
+const HEADER_LEN: usize = 4;
+
+/// Default time a `MacTable` entry is considered valid before the peer
+/// behind it must be re-learned from a fresh frame.
+pub static DEFAULT_ENTRY_TTL_SECS: u64 = 300;
+
+fn write_network_id(buffer: &mut [u8], network_id: u32) {
+    buffer[0] = (network_id & 0xff) as u8;
+    buffer[1] = ((network_id >> 8) & 0xff) as u8;
+    buffer[2] = ((network_id >> 16) & 0xff) as u8;
+    buffer[3] = ((network_id >> 24) & 0xff) as u8;
+}
+
+fn read_network_id(buffer: &[u8]) -> u32 {
+    (buffer[0] as u32) | ((buffer[1] as u32) << 8) | ((buffer[2] as u32) << 16) |
+    ((buffer[3] as u32) << 24)
+}
+
+/// Learning table mapping an Ethernet source MAC to the `SocketAddr` of the
+/// peer frames from it last arrived from. Entries expire after a TTL the
+/// same way `arp::ArpTable` entries do, aged by a pluggable `Clock` so
+/// tests can control the passage of time instead of depending on the real
+/// system clock.
+pub struct MacTable {
+    entries: HashMap<MacAddr, (SocketAddr, Instant)>,
+    clock: Arc<Clock>,
+    ttl: Duration,
+}
+
+impl MacTable {
+    /// Creates a new, empty `MacTable`. Entries live for
+    /// `DEFAULT_ENTRY_TTL_SECS` seconds and time is read from the real OS
+    /// clock.
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock), Duration::new(DEFAULT_ENTRY_TTL_SECS, 0))
+    }
+
+    /// Creates a new, empty `MacTable` using the given `clock` as its time
+    /// source and `ttl` as the lifetime of each learned entry. Mainly
+    /// useful for tests that want to control the passage of time.
+    pub fn with_clock(clock: Arc<Clock>, ttl: Duration) -> Self {
+        MacTable {
+            entries: HashMap::new(),
+            clock: clock,
+            ttl: ttl,
+        }
+    }
+
+    /// Records that frames from `mac` arrive from `peer`, valid for this
+    /// table's TTL from now.
+    pub fn learn(&mut self, mac: MacAddr, peer: SocketAddr) {
+        let expires_at = self.clock.now() + self.ttl;
+        self.entries.insert(mac, (peer, expires_at));
+    }
+
+    /// Looks up the peer currently believed to be behind `mac`, if any
+    /// unexpired entry exists for it.
+    pub fn lookup(&mut self, mac: &MacAddr) -> Option<SocketAddr> {
+        match self.entries.get(mac) {
+            Some(&(peer, expires_at)) if expires_at > self.clock.now() => Some(peer),
+            _ => None,
+        }
+    }
+
+    /// Every peer with an unexpired entry, used to flood frames whose
+    /// destination MAC is unknown or broadcast.
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        let now = self.clock.now();
+        self.entries
+            .values()
+            .filter(|&&(_, expires_at)| expires_at > now)
+            .map(|&(peer, _)| peer)
+            .collect()
+    }
+
+    /// Drops every entry that has expired according to this table's clock.
+    /// Should be called periodically so a long lived overlay does not keep
+    /// stale peers around forever.
+    pub fn flush_expired(&mut self) {
+        let now = self.clock.now();
+        self.entries.retain(|_, &mut (_, expires_at)| expires_at > now);
+    }
+}
+
+impl Default for MacTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `EthernetProvider` that hands out a `UdpTunnelSender`/`UdpTunnelReceiver`
+/// pair backed by a UDP socket instead of a physical network adapter.
+///
+/// `static_peers` are flooded together with whatever peers the `MacTable`
+/// has learned whenever a frame's destination MAC is unknown or broadcast,
+/// so at least the initially configured peers can always be reached even
+/// before anything has been learned from them.
+pub struct UdpTunnelProvider {
+    socket: UdpSocket,
+    network_id: u32,
+    static_peers: Vec<SocketAddr>,
+    mac_table: Arc<Mutex<MacTable>>,
+}
+
+impl UdpTunnelProvider {
+    /// Creates a new `UdpTunnelProvider` sending and receiving tunneled
+    /// frames over `socket`, tagged with `network_id` so several overlays
+    /// can share the same UDP port, and flooding unknown/broadcast frames
+    /// to `static_peers` in addition to whatever `MacTable` has learned.
+    pub fn new(socket: UdpSocket, network_id: u32, static_peers: Vec<SocketAddr>) -> Self {
+        Self::with_mac_table(socket, network_id, static_peers, MacTable::new())
+    }
+
+    /// Like `new`, but lets the caller supply the `MacTable`, e.g. one
+    /// built with `MacTable::with_clock` for deterministic tests.
+    pub fn with_mac_table(socket: UdpSocket,
+                          network_id: u32,
+                          static_peers: Vec<SocketAddr>,
+                          mac_table: MacTable)
+                          -> Self {
+        UdpTunnelProvider {
+            socket: socket,
+            network_id: network_id,
+            static_peers: static_peers,
+            mac_table: Arc::new(Mutex::new(mac_table)),
+        }
+    }
+
+    /// Returns the shared `MacTable` backing this provider, so callers can
+    /// call `flush_expired` on it periodically.
+    pub fn mac_table(&self) -> Arc<Mutex<MacTable>> {
+        self.mac_table.clone()
+    }
+}
+
+impl EthernetProvider for UdpTunnelProvider {
+    /// This overlay has no raw socket of its own for the kernel to filter,
+    /// so `socket_fd` and `filter` are ignored.
+    fn channel(&mut self,
+               _iface: &NetworkInterface,
+               _config: &Config,
+               _socket_fd: Option<RawFd>,
+               _filter: Option<&[BpfInstruction]>)
+               -> io::Result<(Box<EthernetDataLinkSender>, Box<EthernetDataLinkReceiver>)> {
+        let sender = Box::new(UdpTunnelSender {
+            socket: self.socket.try_clone()?,
+            network_id: self.network_id,
+            static_peers: self.static_peers.clone(),
+            mac_table: self.mac_table.clone(),
+        });
+        let receiver = Box::new(UdpTunnelReceiver {
+            socket: self.socket.try_clone()?,
+            network_id: self.network_id,
+            mac_table: self.mac_table.clone(),
+            buffer: vec![0; DEFAULT_BUFFER_SIZE],
+        });
+        Ok((sender, receiver))
+    }
+
+    /// This overlay is not backed by any real network adapter, so there is
+    /// nothing to list.
+    fn get_network_interfaces(&self) -> Vec<NetworkInterface> {
+        Vec::new()
+    }
+}
+
+/// `EthernetDataLinkSender` that encapsulates every frame handed to it in a
+/// UDP datagram, sent to the peer behind the frame's destination MAC if
+/// `MacTable` knows it, or flooded to every known peer otherwise.
+pub struct UdpTunnelSender {
+    socket: UdpSocket,
+    network_id: u32,
+    static_peers: Vec<SocketAddr>,
+    mac_table: Arc<Mutex<MacTable>>,
+}
+
+impl UdpTunnelSender {
+    fn destinations(&self, dst: MacAddr) -> Vec<SocketAddr> {
+        let mut table = self.mac_table.lock().expect("Unable to lock MacTable for reading");
+        match table.lookup(&dst) {
+            Some(peer) => vec![peer],
+            None => {
+                let mut peers = table.peers();
+                for peer in &self.static_peers {
+                    if !peers.contains(peer) {
+                        peers.push(*peer);
+                    }
+                }
+                peers
+            }
+        }
+    }
+
+    fn send_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let dst = match EthernetPacket::new(frame) {
+            Some(packet) => packet.get_destination(),
+            None => return Ok(()),
+        };
+        let mut datagram = vec![0; HEADER_LEN + frame.len()];
+        write_network_id(&mut datagram[..HEADER_LEN], self.network_id);
+        datagram[HEADER_LEN..].copy_from_slice(frame);
+        for peer in self.destinations(dst) {
+            self.socket.send_to(&datagram, peer)?;
+        }
+        Ok(())
+    }
+}
+
+impl EthernetDataLinkSender for UdpTunnelSender {
+    fn build_and_send(&mut self,
+                       num_packets: usize,
+                       packet_size: usize,
+                       func: &mut FnMut(MutableEthernetPacket))
+                       -> Option<io::Result<()>> {
+        for _ in 0..num_packets {
+            let mut buffer = vec![0; packet_size];
+            {
+                let packet = MutableEthernetPacket::new(&mut buffer).unwrap();
+                func(packet);
+            }
+            if let Err(e) = self.send_frame(&buffer) {
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(()))
+    }
+}
+
+/// `EthernetDataLinkReceiver` that decapsulates inbound UDP datagrams back
+/// into frames, learning the sending peer's `SocketAddr` against the
+/// frame's source MAC in `MacTable` as it goes.
+pub struct UdpTunnelReceiver {
+    socket: UdpSocket,
+    network_id: u32,
+    mac_table: Arc<Mutex<MacTable>>,
+    buffer: Vec<u8>,
+}
+
+impl EthernetDataLinkReceiver for UdpTunnelReceiver {
+    fn iter<'a>(&'a mut self) -> Box<EthernetDataLinkChannelIterator<'a> + 'a> {
+        Box::new(UdpTunnelIter { receiver: self })
+    }
+}
+
+struct UdpTunnelIter<'a> {
+    receiver: &'a mut UdpTunnelReceiver,
+}
+
+impl<'a> EthernetDataLinkChannelIterator<'a> for UdpTunnelIter<'a> {
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        loop {
+            let (len, from) = self.receiver.socket.recv_from(&mut self.receiver.buffer)?;
+            if len < HEADER_LEN {
+                continue;
+            }
+            if read_network_id(&self.receiver.buffer[..HEADER_LEN]) != self.receiver.network_id {
+                continue;
+            }
+            let frame = &self.receiver.buffer[HEADER_LEN..len];
+            let src = match EthernetPacket::new(frame) {
+                Some(packet) => packet.get_source(),
+                None => continue,
+            };
+            self.receiver
+                .mac_table
+                .lock()
+                .expect("Unable to lock MacTable for writing")
+                .learn(src, from);
+            return Ok(EthernetPacket::new(frame).unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    struct FakeClock {
+        now: Cell<Instant>,
+    }
+
+    impl FakeClock {
+        fn new() -> Arc<FakeClock> {
+            Arc::new(FakeClock { now: Cell::new(Instant::now()) })
+        }
+
+        fn advance(&self, by: Duration) {
+            self.now.set(self.now.get() + by);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.now.get()
+        }
+    }
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), port))
+    }
+
+    fn mac(n: u8) -> MacAddr {
+        MacAddr::new(0, 0, 0, 0, 0, n)
+    }
+
+    #[test]
+    fn lookup_before_learning_is_none() {
+        let mut table = MacTable::new();
+        assert_eq!(None, table.lookup(&mac(1)));
+    }
+
+    #[test]
+    fn learn_then_lookup() {
+        let mut table = MacTable::new();
+        table.learn(mac(1), peer(1234));
+        assert_eq!(Some(peer(1234)), table.lookup(&mac(1)));
+    }
+
+    #[test]
+    fn entry_expires() {
+        let clock = FakeClock::new();
+        let mut table = MacTable::with_clock(clock.clone(), Duration::new(60, 0));
+
+        table.learn(mac(1), peer(1234));
+        assert_eq!(Some(peer(1234)), table.lookup(&mac(1)));
+
+        clock.advance(Duration::new(61, 0));
+        assert_eq!(None, table.lookup(&mac(1)));
+    }
+
+    #[test]
+    fn flush_expired_removes_stale_entries_only() {
+        let clock = FakeClock::new();
+        let mut table = MacTable::with_clock(clock.clone(), Duration::new(10, 0));
+
+        table.learn(mac(1), peer(1111));
+        clock.advance(Duration::new(5, 0));
+        table.learn(mac(2), peer(2222));
+        clock.advance(Duration::new(6, 0));
+
+        table.flush_expired();
+
+        assert_eq!(None, table.lookup(&mac(1)));
+        assert_eq!(Some(peer(2222)), table.lookup(&mac(2)));
+    }
+
+    #[test]
+    fn peers_only_returns_unexpired_entries() {
+        let clock = FakeClock::new();
+        let mut table = MacTable::with_clock(clock.clone(), Duration::new(10, 0));
+
+        table.learn(mac(1), peer(1111));
+        clock.advance(Duration::new(11, 0));
+        table.learn(mac(2), peer(2222));
+
+        assert_eq!(vec![peer(2222)], table.peers());
+    }
+}
@@ -2,11 +2,15 @@ use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
 use std::time::SystemTime;
 
-use pnet::packet::icmp::{IcmpPacket, IcmpType};
+use arc_swap::ArcSwap;
+
+use pnet::packet::icmp::echo_reply::EchoReplyPacket;
+use pnet::packet::icmp::{checksum, IcmpPacket, IcmpType, IcmpTypes};
 use pnet::packet::ipv4::Ipv4Packet;
 use pnet::packet::Packet;
 
 use {RxError, RxResult};
+use checksum::ChecksumCapabilities;
 use ipv4::Ipv4Listener;
 
 /// Trait that must be implemented by any struct who want to receive Icmp
@@ -16,37 +20,86 @@ pub trait IcmpListener: Send {
     fn recv(&mut self, time: SystemTime, packet: &Ipv4Packet);
 }
 
-/// Type binding for how the listeners in `IcmpRx` are structured.
-pub type IcmpListenerLookup = HashMap<IcmpType, Vec<Box<IcmpListener>>>;
+/// Key listeners in `IcmpRx` are registered under. `EchoReply` packets are
+/// demultiplexed further on their 16 bit echo identifier, so that e.g.
+/// multiple concurrent `PingSocket`s bound to `IcmpTypes::EchoReply` each
+/// only see the replies meant for them. `EchoRequest` packets are *not*
+/// demultiplexed this way, since their identifier is picked by whichever
+/// remote host is pinging us and cannot be known in advance: they, like
+/// every other `IcmpType`, are dispatched on the type alone, so the single
+/// `icmp::EchoResponder` registered for `IcmpTypes::EchoRequest` sees every
+/// incoming ping whatever identifier it carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IcmpListenerKey {
+    Type(IcmpType),
+    Echo(IcmpType, u16),
+}
+
+/// Type binding for how the listeners in `IcmpRx` are structured. Each
+/// listener is individually wrapped in its own `Mutex` so that `IcmpRx::recv`
+/// can dispatch via a lock-free `ArcSwap::load` and only ever has to take a
+/// fine grained, almost never contended, per-listener lock.
+pub type IcmpListenerLookup = HashMap<IcmpListenerKey, Vec<Arc<Mutex<Box<IcmpListener>>>>>;
 
 /// Listener and parser of Icmp packets.
 pub struct IcmpRx {
-    listeners: Arc<Mutex<IcmpListenerLookup>>,
+    listeners: Arc<ArcSwap<IcmpListenerLookup>>,
+    checksums: ChecksumCapabilities,
 }
 
 impl IcmpRx {
-    /// Constructs a new `IcmpRx` with the given listeners.
+    /// Constructs a new `IcmpRx` with the given listeners, verifying every
+    /// incoming packet's checksum in software.
     /// Casted before return to make it easy to add to the desired `Ipv4Rx`.
-    pub fn new(listeners: Arc<Mutex<IcmpListenerLookup>>) -> IcmpRx {
-        IcmpRx { listeners: listeners }
+    pub fn new(listeners: Arc<ArcSwap<IcmpListenerLookup>>) -> IcmpRx {
+        IcmpRx::with_checksums(listeners, ChecksumCapabilities::default())
+    }
+
+    /// Constructs a new `IcmpRx`, skipping checksum verification according
+    /// to `checksums` when the underlying NIC already did it in hardware.
+    pub fn with_checksums(listeners: Arc<ArcSwap<IcmpListenerLookup>>,
+                           checksums: ChecksumCapabilities)
+                           -> IcmpRx {
+        IcmpRx {
+            listeners: listeners,
+            checksums: checksums,
+        }
+    }
+
+    /// Computes the `IcmpListenerKey` a given incoming packet should be
+    /// dispatched on. `EchoReply` packets carry their identifier right
+    /// after the checksum, the same 4 bytes `PingBuilder`/`EchoReplyBuilder`
+    /// write on the Tx side.
+    fn key(icmp_type: IcmpType, payload: &[u8]) -> IcmpListenerKey {
+        match icmp_type {
+            IcmpTypes::EchoReply => {
+                let identifier = EchoReplyPacket::new(payload).unwrap().get_identifier();
+                IcmpListenerKey::Echo(icmp_type, identifier)
+            }
+            _ => IcmpListenerKey::Type(icmp_type),
+        }
     }
 }
 
 impl Ipv4Listener for IcmpRx {
     fn recv(&mut self, time: SystemTime, ip_pkg: Ipv4Packet) -> RxResult {
-        let (icmp_type, _icmp_code) = {
+        let icmp_type = {
             let icmp_pkg = IcmpPacket::new(ip_pkg.payload()).unwrap();
-            (icmp_pkg.get_icmp_type(), icmp_pkg.get_icmp_code())
+            if self.checksums.icmpv4.rx() && icmp_pkg.get_checksum() != checksum(&icmp_pkg) {
+                return Err(RxError::InvalidChecksum);
+            }
+            icmp_pkg.get_icmp_type()
         };
         trace!("Icmp got a packet with {} bytes!", ip_pkg.payload().len());
-        let mut listeners = self.listeners.lock().unwrap();
-        if let Some(type_listeners) = listeners.get_mut(&icmp_type) {
+        let key = Self::key(icmp_type, ip_pkg.payload());
+        let listeners = self.listeners.load();
+        if let Some(type_listeners) = listeners.get(&key) {
             for listener in type_listeners {
-                listener.recv(time, &ip_pkg);
+                listener.lock().unwrap().recv(time, &ip_pkg);
             }
             Ok(())
         } else {
-            Err(RxError::NoListener(format!("Icmp, {:?}", icmp_type)))
+            Err(RxError::NoListener(format!("Icmp, {:?}", key)))
         }
     }
 }
@@ -1,10 +1,17 @@
 use {Payload, HasPayload, BasicPayload, TxResult};
+use checksum::Checksum;
 use ipv4::{Ipv4Payload, Ipv4Tx};
 
-use pnet::packet::MutablePacket;
+use pnet::packet::{MutablePacket, Packet};
 use pnet::packet::icmp::{IcmpCode, IcmpType, MutableIcmpPacket, checksum, IcmpTypes};
+use pnet::packet::icmp::echo_reply::IcmpCodes as EchoReplyCodes;
 use pnet::packet::icmp::echo_request::IcmpCodes;
+use pnet::packet::icmp::destination_unreachable::IcmpCodes as DestinationUnreachableCodes;
+use pnet::packet::icmp::time_exceeded::IcmpCodes as TimeExceededCodes;
 use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv4::Ipv4Packet;
+
+use std::cmp;
 
 /// Trait for anything wishing to be the payload of an Icmp packet.
 pub trait IcmpPayload: Payload {
@@ -57,12 +64,18 @@ impl<'a> HasPayload for BasicIcmpPayload<'a> {
 /// Icmp packet sender struct.
 pub struct IcmpTx<T: Ipv4Tx> {
     ipv4: T,
+    checksum: Checksum,
 }
 
 impl<T: Ipv4Tx> IcmpTx<T> {
-    /// Creates a new `IcmpTx` based on `ipv4`
+    /// Creates a new `IcmpTx` based on `ipv4`. Inherits its checksum
+    /// offload capability from `ipv4`.
     pub fn new(ipv4: T) -> Self {
-        IcmpTx { ipv4: ipv4 }
+        let checksum = ipv4.checksums().icmpv4;
+        IcmpTx {
+            ipv4: ipv4,
+            checksum: checksum,
+        }
     }
 
     /// Sends a general Icmp packet. Should not be called directly in general,
@@ -70,14 +83,33 @@ impl<T: Ipv4Tx> IcmpTx<T> {
     pub fn send<P>(&mut self, payload: P) -> TxResult
         where P: IcmpPayload
     {
-        let builder = IcmpBuilder::new(payload);
+        let builder = IcmpBuilder::new(payload, self.checksum);
         self.ipv4.send(builder)
     }
 
-    /// Sends an Echo Request packet (ping) with the given payload.
-    pub fn send_echo(&mut self, payload: &[u8]) -> TxResult {
-        let builder = PingBuilder::new(payload);
-        println!("PingBuilder has len {}", builder.len());
+    /// Sends an Echo Request packet (ping) with the given identifier,
+    /// sequence number and payload.
+    pub fn send_echo(&mut self, identifier: u16, sequence_number: u16, payload: &[u8]) -> TxResult {
+        let builder = PingBuilder::new(identifier, sequence_number, payload);
+        self.send(builder)
+    }
+
+    /// Sends an Echo Reply packet (pong) with the given identifier,
+    /// sequence number and payload, normally copied verbatim from the
+    /// Echo Request it answers.
+    pub fn send_echo_reply(&mut self,
+                            identifier: u16,
+                            sequence_number: u16,
+                            payload: &[u8])
+                            -> TxResult {
+        let builder = EchoReplyBuilder::new(identifier, sequence_number, payload);
+        self.send(builder)
+    }
+
+    /// Sends an Icmp error reply (Destination Unreachable or Time
+    /// Exceeded) in response to the offending datagram `orig`.
+    pub fn send_error(&mut self, error: IcmpError, orig: &Ipv4Packet) -> TxResult {
+        let builder = IcmpErrorBuilder::new(error, orig);
         self.send(builder)
     }
 }
@@ -85,11 +117,15 @@ impl<T: Ipv4Tx> IcmpTx<T> {
 
 pub struct IcmpBuilder<P: IcmpPayload> {
     builder: P,
+    checksum: Checksum,
 }
 
 impl<P: IcmpPayload> IcmpBuilder<P> {
-    pub fn new(builder: P) -> IcmpBuilder<P> {
-        IcmpBuilder { builder: builder }
+    pub fn new(builder: P, checksum: Checksum) -> IcmpBuilder<P> {
+        IcmpBuilder {
+            builder: builder,
+            checksum: checksum,
+        }
     }
 }
 
@@ -113,18 +149,26 @@ impl<P: IcmpPayload> Payload for IcmpBuilder<P> {
             self.builder.build_header(&mut header_pkg);
         }
         self.builder.build(&mut pkg.packet_mut()[8..]);
-        let checksum = checksum(&pkg.to_immutable());
-        pkg.set_checksum(checksum);
+        if self.checksum.tx() {
+            let csum = checksum(&pkg.to_immutable());
+            pkg.set_checksum(csum);
+        }
     }
 }
 
 pub struct PingBuilder<'a> {
+    identifier: u16,
+    sequence_number: u16,
     payload: BasicPayload<'a>,
 }
 
 impl<'a> PingBuilder<'a> {
-    pub fn new(payload: &'a [u8]) -> PingBuilder<'a> {
-        PingBuilder { payload: BasicPayload::new(payload) }
+    pub fn new(identifier: u16, sequence_number: u16, payload: &'a [u8]) -> PingBuilder<'a> {
+        PingBuilder {
+            identifier: identifier,
+            sequence_number: sequence_number,
+            payload: BasicPayload::new(payload),
+        }
     }
 }
 
@@ -137,7 +181,17 @@ impl<'a> IcmpPayload for PingBuilder<'a> {
         IcmpCodes::NoCode
     }
 
-    fn build_header(&self, _header: &mut MutableIcmpPacket) {}
+    /// Writes the echo identifier and sequence number into the 4 bytes of
+    /// the generic Icmp header following the checksum, since `pnet`'s
+    /// generic `MutableIcmpPacket` has no concept of those echo-specific
+    /// fields.
+    fn build_header(&self, header: &mut MutableIcmpPacket) {
+        let buffer = header.packet_mut();
+        buffer[4] = (self.identifier >> 8) as u8;
+        buffer[5] = self.identifier as u8;
+        buffer[6] = (self.sequence_number >> 8) as u8;
+        buffer[7] = self.sequence_number as u8;
+    }
 }
 
 impl<'a> HasPayload for PingBuilder<'a> {
@@ -150,6 +204,155 @@ impl<'a> HasPayload for PingBuilder<'a> {
     }
 }
 
+/// Builds an Echo Reply, the answer to an Echo Request (`PingBuilder`)
+/// carrying the same identifier, sequence number and payload.
+pub struct EchoReplyBuilder<'a> {
+    identifier: u16,
+    sequence_number: u16,
+    payload: BasicPayload<'a>,
+}
+
+impl<'a> EchoReplyBuilder<'a> {
+    pub fn new(identifier: u16, sequence_number: u16, payload: &'a [u8]) -> EchoReplyBuilder<'a> {
+        EchoReplyBuilder {
+            identifier: identifier,
+            sequence_number: sequence_number,
+            payload: BasicPayload::new(payload),
+        }
+    }
+}
+
+impl<'a> IcmpPayload for EchoReplyBuilder<'a> {
+    fn icmp_type(&self) -> IcmpType {
+        IcmpTypes::EchoReply
+    }
+
+    fn icmp_code(&self) -> IcmpCode {
+        EchoReplyCodes::NoCode
+    }
+
+    /// Writes the echo identifier and sequence number into the 4 bytes of
+    /// the generic Icmp header following the checksum, same as
+    /// `PingBuilder::build_header`.
+    fn build_header(&self, header: &mut MutableIcmpPacket) {
+        let buffer = header.packet_mut();
+        buffer[4] = (self.identifier >> 8) as u8;
+        buffer[5] = self.identifier as u8;
+        buffer[6] = (self.sequence_number >> 8) as u8;
+        buffer[7] = self.sequence_number as u8;
+    }
+}
+
+impl<'a> HasPayload for EchoReplyBuilder<'a> {
+    fn get_payload(&self) -> &Payload {
+        &self.payload
+    }
+
+    fn get_payload_mut(&mut self) -> &mut Payload {
+        &mut self.payload
+    }
+}
+
+/// The different kinds of Icmp error packets `IcmpErrorBuilder` can build.
+/// Both carry the offending datagram's IP header plus the first 8 bytes of
+/// its payload, per RFC 792.
+#[derive(Debug)]
+pub enum IcmpError {
+    /// Sent when the destination is ours, but there was no listener able to
+    /// handle the next level protocol or port it was addressed to.
+    DestinationUnreachable(IcmpCode),
+    /// Sent when a forwarded datagram's TTL reached zero in transit.
+    TimeExceeded(IcmpCode),
+}
+
+impl IcmpError {
+    /// Shorthand for `DestinationUnreachable` with the Protocol Unreachable
+    /// code, the response to a datagram addressed to us with no registered
+    /// handler for its next level protocol.
+    pub fn protocol_unreachable() -> IcmpError {
+        IcmpError::DestinationUnreachable(DestinationUnreachableCodes::DestinationProtocolUnreachable)
+    }
+
+    /// Shorthand for `DestinationUnreachable` with the Port Unreachable
+    /// code, the response to a datagram addressed to a transport protocol
+    /// that has no listener bound to its destination port.
+    pub fn port_unreachable() -> IcmpError {
+        IcmpError::DestinationUnreachable(DestinationUnreachableCodes::DestinationPortUnreachable)
+    }
+
+    /// Shorthand for `TimeExceeded` with the Time to Live Exceeded in
+    /// Transit code, the response to a forwarded datagram whose TTL hit
+    /// zero.
+    pub fn ttl_exceeded() -> IcmpError {
+        IcmpError::TimeExceeded(TimeExceededCodes::TimeToLiveExceededInTransit)
+    }
+
+    /// Shorthand for `TimeExceeded` with the Fragment Reassembly Time
+    /// Exceeded code, the response sent when `Ipv4Rx`'s reassembly timeout
+    /// elapses before every fragment of a datagram arrived.
+    pub fn reassembly_timeout() -> IcmpError {
+        IcmpError::TimeExceeded(TimeExceededCodes::FragmentReassemblyTimeExceeded)
+    }
+
+    fn icmp_type(&self) -> IcmpType {
+        match *self {
+            IcmpError::DestinationUnreachable(_) => IcmpTypes::DestinationUnreachable,
+            IcmpError::TimeExceeded(_) => IcmpTypes::TimeExceeded,
+        }
+    }
+
+    fn icmp_code(&self) -> IcmpCode {
+        match *self {
+            IcmpError::DestinationUnreachable(code) => code,
+            IcmpError::TimeExceeded(code) => code,
+        }
+    }
+}
+
+/// Builds an Icmp error reply (Destination Unreachable or Time Exceeded)
+/// for the offending datagram `orig`. Only the original IP header and the
+/// first 8 bytes of its payload are embedded, as required by RFC 792. The
+/// 4 bytes following the checksum are unused for both these types and are
+/// left zeroed.
+pub struct IcmpErrorBuilder<'a> {
+    error: IcmpError,
+    payload: BasicPayload<'a>,
+}
+
+impl<'a> IcmpErrorBuilder<'a> {
+    pub fn new(error: IcmpError, orig: &'a Ipv4Packet) -> IcmpErrorBuilder<'a> {
+        let header_len = (orig.get_header_length() as usize) * 4;
+        let embedded_len = cmp::min(orig.packet().len(), header_len + 8);
+        let embedded = &orig.packet()[..embedded_len];
+        IcmpErrorBuilder {
+            error: error,
+            payload: BasicPayload::new(embedded),
+        }
+    }
+}
+
+impl<'a> IcmpPayload for IcmpErrorBuilder<'a> {
+    fn icmp_type(&self) -> IcmpType {
+        self.error.icmp_type()
+    }
+
+    fn icmp_code(&self) -> IcmpCode {
+        self.error.icmp_code()
+    }
+
+    fn build_header(&self, _header: &mut MutableIcmpPacket) {}
+}
+
+impl<'a> HasPayload for IcmpErrorBuilder<'a> {
+    fn get_payload(&self) -> &Payload {
+        &self.payload
+    }
+
+    fn get_payload_mut(&mut self) -> &mut Payload {
+        &mut self.payload
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use {TxResult, TxError};
@@ -158,22 +361,34 @@ mod tests {
     use pnet::packet::Packet;
     use pnet::packet::icmp::IcmpTypes;
     use pnet::packet::icmp::echo_request::EchoRequestPacket;
+    use pnet::packet::icmp::destination_unreachable::DestinationUnreachablePacket;
     use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+    use pnet::packet::ipv4::{Ipv4Packet, MutableIpv4Packet};
 
     use std::error::Error;
     use std::net::Ipv4Addr;
     use std::sync::mpsc::{self, Sender, Receiver};
 
     use super::*;
+    use checksum::ChecksumCapabilities;
 
     pub struct MockIpv4Tx {
         tx: Sender<(IpNextHeaderProtocol, Box<[u8]>)>,
+        checksums: ChecksumCapabilities,
     }
 
     impl MockIpv4Tx {
         pub fn new() -> (MockIpv4Tx, Receiver<(IpNextHeaderProtocol, Box<[u8]>)>) {
+            Self::with_checksums(ChecksumCapabilities::default())
+        }
+
+        pub fn with_checksums(checksums: ChecksumCapabilities)
+                               -> (MockIpv4Tx, Receiver<(IpNextHeaderProtocol, Box<[u8]>)>) {
             let (tx, rx) = mpsc::channel();
-            let ipv4 = MockIpv4Tx { tx: tx };
+            let ipv4 = MockIpv4Tx {
+                tx: tx,
+                checksums: checksums,
+            };
             (ipv4, rx)
         }
     }
@@ -187,6 +402,10 @@ mod tests {
             Ipv4Addr::new(0, 0, 0, 0)
         }
 
+        fn checksums(&self) -> ChecksumCapabilities {
+            self.checksums
+        }
+
         fn send<P: Ipv4Payload>(&mut self, mut payload: P) -> TxResult {
             let mut buffer = vec![0; payload.len() as usize];
             payload.build(&mut buffer);
@@ -201,7 +420,7 @@ mod tests {
     fn test_send_echo() {
         let (ipv4, read_handle) = MockIpv4Tx::new();
         let mut testee = IcmpTx::new(ipv4);
-        testee.send_echo(&[9, 55]).unwrap();
+        testee.send_echo(0, 0, &[9, 55]).unwrap();
 
         let (next_level_protocol, data) = read_handle.try_recv().unwrap();
         assert_eq!(IpNextHeaderProtocols::Icmp, next_level_protocol);
@@ -209,7 +428,63 @@ mod tests {
         assert_eq!(IcmpTypes::EchoRequest, echo_pkg.get_icmp_type());
         assert_eq!(0, echo_pkg.get_icmp_code().0);
         assert_eq!(61128, echo_pkg.get_checksum());
+        assert_eq!(0, echo_pkg.get_identifier());
+        assert_eq!(0, echo_pkg.get_sequence_number());
         assert_eq!([9, 55], echo_pkg.payload());
     }
 
+    #[test]
+    fn test_send_echo_skips_checksum_when_offloaded() {
+        let mut checksums = ChecksumCapabilities::default();
+        checksums.icmpv4 = Checksum::Rx;
+        let (ipv4, read_handle) = MockIpv4Tx::with_checksums(checksums);
+        let mut testee = IcmpTx::new(ipv4);
+        testee.send_echo(0, 0, &[9, 55]).unwrap();
+
+        let (_, data) = read_handle.try_recv().unwrap();
+        let echo_pkg = EchoRequestPacket::new(&data).unwrap();
+        assert_eq!(0, echo_pkg.get_checksum());
+    }
+
+    #[test]
+    fn test_send_echo_sets_identifier_and_sequence_number() {
+        let (ipv4, read_handle) = MockIpv4Tx::new();
+        let mut testee = IcmpTx::new(ipv4);
+        testee.send_echo(0x1234, 0x5678, &[9, 55]).unwrap();
+
+        let (_, data) = read_handle.try_recv().unwrap();
+        let echo_pkg = EchoRequestPacket::new(&data).unwrap();
+        assert_eq!(0x1234, echo_pkg.get_identifier());
+        assert_eq!(0x5678, echo_pkg.get_sequence_number());
+    }
+
+    #[test]
+    fn test_send_error_embeds_original_header_and_8_bytes_of_payload() {
+        let mut orig_buffer = vec![0u8; 30];
+        {
+            let mut orig_pkg = MutableIpv4Packet::new(&mut orig_buffer).unwrap();
+            orig_pkg.set_header_length(5); // No options, 20 byte header
+            orig_pkg.set_total_length(30);
+            orig_pkg.set_source(Ipv4Addr::new(10, 0, 0, 1));
+            orig_pkg.set_destination(Ipv4Addr::new(10, 0, 0, 2));
+            orig_pkg.payload_mut().copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        }
+
+        let (ipv4, read_handle) = MockIpv4Tx::new();
+        let mut testee = IcmpTx::new(ipv4);
+        {
+            let orig_pkg = Ipv4Packet::new(&orig_buffer).unwrap();
+            testee.send_error(IcmpError::protocol_unreachable(), &orig_pkg).unwrap();
+        }
+
+        let (next_level_protocol, data) = read_handle.try_recv().unwrap();
+        assert_eq!(IpNextHeaderProtocols::Icmp, next_level_protocol);
+        let error_pkg = DestinationUnreachablePacket::new(&data).unwrap();
+        assert_eq!(IcmpTypes::DestinationUnreachable, error_pkg.get_icmp_type());
+        assert_eq!(DestinationUnreachableCodes::DestinationProtocolUnreachable.0,
+                   error_pkg.get_icmp_code().0);
+        assert_eq!(20 + 8, error_pkg.payload().len());
+        assert_eq!([1, 2, 3, 4, 5, 6, 7, 8], error_pkg.payload()[20..]);
+    }
+
 }
@@ -1,25 +1,218 @@
 mod icmp_rx;
 mod icmp_tx;
 
-pub use self::icmp_rx::{IcmpListener, IcmpListenerLookup, IcmpRx};
-pub use self::icmp_tx::{BasicIcmpPayload, IcmpBuilder, IcmpPayload, IcmpTx, PingBuilder};
-
-
-// pub struct PingSocket {
-//     echo: Echo,
-//     reader: Option<Receiver<Box<[u8]>>>,
-//     identifier: u16,
-//     sequence_number: u16,
-// }
-
-// impl PingSocket {
-//     pub fn bind(str, stack?) -> PingSocket {
-//
-//     }
-//
-//     pub fn send_to();
-//
-//     pub fn recv();
-//
-//     pub fn take_recv() -> Result<Receiver<Box<[u8]>>, ()>;
-// }
+pub use self::icmp_rx::{IcmpListener, IcmpListenerKey, IcmpListenerLookup, IcmpRx};
+pub use self::icmp_tx::{BasicIcmpPayload, EchoReplyBuilder, IcmpBuilder, IcmpError,
+                         IcmpErrorBuilder, IcmpPayload, IcmpTx, PingBuilder};
+
+use std::collections::HashMap;
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use pnet::packet::Packet;
+use pnet::packet::icmp::IcmpTypes;
+use pnet::packet::icmp::echo_reply::EchoReplyPacket;
+use pnet::packet::ipv4::Ipv4Packet;
+
+use ethernet::EthernetTxImpl;
+use ipv4::Ipv4TxImpl;
+use stack::StackInterfaceMsg;
+use tx::TxImpl;
+use {TxError, TxResult};
+#[cfg(not(feature = "unit-tests"))]
+use {NetworkStack, StackError, StackResult};
+
+/// Concrete `IcmpTx` type handed out by `NetworkStack::icmp_tx`. Named here
+/// so `PingSocket` does not have to spell out the full `Ipv4Tx`/`EthernetTx`
+/// stack every time, the same way `udp::UdpSocket` caches a concrete
+/// `UdpTx`.
+type StackIcmpTx = IcmpTx<Ipv4TxImpl<EthernetTxImpl<TxImpl>>>;
+
+/// Hands out Icmp echo identifiers that are unique for the lifetime of the
+/// process, the same way `NetworkStack::get_random_port` picks unused Udp
+/// ports, but without needing to consult any shared listener table since
+/// identifiers are simply never reused.
+static NEXT_IDENTIFIER: AtomicUsize = AtomicUsize::new(0);
+
+fn next_identifier() -> u16 {
+    NEXT_IDENTIFIER.fetch_add(1, Ordering::Relaxed) as u16
+}
+
+/// `IcmpListener` that answers every incoming Echo Request by reporting it
+/// to the owning `StackInterfaceThread`, which builds and sends the
+/// matching Echo Reply, mirroring how smoltcp's interface auto-answers a
+/// ping instead of requiring every embedder to implement one itself.
+/// Registered by `NetworkStack::add_ipv4` under
+/// `IcmpListenerKey::Type(IcmpTypes::EchoRequest)` on every local address,
+/// so it sees every Echo Request whatever identifier the remote sender
+/// picked for it.
+#[derive(Clone)]
+pub struct EchoResponder {
+    stack_tx: mpsc::Sender<StackInterfaceMsg>,
+}
+
+impl EchoResponder {
+    pub fn new(stack_tx: mpsc::Sender<StackInterfaceMsg>) -> EchoResponder {
+        EchoResponder { stack_tx: stack_tx }
+    }
+}
+
+impl IcmpListener for EchoResponder {
+    fn recv(&mut self, _time: SystemTime, packet: &Ipv4Packet) {
+        let (src, dst) = (packet.get_destination(), packet.get_source());
+        let msg = StackInterfaceMsg::IcmpEchoReply(src, dst, packet.packet().to_vec());
+        self.stack_tx.send(msg).unwrap_or(());
+    }
+}
+
+/// `IcmpListener` that forwards the payload of every Echo Reply it is
+/// handed, tagged with the `SystemTime` it arrived at so a caller can
+/// compute a round-trip time against when it sent the matching Echo
+/// Request, onto an internal channel that its `PingSocket`'s `recv`/
+/// `try_recv` reads from. Registered under an `IcmpListenerKey::Echo` key,
+/// so `IcmpRx` only ever hands it replies carrying this socket's
+/// identifier.
+#[derive(Clone)]
+struct PingSocketListener {
+    chan: mpsc::Sender<(SystemTime, Box<[u8]>)>,
+}
+
+impl IcmpListener for PingSocketListener {
+    fn recv(&mut self, time: SystemTime, packet: &Ipv4Packet) {
+        if let Some(echo_reply) = EchoReplyPacket::new(packet.payload()) {
+            let payload = echo_reply.payload().to_vec().into_boxed_slice();
+            self.chan.send((time, payload)).unwrap_or(());
+        }
+    }
+}
+
+/// A bound Icmp echo ("ping") socket, modeled on smoltcp's Icmp socket:
+/// `bind` allocates this socket a unique echo identifier and registers it
+/// to receive Echo Replies on `local_ip`, `send_to` wraps `IcmpTx`/
+/// `PingBuilder` to emit an Echo Request carrying that identifier and an
+/// auto-incrementing sequence number, and `recv`/`try_recv` read matching
+/// Echo Reply payloads off an internal channel fed only by replies whose
+/// identifier belongs to this socket.
+#[cfg(not(feature = "unit-tests"))]
+pub struct PingSocket {
+    local_ip: Ipv4Addr,
+    identifier: u16,
+    sequence_number: u16,
+    stack: Arc<Mutex<NetworkStack>>,
+    tx_cache: HashMap<Ipv4Addr, StackIcmpTx>,
+    reader: Option<Receiver<(SystemTime, Box<[u8]>)>>,
+}
+
+#[cfg(not(feature = "unit-tests"))]
+impl PingSocket {
+    /// Binds a new `PingSocket` to `local_ip`, allocating it a unique Icmp
+    /// echo identifier and registering it with `stack` to receive Echo
+    /// Replies addressed to that identifier.
+    pub fn bind(local_ip: Ipv4Addr, stack: Arc<Mutex<NetworkStack>>) -> io::Result<PingSocket> {
+        let identifier = next_identifier();
+        let (chan, reader) = mpsc::channel();
+        let listener = PingSocketListener { chan: chan };
+        let key = IcmpListenerKey::Echo(IcmpTypes::EchoReply, identifier);
+        stack.lock().expect("Unable to lock stack").icmp_listen(local_ip, key, listener)?;
+        Ok(PingSocket {
+            local_ip: local_ip,
+            identifier: identifier,
+            sequence_number: 0,
+            stack: stack,
+            tx_cache: HashMap::new(),
+            reader: Some(reader),
+        })
+    }
+
+    /// This socket's Icmp echo identifier, the same role a local port plays
+    /// for `UdpSocket`.
+    pub fn identifier(&self) -> u16 {
+        self.identifier
+    }
+
+    /// The local address this socket is bound to.
+    pub fn local_addr(&self) -> Ipv4Addr {
+        self.local_ip
+    }
+
+    /// Sends an Echo Request with `payload` to `dst`, tagged with this
+    /// socket's identifier and the next sequence number. The sequence
+    /// number is incremented on every call so an application can run a ping
+    /// loop without manually driving `IcmpTx`.
+    pub fn send_to(&mut self, dst: Ipv4Addr, payload: &[u8]) -> io::Result<usize> {
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        self.internal_send(dst, payload).map(|_| payload.len()).map_err(|e| e.into())
+    }
+
+    fn internal_send(&mut self, dst: Ipv4Addr, payload: &[u8]) -> StackResult<()> {
+        match self.internal_send_on_cached_tx(dst, payload) {
+            Err(TxError::InvalidTx) => {
+                let new_icmp_tx = {
+                    let mut stack = self.stack.lock().unwrap();
+                    stack.icmp_tx(dst)?
+                };
+                self.tx_cache.insert(dst, new_icmp_tx);
+                self.internal_send(dst, payload)
+            }
+            result => result.map_err(StackError::TxError),
+        }
+    }
+
+    fn internal_send_on_cached_tx(&mut self, dst: Ipv4Addr, payload: &[u8]) -> TxResult {
+        let (identifier, sequence_number) = (self.identifier, self.sequence_number);
+        if let Some(icmp_tx) = self.tx_cache.get_mut(&dst) {
+            icmp_tx.send_echo(identifier, sequence_number, payload)
+        } else {
+            // No cached IcmpTx is treated as an existing but outdated one
+            Err(TxError::InvalidTx)
+        }
+    }
+
+    /// Blocks until an Echo Reply matching this socket's identifier
+    /// arrives, then returns its arrival time (for computing a round-trip
+    /// time against when the matching Echo Request was sent) and payload.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `take_recv` has already handed out the receiving end of
+    /// this socket's channel.
+    pub fn recv(&self) -> io::Result<(SystemTime, Box<[u8]>)> {
+        self.reader
+            .as_ref()
+            .expect("PingSocket: recv called after take_recv")
+            .recv()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Stack is gone"))
+    }
+
+    /// Like `recv`, but returns `io::ErrorKind::WouldBlock` instead of
+    /// blocking if no matching Echo Reply has arrived yet.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `take_recv` has already handed out the receiving end of
+    /// this socket's channel.
+    pub fn try_recv(&self) -> io::Result<(SystemTime, Box<[u8]>)> {
+        self.reader
+            .as_ref()
+            .expect("PingSocket: try_recv called after take_recv")
+            .try_recv()
+            .map_err(|e| match e {
+                TryRecvError::Empty => {
+                    io::Error::new(io::ErrorKind::WouldBlock, "No reply available yet")
+                }
+                TryRecvError::Disconnected => io::Error::new(io::ErrorKind::Other, "Stack is gone"),
+            })
+    }
+
+    /// Hands out the receiving end of the channel `IcmpRx` delivers this
+    /// socket's matched Echo Replies on, for callers who want to drive
+    /// their own event loop (e.g. a `mio`/`select`-based one) instead of
+    /// calling `recv`/`try_recv`. Returns `None` if already taken.
+    pub fn take_recv(&mut self) -> Option<Receiver<(SystemTime, Box<[u8]>)>> {
+        self.reader.take()
+    }
+}
@@ -0,0 +1,61 @@
+use {RxError, RxResult};
+use ipv4::Ipv4Listener;
+use stack::StackInterfaceMsg;
+
+use pnet::packet::Packet;
+use pnet::packet::ipv4::Ipv4Packet;
+
+use std::net::Ipv4Addr;
+use std::sync::mpsc::Sender;
+use std::time::SystemTime;
+
+use super::igmp_tx::IGMP_HEADER_LEN;
+
+const MEMBERSHIP_QUERY: u8 = 0x11;
+const MEMBERSHIP_REPORT_V1: u8 = 0x12;
+const MEMBERSHIP_REPORT_V2: u8 = 0x16;
+const LEAVE_GROUP: u8 = 0x17;
+
+/// Listener and parser for IGMP packets. Like `ArpRx`, this does not keep
+/// any registration table of its own, it merely parses enough of the
+/// packet to forward the event to the owning `StackInterfaceThread`, which
+/// is the one actually tracking group membership through its `IgmpTable`.
+pub struct IgmpRx {
+    stack_tx: Sender<StackInterfaceMsg>,
+}
+
+impl IgmpRx {
+    pub fn new(stack_tx: Sender<StackInterfaceMsg>) -> Self {
+        IgmpRx { stack_tx: stack_tx }
+    }
+}
+
+impl Ipv4Listener for IgmpRx {
+    fn recv(&mut self, _time: SystemTime, ip_pkg: Ipv4Packet) -> RxResult {
+        let payload = ip_pkg.payload();
+        if payload.len() < IGMP_HEADER_LEN {
+            return Err(RxError::InvalidLength);
+        }
+        let group = Ipv4Addr::new(payload[4], payload[5], payload[6], payload[7]);
+        match payload[0] {
+            MEMBERSHIP_QUERY => {
+                // Max Resp Time is in units of 1/10 second. A General
+                // Query (v1 compatibility) may carry 0 here, meaning the
+                // IGMPv2 default of 10 seconds applies instead.
+                let max_resp_time = if payload[1] == 0 { 100 } else { payload[1] };
+                self.stack_tx.send(StackInterfaceMsg::IgmpQuery(group, max_resp_time)).unwrap();
+                Ok(())
+            }
+            MEMBERSHIP_REPORT_V1 | MEMBERSHIP_REPORT_V2 => {
+                self.stack_tx.send(StackInterfaceMsg::IgmpReportSeen(group)).unwrap();
+                Ok(())
+            }
+            LEAVE_GROUP => {
+                // Only routers need to act on Leave Group, we have nothing
+                // listening for it.
+                Ok(())
+            }
+            _ => Err(RxError::InvalidContent),
+        }
+    }
+}
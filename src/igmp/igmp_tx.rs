@@ -0,0 +1,207 @@
+use {Payload, TxResult};
+use ipv4::{Ipv4Payload, Ipv4Tx};
+
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+
+use std::net::Ipv4Addr;
+
+/// The length in bytes of an IGMPv2 header: type, max response time,
+/// checksum and group address. IGMPv2 carries no payload past this.
+pub const IGMP_HEADER_LEN: usize = 8;
+
+/// The IGMPv2 message types this crate knows how to send. Membership
+/// Queries are only ever received, never sent by us, so there is no
+/// variant for them here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IgmpType {
+    /// Announces that we are a member of a group. Sent on join, and again
+    /// whenever `IgmpTable::due_reports` says a delayed response to a
+    /// Membership Query is due.
+    MembershipReportV2,
+    /// Announces that we are leaving a group, so routers on the link can
+    /// stop forwarding its traffic to us sooner than the group's
+    /// membership would otherwise time out.
+    LeaveGroup,
+}
+
+impl IgmpType {
+    fn value(&self) -> u8 {
+        match *self {
+            IgmpType::MembershipReportV2 => 0x16,
+            IgmpType::LeaveGroup => 0x17,
+        }
+    }
+}
+
+/// Igmp packet sender. Unlike `IcmpTx`/`UdpTx`, the destination address
+/// depends on what is being sent (the group itself for a report, the
+/// all-routers address for a leave) rather than being fixed for the
+/// lifetime of the socket, so callers construct the wrapped `Ipv4Tx` with
+/// whichever destination the message at hand requires, the same way
+/// `StackInterfaceThread::send_icmp_unreachable` builds a fresh `Ipv4Tx`
+/// per error reply.
+pub struct IgmpTx<T: Ipv4Tx> {
+    ipv4: T,
+}
+
+impl<T: Ipv4Tx> IgmpTx<T> {
+    /// Creates a new `IgmpTx` based on `ipv4`. `ipv4`'s destination must
+    /// already be set to wherever the message being sent belongs (the
+    /// group for a report, the all-routers address for a leave).
+    pub fn new(ipv4: T) -> Self {
+        IgmpTx { ipv4: ipv4 }
+    }
+
+    /// Sends an IGMPv2 Membership Report for `group`, announcing that we
+    /// are a member of it.
+    pub fn send_membership_report(&mut self, group: Ipv4Addr) -> TxResult {
+        let builder = IgmpBuilder::new(IgmpType::MembershipReportV2, group);
+        self.ipv4.send(builder)
+    }
+
+    /// Sends an IGMPv2 Leave Group for `group`, telling routers on the
+    /// link we are no longer interested in its traffic.
+    pub fn send_leave_group(&mut self, group: Ipv4Addr) -> TxResult {
+        let builder = IgmpBuilder::new(IgmpType::LeaveGroup, group);
+        self.ipv4.send(builder)
+    }
+}
+
+/// Builds an IGMPv2 packet of the given `igmp_type` for `group`. The Max
+/// Response Time field is always zero, since it is only meaningful on
+/// Membership Queries, which this crate never sends.
+pub struct IgmpBuilder {
+    igmp_type: IgmpType,
+    group: Ipv4Addr,
+}
+
+impl IgmpBuilder {
+    pub fn new(igmp_type: IgmpType, group: Ipv4Addr) -> Self {
+        IgmpBuilder {
+            igmp_type: igmp_type,
+            group: group,
+        }
+    }
+}
+
+impl Ipv4Payload for IgmpBuilder {
+    fn next_level_protocol(&self) -> IpNextHeaderProtocol {
+        IpNextHeaderProtocols::Igmp
+    }
+}
+
+impl Payload for IgmpBuilder {
+    fn len(&self) -> usize {
+        IGMP_HEADER_LEN
+    }
+
+    fn build(&mut self, buffer: &mut [u8]) {
+        buffer[0] = self.igmp_type.value();
+        buffer[1] = 0;
+        buffer[2] = 0;
+        buffer[3] = 0;
+        buffer[4..8].copy_from_slice(&self.group.octets());
+        let csum = checksum(&buffer[..IGMP_HEADER_LEN]);
+        buffer[2] = (csum >> 8) as u8;
+        buffer[3] = csum as u8;
+    }
+}
+
+/// Standard Internet checksum (RFC 1071): the one's complement of the
+/// one's complement sum of the packet's 16 bit words, with the checksum
+/// field itself assumed zero. Computed locally instead of through `pnet`
+/// since `pnet` has no dedicated Igmp packet type.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    for chunk in data.chunks(2) {
+        let word = if chunk.len() == 2 {
+            ((chunk[0] as u32) << 8) | chunk[1] as u32
+        } else {
+            (chunk[0] as u32) << 8
+        };
+        sum += word;
+    }
+    while (sum >> 16) != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !sum as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Payload, TxResult};
+    use ipv4::Ipv4Payload;
+
+    use pnet::packet::ip::IpNextHeaderProtocol;
+
+    use std::net::Ipv4Addr;
+    use std::sync::mpsc::{self, Sender, Receiver};
+
+    struct MockIpv4Tx {
+        tx: Sender<(IpNextHeaderProtocol, Box<[u8]>)>,
+    }
+
+    impl MockIpv4Tx {
+        fn new() -> (MockIpv4Tx, Receiver<(IpNextHeaderProtocol, Box<[u8]>)>) {
+            let (tx, rx) = mpsc::channel();
+            (MockIpv4Tx { tx: tx }, rx)
+        }
+    }
+
+    impl Ipv4Tx for MockIpv4Tx {
+        fn src(&self) -> Ipv4Addr {
+            Ipv4Addr::new(0, 0, 0, 0)
+        }
+
+        fn dst(&self) -> Ipv4Addr {
+            Ipv4Addr::new(0, 0, 0, 0)
+        }
+
+        fn send<P: Ipv4Payload>(&mut self, mut payload: P) -> TxResult {
+            let mut buffer = vec![0; payload.len()];
+            payload.build(&mut buffer);
+            self.tx.send((payload.next_level_protocol(), buffer.into_boxed_slice())).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn send_membership_report_uses_igmp_protocol_and_embeds_group() {
+        let (ipv4, rx) = MockIpv4Tx::new();
+        let mut testee = IgmpTx::new(ipv4);
+        let group = Ipv4Addr::new(224, 1, 2, 3);
+        testee.send_membership_report(group).unwrap();
+
+        let (next_level_protocol, data) = rx.try_recv().unwrap();
+        assert_eq!(::pnet::packet::ip::IpNextHeaderProtocols::Igmp, next_level_protocol);
+        assert_eq!(0x16, data[0]);
+        assert_eq!([224, 1, 2, 3], data[4..8]);
+    }
+
+    #[test]
+    fn send_leave_group_uses_leave_type() {
+        let (ipv4, rx) = MockIpv4Tx::new();
+        let mut testee = IgmpTx::new(ipv4);
+        testee.send_leave_group(Ipv4Addr::new(224, 1, 2, 3)).unwrap();
+
+        let (_, data) = rx.try_recv().unwrap();
+        assert_eq!(0x17, data[0]);
+    }
+
+    #[test]
+    fn checksum_folds_to_all_ones() {
+        let mut testee = IgmpBuilder::new(IgmpType::MembershipReportV2, Ipv4Addr::new(224, 0, 0, 5));
+        let mut buffer = vec![0u8; IGMP_HEADER_LEN];
+        testee.build(&mut buffer);
+
+        let mut sum = 0u32;
+        for chunk in buffer.chunks(2) {
+            sum += ((chunk[0] as u32) << 8) | chunk[1] as u32;
+        }
+        while (sum >> 16) != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        assert_eq!(0xffff, sum);
+    }
+}
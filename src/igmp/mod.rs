@@ -0,0 +1,288 @@
+//! IGMPv2 (RFC 2236) group membership: joining/leaving multicast groups and
+//! answering Membership Queries from routers on the link.
+//!
+//! Like `arp::ArpTable`, `IgmpTable` only tracks state; it does not own a
+//! thread or timer of its own. Callers must periodically call
+//! `IgmpTable::due_reports` (the same externally-driven convention as
+//! `ArpTable::flush_expired`) so scheduled reports actually get sent.
+
+use arp::{Clock, SystemClock};
+
+use pnet::util::MacAddr;
+
+use rand;
+use rand::distributions::{IndependentSample, Range};
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod igmp_rx;
+mod igmp_tx;
+
+pub use self::igmp_rx::IgmpRx;
+pub use self::igmp_tx::{IGMP_HEADER_LEN, IgmpBuilder, IgmpTx, IgmpType};
+
+/// The address every multicast listener implicitly joins, used by routers
+/// to send General Queries.
+pub fn all_hosts() -> Ipv4Addr {
+    Ipv4Addr::new(224, 0, 0, 1)
+}
+
+/// The address Leave Group messages are sent to, so any router on the link
+/// hears them regardless of which group is being left.
+pub fn all_routers() -> Ipv4Addr {
+    Ipv4Addr::new(224, 0, 0, 2)
+}
+
+/// Maps a multicast `group` address to the Ethernet MAC frames for it are
+/// sent/received on, per RFC 1112: `01:00:5e` followed by the low 23 bits
+/// of the group address.
+pub fn multicast_mac(group: Ipv4Addr) -> MacAddr {
+    let octets = group.octets();
+    MacAddr::new(0x01, 0x00, 0x5e, octets[1] & 0x7f, octets[2], octets[3])
+}
+
+/// The IP TTL every IGMP message must be sent with, per RFC 2236 section 2:
+/// routers must not forward IGMP traffic, so it never needs to travel past
+/// the first hop.
+pub const IGMP_TTL: u8 = 1;
+
+/// The Router Alert IPv4 option (RFC 2113), set on every IGMP message so
+/// routers along the path intercept it even though it is addressed to a
+/// multicast group rather than to them directly.
+pub const ROUTER_ALERT_OPTION: [u8; 4] = [0x94, 0x04, 0x00, 0x00];
+
+/// Whether we currently have an unanswered Membership Query for a group, or
+/// are simply an idle member of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GroupState {
+    /// Member of the group, no Membership Query pending a response.
+    Idle,
+    /// A Membership Query was seen for this group and no other member's
+    /// report has suppressed ours yet. `due_at` is when our own randomized
+    /// response delay, chosen per RFC 2236 section 3, elapses.
+    DelayingMember { due_at: Instant },
+}
+
+/// Tracks which multicast groups we are a member of, and when a delayed
+/// Membership Report for one of them is due. Time is read from a pluggable
+/// `Clock`, the same abstraction `ArpTable` and `MacTable` use, so tests can
+/// drive the randomized response delay deterministically.
+pub struct IgmpTable {
+    groups: HashMap<Ipv4Addr, GroupState>,
+    clock: Arc<Clock>,
+}
+
+impl IgmpTable {
+    /// Creates a new, empty `IgmpTable` reading time from the real OS clock.
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Creates a new, empty `IgmpTable` using the given `clock` as its time
+    /// source. Mainly useful for tests that want to control the passage of
+    /// time.
+    pub fn with_clock(clock: Arc<Clock>) -> Self {
+        IgmpTable {
+            groups: HashMap::new(),
+            clock: clock,
+        }
+    }
+
+    /// Records that we just joined `group`. Does not by itself send
+    /// anything, callers are expected to also send an unsolicited
+    /// Membership Report through `IgmpTx`.
+    pub fn join(&mut self, group: Ipv4Addr) {
+        self.groups.entry(group).or_insert(GroupState::Idle);
+    }
+
+    /// Forgets `group`. Returns `true` if we were a member of it.
+    pub fn leave(&mut self, group: Ipv4Addr) -> bool {
+        self.groups.remove(&group).is_some()
+    }
+
+    /// Returns whether we currently consider ourselves a member of `group`.
+    pub fn is_member(&self, group: &Ipv4Addr) -> bool {
+        self.groups.contains_key(group)
+    }
+
+    /// Every group we are currently a member of.
+    pub fn groups(&self) -> Vec<Ipv4Addr> {
+        self.groups.keys().cloned().collect()
+    }
+
+    /// Handles an incoming Membership Query for `group` with the given
+    /// `max_resp_time_tenths` (in units of 1/10 second, as carried on the
+    /// wire). `group` being `0.0.0.0` means a General Query covering every
+    /// group we are a member of, otherwise it is a Group-Specific Query for
+    /// just that one. A randomized delay is picked for each affected group,
+    /// per RFC 2236 section 3; a group that already has a report pending
+    /// keeps whichever of the two due times is sooner.
+    pub fn query_received(&mut self, group: Ipv4Addr, max_resp_time_tenths: u8) {
+        let now = self.clock.now();
+        let max_delay = Duration::from_millis(max_resp_time_tenths as u64 * 100);
+        if group == Ipv4Addr::new(0, 0, 0, 0) {
+            let targets: Vec<Ipv4Addr> = self.groups.keys().cloned().collect();
+            for g in targets {
+                self.schedule_report(g, now, max_delay);
+            }
+        } else if self.groups.contains_key(&group) {
+            self.schedule_report(group, now, max_delay);
+        }
+    }
+
+    fn schedule_report(&mut self, group: Ipv4Addr, now: Instant, max_delay: Duration) {
+        let due_at = now + random_delay(max_delay);
+        let state = self.groups.entry(group).or_insert(GroupState::Idle);
+        let should_reschedule = match *state {
+            GroupState::Idle => true,
+            GroupState::DelayingMember { due_at: current } => due_at < current,
+        };
+        if should_reschedule {
+            *state = GroupState::DelayingMember { due_at: due_at };
+        }
+    }
+
+    /// Handles an incoming Membership Report for `group` seen from another
+    /// host. Suppresses our own pending report for it, if any, since RFC
+    /// 2236 only requires one member to answer a query per group.
+    pub fn report_seen(&mut self, group: Ipv4Addr) {
+        if let Some(state) = self.groups.get_mut(&group) {
+            *state = GroupState::Idle;
+        }
+    }
+
+    /// Returns every group whose delayed report is now due, resetting them
+    /// to `Idle`. Must be called periodically, the same way
+    /// `ArpTable::flush_expired` must, for scheduled reports to actually
+    /// get sent.
+    pub fn due_reports(&mut self) -> Vec<Ipv4Addr> {
+        let now = self.clock.now();
+        let mut due = Vec::new();
+        for (group, state) in self.groups.iter_mut() {
+            if let GroupState::DelayingMember { due_at } = *state {
+                if due_at <= now {
+                    due.push(*group);
+                    *state = GroupState::Idle;
+                }
+            }
+        }
+        due
+    }
+}
+
+impl Default for IgmpTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Picks a uniformly random duration in `[0, max)`, per RFC 2236 section 3's
+/// requirement that a Membership Report be delayed by a random amount of
+/// time no greater than the Max Resp Time of the Query that triggered it.
+fn random_delay(max: Duration) -> Duration {
+    let max_millis = max.as_secs() * 1000 + (max.subsec_nanos() / 1_000_000) as u64;
+    if max_millis == 0 {
+        Duration::new(0, 0)
+    } else {
+        let range = Range::new(0, max_millis);
+        let millis = range.ind_sample(&mut rand::thread_rng());
+        Duration::from_millis(millis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    struct FakeClock {
+        now: Cell<Instant>,
+    }
+
+    impl FakeClock {
+        fn new() -> Arc<FakeClock> {
+            Arc::new(FakeClock { now: Cell::new(Instant::now()) })
+        }
+
+        fn advance(&self, by: Duration) {
+            self.now.set(self.now.get() + by);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.now.get()
+        }
+    }
+
+    fn group(n: u8) -> Ipv4Addr {
+        Ipv4Addr::new(224, 0, 0, n)
+    }
+
+    #[test]
+    fn multicast_mac_masks_high_bit_of_second_octet() {
+        let mac = multicast_mac(Ipv4Addr::new(230, 129, 2, 3));
+        assert_eq!(MacAddr::new(0x01, 0x00, 0x5e, 0x01, 0x02, 0x03), mac);
+    }
+
+    #[test]
+    fn due_reports_is_empty_until_a_query_is_answered() {
+        let clock = FakeClock::new();
+        let mut table = IgmpTable::with_clock(clock.clone());
+        table.join(group(1));
+
+        assert!(table.due_reports().is_empty());
+
+        table.query_received(group(1), 100);
+        clock.advance(Duration::new(11, 0));
+
+        assert_eq!(vec![group(1)], table.due_reports());
+        // Once collected, it shouldn't be returned again.
+        assert!(table.due_reports().is_empty());
+    }
+
+    #[test]
+    fn general_query_schedules_every_joined_group() {
+        let clock = FakeClock::new();
+        let mut table = IgmpTable::with_clock(clock.clone());
+        table.join(group(1));
+        table.join(group(2));
+
+        table.query_received(Ipv4Addr::new(0, 0, 0, 0), 100);
+        clock.advance(Duration::new(11, 0));
+
+        let mut due = table.due_reports();
+        due.sort();
+        assert_eq!(vec![group(1), group(2)], due);
+    }
+
+    #[test]
+    fn report_seen_suppresses_pending_report() {
+        let clock = FakeClock::new();
+        let mut table = IgmpTable::with_clock(clock.clone());
+        table.join(group(1));
+        table.query_received(group(1), 100);
+
+        table.report_seen(group(1));
+        clock.advance(Duration::new(11, 0));
+
+        assert!(table.due_reports().is_empty());
+    }
+
+    #[test]
+    fn leave_forgets_the_group() {
+        let mut table = IgmpTable::new();
+        table.join(group(1));
+        assert!(table.is_member(&group(1)));
+
+        assert!(table.leave(group(1)));
+        assert!(!table.is_member(&group(1)));
+        assert!(!table.leave(group(1)));
+    }
+}
@@ -1,20 +1,24 @@
 use std::io;
+use std::os::unix::io::RawFd;
 
 use pnet::datalink::{Config, Channel, EthernetDataLinkSender, EthernetDataLinkReceiver};
 use pnet::util::NetworkInterface;
 
-use ethernet::EthernetProvider;
+use ethernet::{BpfInstruction, EthernetProvider};
 
-/// Used internally to use `libpnet` as the datalink layer provider
+/// `EthernetProvider` backed by `libpnet`'s datalink layer, i.e. a real
+/// network adapter. This is the default provider used by `default_stack`.
 pub struct PnetEthernetProvider;
 
 impl EthernetProvider for PnetEthernetProvider {
     fn channel(&mut self,
                iface: &NetworkInterface,
-               config: &Config)
+               config: &Config,
+               socket_fd: Option<RawFd>,
+               filter: Option<&[BpfInstruction]>)
                -> io::Result<(Box<EthernetDataLinkSender>, Box<EthernetDataLinkReceiver>)> {
         use pnet::datalink::channel;
-        match channel(iface, config) {
+        match channel(iface, config, socket_fd, filter) {
             Ok(Channel::Ethernet(sender, receiver)) => Ok((sender, receiver)),
             Ok(_) => Err(io::Error::new(io::ErrorKind::Other, "Invalid channel type")),
             Err(e) => {
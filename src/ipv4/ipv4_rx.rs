@@ -1,5 +1,10 @@
 use {RxError, RxResult};
+use checksum::ChecksumCapabilities;
 use ethernet::EthernetListener;
+use icmp::IcmpError;
+use stack::StackInterfaceMsg;
+
+use arc_swap::ArcSwap;
 
 use pnet::packet::Packet;
 use pnet::packet::ethernet::{EtherType, EtherTypes, EthernetPacket};
@@ -9,10 +14,46 @@ use pnet::packet::ipv4::{Ipv4Packet, MutableIpv4Packet, checksum};
 use std::collections::HashMap;
 use std::net::Ipv4Addr;
 use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, SystemTime};
 
 use super::{MORE_FRAGMENTS, NO_FLAGS};
-use util::Buffer;
+use util::{Buffer, CacheMap};
+
+/// How long an incomplete, in-progress reassembly is kept around waiting
+/// for its remaining fragments before being dropped.
+const FRAGMENT_TIMEOUT_SECS: u64 = 30;
+
+/// How many datagrams can be mid-reassembly at once. Bounds the memory a
+/// fragment flood can make us hold, evicting the one closest to timing out
+/// to make room for a new one once full.
+const MAX_CONCURRENT_REASSEMBLIES: usize = 64;
+
+/// Tunable limits for `Ipv4Rx`'s fragment reassembly, grouped into one
+/// struct the same way `ChecksumCapabilities` groups several independent
+/// offload flags, rather than growing the constructor's parameter list
+/// further.
+#[derive(Debug, Clone, Copy)]
+pub struct ReassemblyConfig {
+    /// How long an incomplete reassembly is kept around waiting for its
+    /// remaining fragments, reset whenever its first fragment arrives,
+    /// before being dropped.
+    pub timeout: Duration,
+    /// How many datagrams can be mid-reassembly at once. Bounds the memory
+    /// a fragment flood can make us hold (each buffer tops out at a full
+    /// 64 KiB datagram) by evicting the one closest to timing out to make
+    /// room for a new one once full.
+    pub max_concurrent: usize,
+}
+
+impl Default for ReassemblyConfig {
+    fn default() -> Self {
+        ReassemblyConfig {
+            timeout: Duration::new(FRAGMENT_TIMEOUT_SECS, 0),
+            max_concurrent: MAX_CONCURRENT_REASSEMBLIES,
+        }
+    }
+}
 
 /// Anyone interested in receiving IPv4 packets from `Ipv4` must implement this.
 pub trait Ipv4Listener: Send {
@@ -20,36 +61,146 @@ pub trait Ipv4Listener: Send {
     fn recv(&mut self, time: SystemTime, packet: Ipv4Packet) -> RxResult;
 }
 
-/// Type binding for how the listeners in `Ipv4Rx` are structured.
-pub type IpListenerLookup = HashMap<Ipv4Addr, HashMap<IpNextHeaderProtocol, Box<Ipv4Listener>>>;
+/// Type binding for how the listeners in `Ipv4Rx` are structured. Each
+/// listener is individually wrapped in its own `Mutex` so that `forward` can
+/// dispatch via a lock-free `ArcSwap::load` and only ever has to take a fine
+/// grained, almost never contended, per-listener lock.
+pub type IpListenerLookup = HashMap<Ipv4Addr, HashMap<IpNextHeaderProtocol, Arc<Mutex<Box<Ipv4Listener>>>>>;
+
+/// Type binding for the list of promiscuous listeners in `Ipv4Rx`, handed a
+/// clone of every valid datagram regardless of destination address or
+/// protocol. Unlike `IpListenerLookup` these aren't keyed on anything, since
+/// there is nothing left to demux on once a listener has asked for
+/// everything.
+pub type RawIpv4ListenerLookup = Vec<Arc<Mutex<Box<Ipv4Listener>>>>;
 
 // Header fields that are used to identify fragments as belonging to the same
 // packet
-type FragmentIdent = (Ipv4Addr, Ipv4Addr, u16);
+type FragmentIdent = (Ipv4Addr, Ipv4Addr, u16, IpNextHeaderProtocol);
 
 /// Listener and parser for IPv4 packets. Receives ethernet frames from the
 /// `EthernetRx` it's owned by and forwards them to the correct `Ipv4Listener`.
 /// Will cache and reassemble fragmented packets before forwarding them.
 pub struct Ipv4Rx {
-    listeners: Arc<Mutex<IpListenerLookup>>,
-    buffers: HashMap<FragmentIdent, (Buffer, usize)>,
+    listeners: Arc<ArcSwap<IpListenerLookup>>,
+    raw_listeners: Arc<ArcSwap<RawIpv4ListenerLookup>>,
+    buffers: CacheMap<FragmentIdent, (Buffer, usize)>,
+    stack_tx: Sender<StackInterfaceMsg>,
+    checksums: ChecksumCapabilities,
 }
 
 impl Ipv4Rx {
-    /// Creates a new `Ipv4Rx` with the given listeners. Listeners can't be
+    /// Creates a new `Ipv4Rx` with the given listeners, verifying every
+    /// incoming packet's header checksum in software. The caller keeps its
+    /// own clone of the `listeners` `Arc`, so listeners can be
     /// changed later. Returns the instance casted for easy addition to
-    /// the `EthernetRx` listener `Vec`.
-    pub fn new(listeners: Arc<Mutex<IpListenerLookup>>) -> Box<EthernetListener> {
+    /// the `EthernetRx` listener `Vec`. `stack_tx` is used to report
+    /// datagrams that reached us but had no listener, so the owning
+    /// `StackInterfaceThread` can reply with an Icmp error.
+    pub fn new(listeners: Arc<ArcSwap<IpListenerLookup>>,
+               stack_tx: Sender<StackInterfaceMsg>)
+               -> Box<EthernetListener> {
+        Self::with_checksums(listeners, stack_tx, ChecksumCapabilities::default())
+    }
+
+    /// Creates a new `Ipv4Rx`, skipping header checksum verification
+    /// according to `checksums` when the underlying NIC already did it in
+    /// hardware. Incomplete reassemblies are kept around for the default
+    /// `FRAGMENT_TIMEOUT_SECS`; use `with_checksums_and_reassembly_timeout`
+    /// to override that.
+    pub fn with_checksums(listeners: Arc<ArcSwap<IpListenerLookup>>,
+                           stack_tx: Sender<StackInterfaceMsg>,
+                           checksums: ChecksumCapabilities)
+                           -> Box<EthernetListener> {
+        let timeout = Duration::new(FRAGMENT_TIMEOUT_SECS, 0);
+        Self::with_checksums_and_reassembly_timeout(listeners, stack_tx, checksums, timeout)
+    }
+
+    /// Creates a new `Ipv4Rx` like `with_checksums`, but overriding how
+    /// long an incomplete reassembly is kept around waiting for its
+    /// remaining fragments, reset whenever its first fragment arrives,
+    /// before being dropped and, if the datagram's header was already
+    /// known (its zero-offset fragment had arrived), answered with an
+    /// Icmp Time Exceeded (reassembly timeout) per RFC 792.
+    pub fn with_checksums_and_reassembly_timeout(listeners: Arc<ArcSwap<IpListenerLookup>>,
+                                                  stack_tx: Sender<StackInterfaceMsg>,
+                                                  checksums: ChecksumCapabilities,
+                                                  reassembly_timeout: Duration)
+                                                  -> Box<EthernetListener> {
+        let config = ReassemblyConfig { timeout: reassembly_timeout, ..ReassemblyConfig::default() };
+        Self::with_reassembly_config(listeners, stack_tx, checksums, config)
+    }
+
+    /// Creates a new `Ipv4Rx` like `with_checksums`, but overriding both the
+    /// reassembly timeout and the cap on concurrent in-progress reassemblies
+    /// via `config`, for embedded callers that need to tune the memory a
+    /// fragment flood can make us hold. No raw listeners are registered; use
+    /// `with_reassembly_config_and_raw_listeners` to start with some.
+    pub fn with_reassembly_config(listeners: Arc<ArcSwap<IpListenerLookup>>,
+                                   stack_tx: Sender<StackInterfaceMsg>,
+                                   checksums: ChecksumCapabilities,
+                                   config: ReassemblyConfig)
+                                   -> Box<EthernetListener> {
+        let raw_listeners = Arc::new(ArcSwap::new(Arc::new(Vec::new())));
+        Self::with_reassembly_config_and_raw_listeners(listeners, raw_listeners, stack_tx, checksums, config)
+    }
+
+    /// Creates a new `Ipv4Rx` like `with_reassembly_config`, additionally
+    /// taking `raw_listeners`: a promiscuous listener list, handed a clone of
+    /// every valid, fully reassembled datagram before the normal per-address,
+    /// per-protocol `forward` demux runs, regardless of whether it matches
+    /// anything registered there. The caller keeps its own clone of the
+    /// `Arc`, the same way it does for `listeners`, so raw listeners can be
+    /// registered or removed at runtime with `add_raw_listener`/
+    /// `remove_raw_listener`. Useful for sniffers, firewalls, and protocols
+    /// this crate doesn't natively parse.
+    pub fn with_reassembly_config_and_raw_listeners(listeners: Arc<ArcSwap<IpListenerLookup>>,
+                                                     raw_listeners: Arc<ArcSwap<RawIpv4ListenerLookup>>,
+                                                     stack_tx: Sender<StackInterfaceMsg>,
+                                                     checksums: ChecksumCapabilities,
+                                                     config: ReassemblyConfig)
+                                                     -> Box<EthernetListener> {
         let this = Ipv4Rx {
             listeners: listeners,
-            buffers: HashMap::new(),
+            raw_listeners: raw_listeners,
+            buffers: CacheMap::with_capacity(config.max_concurrent, config.timeout),
+            stack_tx: stack_tx,
+            checksums: checksums,
         };
         Box::new(this) as Box<EthernetListener>
     }
 
+    /// Registers `listener` as a raw listener, on top of whatever is already
+    /// registered, handing it a clone of every valid, fully reassembled
+    /// `Ipv4Packet` this `Ipv4Rx` sees regardless of destination address or
+    /// protocol. Returns the `Arc` wrapping it so it can later be handed to
+    /// `remove_raw_listener`. Mirrors `EthernetRx::add_listener`.
+    pub fn add_raw_listener(raw_listeners: &Arc<ArcSwap<RawIpv4ListenerLookup>>,
+                             listener: Box<Ipv4Listener>)
+                             -> Arc<Mutex<Box<Ipv4Listener>>> {
+        let listener = Arc::new(Mutex::new(listener));
+        raw_listeners.rcu(|current| {
+            let mut new_listeners = (**current).clone();
+            new_listeners.push(listener.clone());
+            new_listeners
+        });
+        listener
+    }
+
+    /// Unregisters `listener`, previously returned by `add_raw_listener`. A
+    /// no-op if it is not (or no longer) registered.
+    pub fn remove_raw_listener(raw_listeners: &Arc<ArcSwap<RawIpv4ListenerLookup>>,
+                                listener: &Arc<Mutex<Box<Ipv4Listener>>>) {
+        raw_listeners.rcu(|current| {
+            let mut new_listeners = (**current).clone();
+            new_listeners.retain(|candidate| !Arc::ptr_eq(candidate, listener));
+            new_listeners
+        });
+    }
+
     /// Returns the Ipv4Packet contained in this EthernetPacket if it looks
     /// valid
-    fn get_ipv4_pkg<'a>(eth_pkg: &'a EthernetPacket) -> Result<Ipv4Packet<'a>, RxError> {
+    fn get_ipv4_pkg<'a>(&self, eth_pkg: &'a EthernetPacket) -> Result<Ipv4Packet<'a>, RxError> {
         let eth_payload = eth_pkg.payload();
         if eth_payload.len() < Ipv4Packet::minimum_packet_size() {
             return Err(RxError::InvalidLength);
@@ -62,7 +213,7 @@ impl Ipv4Rx {
             Err(RxError::InvalidLength)
         } else {
             let ip_pkg = Ipv4Packet::new(&eth_payload[..total_length]).unwrap();
-            if ip_pkg.get_checksum() != checksum(&ip_pkg) {
+            if self.checksums.ipv4.rx() && ip_pkg.get_checksum() != checksum(&ip_pkg) {
                 Err(RxError::InvalidChecksum)
             } else {
                 Ok(ip_pkg)
@@ -76,90 +227,182 @@ impl Ipv4Rx {
         mf || offset
     }
 
-    /// Saves a packet fragment to a buffer for reassembly. If the Ipv4Packet
-    /// becomes complete with the addition of `ip_pkg` then the complete
-    /// reassembled packet is returned in a Buffer.
+    /// Saves a packet fragment to a buffer for reassembly. Fragments may
+    /// arrive in any order; the hole between the first fragment's header
+    /// and the rest of the datagram is simply another gap that `Buffer`
+    /// fills in as later fragments arrive. If the Ipv4Packet becomes
+    /// complete with the addition of `ip_pkg` then the complete
+    /// reassembled packet is returned.
     fn save_fragment(&mut self,
                      ip_pkg: Ipv4Packet)
                      -> Result<Option<Ipv4Packet<'static>>, RxError> {
         let ident = Self::get_fragment_identification(&ip_pkg);
         if !self.buffers.contains_key(&ident) {
-            try!(self.start_new_fragment(ip_pkg, ident));
-            Ok(None)
-        } else {
-            let pkg_done = {
-                let &mut (ref mut buffer, ref mut total_length) =
-                    self.buffers.get_mut(&ident).unwrap();
-                let offset = Ipv4Packet::minimum_packet_size() +
-                             ip_pkg.get_fragment_offset() as usize * 8;
-                // Check if this is the last fragment
-                if (ip_pkg.get_flags() & MORE_FRAGMENTS) == 0 {
-                    if *total_length != 0 {
-                        return Err(RxError::InvalidContent);
-                    } else {
-                        *total_length = offset + ip_pkg.payload().len();
-                    }
+            self.buffers.insert(ident, (Buffer::new(::std::u16::MAX as usize), 0));
+        }
+        let pkg_done = {
+            let &mut (ref mut buffer, ref mut total_length) = self.buffers.get_mut(&ident).unwrap();
+            let offset = Ipv4Packet::minimum_packet_size() +
+                         ip_pkg.get_fragment_offset() as usize * 8;
+            // The first fragment also carries the header every other
+            // fragment lacks.
+            if ip_pkg.get_fragment_offset() == 0 {
+                if buffer.push(0, ip_pkg.packet()).is_err() {
+                    return Err(RxError::InvalidContent);
                 }
-                match buffer.push(offset, ip_pkg.payload()) {
-                    Ok(i) => i == *total_length,
-                    Err(_) => {
-                        return Err(RxError::InvalidContent);
-                    }
+            } else if buffer.push(offset, ip_pkg.payload()).is_err() {
+                return Err(RxError::InvalidContent);
+            }
+            // Check if this is the last fragment
+            if (ip_pkg.get_flags() & MORE_FRAGMENTS) == 0 {
+                if *total_length != 0 {
+                    return Err(RxError::InvalidContent);
+                } else {
+                    *total_length = offset + ip_pkg.payload().len();
                 }
-            };
-            if pkg_done {
-                let (buffer, len) = self.buffers.remove(&ident).unwrap();
-                let mut ip_pkg = MutableIpv4Packet::owned(buffer.into_vec()).unwrap();
-                ip_pkg.set_flags(NO_FLAGS);
-                ip_pkg.set_total_length(len as u16);
-                let csum = checksum(&ip_pkg.to_immutable());
-                ip_pkg.set_checksum(csum);
-                Ok(Some(ip_pkg.consume_to_immutable()))
-            } else {
-                Ok(None)
             }
+            *total_length != 0 && buffer.is_complete(*total_length)
+        };
+        if pkg_done {
+            let (buffer, len) = self.buffers.remove(&ident).unwrap();
+            let mut ip_pkg = MutableIpv4Packet::owned(buffer.into_vec()).unwrap();
+            ip_pkg.set_flags(NO_FLAGS);
+            ip_pkg.set_total_length(len as u16);
+            let csum = checksum(&ip_pkg.to_immutable());
+            ip_pkg.set_checksum(csum);
+            Ok(Some(ip_pkg.consume_to_immutable()))
+        } else {
+            Ok(None)
         }
     }
 
-    fn start_new_fragment(&mut self, ip_pkg: Ipv4Packet, ident: FragmentIdent) -> RxResult {
-        if ip_pkg.get_fragment_offset() == 0 {
-            let mut buffer = Buffer::new(::std::u16::MAX as usize);
-            buffer.push(0, ip_pkg.packet()).unwrap();
-            self.buffers.insert(ident, (buffer, 0));
-            Ok(())
-        } else {
-            Err(RxError::InvalidContent)
+    /// Drops every reassembly whose timeout elapsed before it completed.
+    /// For any of them whose zero-offset fragment had already arrived (so
+    /// we actually have a header to embed), reports it to the owning
+    /// `StackInterfaceThread` so it can reply with an Icmp Time Exceeded
+    /// (reassembly timeout), the same way the no-listener path reports a
+    /// Destination Unreachable.
+    fn expire_reassemblies(&mut self) {
+        for (_, (buffer, _)) in self.buffers.take_expired() {
+            if let Some(ip_pkg) = Ipv4Packet::new(&buffer) {
+                self.report_reassembly_timeout(&ip_pkg);
+            }
         }
     }
 
+    /// Tells the owning `StackInterfaceThread` that a fragmented datagram's
+    /// remaining fragments never arrived before the reassembly timeout
+    /// elapsed, so it can reply with an Icmp Time Exceeded (reassembly
+    /// timeout) if error replies are enabled.
+    fn report_reassembly_timeout(&self, ip_pkg: &Ipv4Packet) {
+        let error = IcmpError::reassembly_timeout();
+        let src = ip_pkg.get_destination();
+        let dst = ip_pkg.get_source();
+        let msg = StackInterfaceMsg::IcmpUnreachable(error, src, dst, ip_pkg.packet().to_vec());
+        self.stack_tx.send(msg).unwrap_or(());
+    }
+
     fn get_fragment_identification(ip_pkg: &Ipv4Packet) -> FragmentIdent {
         let src = ip_pkg.get_source();
         let dst = ip_pkg.get_destination();
         let ident = ip_pkg.get_identification();
-        (src, dst, ident)
+        let protocol = ip_pkg.get_next_level_protocol();
+        (src, dst, ident, protocol)
     }
 
-    /// Forwards a complete packet to its listener
+    /// Forwards a complete packet to its listener. No special-casing is
+    /// needed for multicast: `StackInterface::join_multicast_group` aliases
+    /// a joined group's entry in the same listener table `dest_ip` is
+    /// looked up in here, onto whichever listeners are already registered
+    /// on the joining `local_ip`.
+    ///
+    /// `EthernetListener::recv` only ever calls this with a datagram that
+    /// already has a fragment offset of zero: either it arrived unfragmented
+    /// to begin with, or `save_fragment` already reassembled it. So the Icmp
+    /// errors `report_ttl_exceeded`/`report_protocol_unreachable` send from
+    /// in here can never be mistaken replies to a lone, non-initial
+    /// fragment, per RFC 792's requirement that an Icmp error is never sent
+    /// about anything but a fragment's zero offset.
+    ///
+    /// Before that normal demux runs, a clone of `ip_pkg` is handed to every
+    /// registered raw listener, regardless of `dest_ip` or
+    /// `next_level_protocol`. A raw listener declining a packet with
+    /// `RxError::NoListener` isn't fatal, it just means that particular
+    /// listener wasn't interested; any other error, recoverable or not, is
+    /// logged and otherwise ignored. If a raw listener does accept the packet but nothing is
+    /// registered for it in the normal demux, the packet still counts as
+    /// handled rather than producing `NoListener`.
     fn forward(&self, time: SystemTime, ip_pkg: Ipv4Packet) -> RxResult {
         let dest_ip = ip_pkg.get_destination();
         let next_level_protocol = ip_pkg.get_next_level_protocol();
         trace!("Ipv4 got a packet to {}!", dest_ip);
-        let mut listeners = self.listeners.lock().unwrap();
-        if let Some(mut listeners) = listeners.get_mut(&dest_ip) {
-            if let Some(mut listener) = listeners.get_mut(&next_level_protocol) {
-                listener.recv(time, ip_pkg)
+        if ip_pkg.get_ttl() == 0 {
+            self.report_ttl_exceeded(&ip_pkg);
+            return Err(RxError::InvalidContent);
+        }
+        let mut consumed_by_raw_listener = false;
+        for raw_listener in self.raw_listeners.load().iter() {
+            let raw_pkg = Ipv4Packet::new(ip_pkg.packet()).unwrap();
+            match raw_listener.lock().unwrap().recv(time, raw_pkg) {
+                Ok(()) => consumed_by_raw_listener = true,
+                Err(RxError::NoListener(_)) => {}
+                Err(e) => warn!("Ipv4: raw listener failed: {:?}", e),
+            }
+        }
+        let listeners = self.listeners.load();
+        if let Some(listeners) = listeners.get(&dest_ip) {
+            if let Some(listener) = listeners.get(&next_level_protocol) {
+                listener.lock().unwrap().recv(time, ip_pkg)
+            } else if consumed_by_raw_listener {
+                Ok(())
             } else {
+                self.report_protocol_unreachable(&ip_pkg);
                 Err(RxError::NoListener(format!("Ipv4 {:?}", next_level_protocol)))
             }
+        } else if consumed_by_raw_listener {
+            Ok(())
         } else {
             Err(RxError::NoListener(format!("Ipv4 {}", dest_ip)))
         }
     }
+
+    /// Tells the owning `StackInterfaceThread` that `ip_pkg` arrived with a
+    /// Ttl of zero, so it can reply with an Icmp Time Exceeded (Ttl
+    /// Exceeded) if error replies are enabled. Per RFC 791 section 3.2 a
+    /// datagram must never be processed once its Ttl reaches zero, the
+    /// same validity check `get_ipv4_pkg` already applies to the header
+    /// checksum.
+    fn report_ttl_exceeded(&self, ip_pkg: &Ipv4Packet) {
+        let error = IcmpError::ttl_exceeded();
+        let src = ip_pkg.get_destination();
+        let dst = ip_pkg.get_source();
+        let msg = StackInterfaceMsg::IcmpUnreachable(error, src, dst, ip_pkg.packet().to_vec());
+        self.stack_tx.send(msg).unwrap_or(());
+    }
+
+    /// Tells the owning `StackInterfaceThread` that `ip_pkg` was addressed
+    /// to us but had no listener registered for its next level protocol, so
+    /// it can reply with an Icmp Destination Unreachable (Protocol
+    /// Unreachable) if error replies are enabled. `udp::UdpRx` sends the
+    /// analogous `IcmpError::port_unreachable` itself once it knows no
+    /// socket is bound to the destination port, since that is a property of
+    /// the Udp listener table rather than something `forward` can see.
+    /// Either way `send_icmp_unreachable` is the one that actually embeds
+    /// `ip_pkg`'s header plus its first 8 bytes per RFC 792 and applies the
+    /// broadcast/multicast/Icmp-to-Icmp gating.
+    fn report_protocol_unreachable(&self, ip_pkg: &Ipv4Packet) {
+        let error = IcmpError::protocol_unreachable();
+        let src = ip_pkg.get_destination();
+        let dst = ip_pkg.get_source();
+        let msg = StackInterfaceMsg::IcmpUnreachable(error, src, dst, ip_pkg.packet().to_vec());
+        self.stack_tx.send(msg).unwrap_or(());
+    }
 }
 
 impl EthernetListener for Ipv4Rx {
     fn recv(&mut self, time: SystemTime, eth_pkg: &EthernetPacket) -> RxResult {
-        let ip_pkg = try!(Self::get_ipv4_pkg(eth_pkg));
+        self.expire_reassemblies();
+        let ip_pkg = try!(self.get_ipv4_pkg(eth_pkg));
         if Self::is_fragment(&ip_pkg) {
             if let Some(reassembled_pkg) = try!(self.save_fragment(ip_pkg)) {
                 self.forward(time, reassembled_pkg)
@@ -175,3 +418,391 @@ impl EthernetListener for Ipv4Rx {
         EtherTypes::Ipv4
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::Checksum;
+    use icmp::IcmpError;
+    use stack::StackInterfaceMsg;
+
+    use pnet::packet::MutablePacket;
+    use pnet::packet::ethernet::MutableEthernetPacket;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use pnet::packet::ipv4::MutableIpv4Packet;
+
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+    use std::sync::mpsc;
+
+    #[test]
+    fn forward_reports_protocol_unreachable_when_no_listener() {
+        let dest_ip = Ipv4Addr::new(10, 0, 0, 2);
+        let source_ip = Ipv4Addr::new(10, 0, 0, 1);
+
+        let mut buffer = vec![0u8; 20];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(20);
+            ip_pkg.set_source(source_ip);
+            ip_pkg.set_destination(dest_ip);
+            ip_pkg.set_ttl(64);
+            ip_pkg.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        }
+        let ip_pkg = Ipv4Packet::new(&buffer).unwrap();
+
+        // `dest_ip` has listeners registered, but none for Udp.
+        let mut listeners = HashMap::new();
+        listeners.insert(dest_ip, HashMap::new());
+        let (stack_tx, stack_rx) = mpsc::channel();
+        let ipv4_rx = Ipv4Rx {
+            listeners: Arc::new(ArcSwap::new(Arc::new(listeners))),
+            raw_listeners: Arc::new(ArcSwap::new(Arc::new(Vec::new()))),
+            buffers: CacheMap::with_capacity(MAX_CONCURRENT_REASSEMBLIES, Duration::new(FRAGMENT_TIMEOUT_SECS, 0)),
+            stack_tx: stack_tx,
+            checksums: ChecksumCapabilities::default(),
+        };
+
+        let result = ipv4_rx.forward(SystemTime::now(), ip_pkg);
+        assert!(result.is_err());
+
+        match stack_rx.try_recv().unwrap() {
+            StackInterfaceMsg::IcmpUnreachable(IcmpError::DestinationUnreachable(_), src, dst, orig) => {
+                assert_eq!(dest_ip, src);
+                assert_eq!(source_ip, dst);
+                assert_eq!(buffer, orig);
+            }
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+    }
+
+    struct CountingRawListener {
+        count: Arc<Mutex<usize>>,
+    }
+
+    impl Ipv4Listener for CountingRawListener {
+        fn recv(&mut self, _time: SystemTime, _packet: Ipv4Packet) -> RxResult {
+            *self.count.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn forward_hands_every_packet_to_raw_listeners_before_the_normal_demux() {
+        let dest_ip = Ipv4Addr::new(10, 0, 0, 2);
+        let source_ip = Ipv4Addr::new(10, 0, 0, 1);
+
+        let mut buffer = vec![0u8; 20];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(20);
+            ip_pkg.set_source(source_ip);
+            ip_pkg.set_destination(dest_ip);
+            ip_pkg.set_ttl(64);
+            ip_pkg.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        }
+        let ip_pkg = Ipv4Packet::new(&buffer).unwrap();
+
+        let count = Arc::new(Mutex::new(0));
+        let raw_listeners: Arc<ArcSwap<RawIpv4ListenerLookup>> = Arc::new(ArcSwap::new(Arc::new(Vec::new())));
+        Ipv4Rx::add_raw_listener(&raw_listeners, Box::new(CountingRawListener { count: count.clone() }));
+
+        // No normal listener is registered for `dest_ip` at all, so were it
+        // not for the raw listener above this would report
+        // `RxError::NoListener` and an Icmp Destination Unreachable.
+        let (stack_tx, stack_rx) = mpsc::channel();
+        let ipv4_rx = Ipv4Rx {
+            listeners: Arc::new(ArcSwap::new(Arc::new(HashMap::new()))),
+            raw_listeners: raw_listeners,
+            buffers: CacheMap::with_capacity(MAX_CONCURRENT_REASSEMBLIES, Duration::new(FRAGMENT_TIMEOUT_SECS, 0)),
+            stack_tx: stack_tx,
+            checksums: ChecksumCapabilities::default(),
+        };
+
+        let result = ipv4_rx.forward(SystemTime::now(), ip_pkg);
+        assert!(result.is_ok());
+        assert_eq!(1, *count.lock().unwrap());
+        assert!(stack_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn forward_reports_ttl_exceeded_for_a_zero_ttl_datagram() {
+        let dest_ip = Ipv4Addr::new(10, 0, 0, 2);
+        let source_ip = Ipv4Addr::new(10, 0, 0, 1);
+
+        let mut buffer = vec![0u8; 20];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(20);
+            ip_pkg.set_source(source_ip);
+            ip_pkg.set_destination(dest_ip);
+            ip_pkg.set_ttl(0);
+            ip_pkg.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        }
+        let ip_pkg = Ipv4Packet::new(&buffer).unwrap();
+
+        // Absence of a registered listener must not matter: the Ttl check
+        // has to happen before the listener lookup even runs.
+        let (stack_tx, stack_rx) = mpsc::channel();
+        let ipv4_rx = Ipv4Rx {
+            listeners: Arc::new(ArcSwap::new(Arc::new(HashMap::new()))),
+            raw_listeners: Arc::new(ArcSwap::new(Arc::new(Vec::new()))),
+            buffers: CacheMap::with_capacity(MAX_CONCURRENT_REASSEMBLIES, Duration::new(FRAGMENT_TIMEOUT_SECS, 0)),
+            stack_tx: stack_tx,
+            checksums: ChecksumCapabilities::default(),
+        };
+
+        let result = ipv4_rx.forward(SystemTime::now(), ip_pkg);
+        assert!(result.is_err());
+
+        match stack_rx.try_recv().unwrap() {
+            StackInterfaceMsg::IcmpUnreachable(IcmpError::TimeExceeded(_), src, dst, orig) => {
+                assert_eq!(dest_ip, src);
+                assert_eq!(source_ip, dst);
+                assert_eq!(buffer, orig);
+            }
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn get_ipv4_pkg_skips_checksum_verification_when_offloaded() {
+        let dest_ip = Ipv4Addr::new(10, 0, 0, 2);
+
+        let size = EthernetPacket::minimum_packet_size() + Ipv4Packet::minimum_packet_size();
+        let mut buffer = vec![0u8; size];
+        {
+            let mut eth_pkg = MutableEthernetPacket::new(&mut buffer).unwrap();
+            eth_pkg.set_ethertype(EtherTypes::Ipv4);
+            let mut ip_pkg = MutableIpv4Packet::new(eth_pkg.payload_mut()).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(Ipv4Packet::minimum_packet_size() as u16);
+            ip_pkg.set_destination(dest_ip);
+            // Deliberately wrong checksum, as if the NIC hadn't verified it.
+            ip_pkg.set_checksum(0);
+        }
+        let eth_pkg = EthernetPacket::new(&buffer).unwrap();
+
+        let (stack_tx, _stack_rx) = mpsc::channel();
+        let mut checksums = ChecksumCapabilities::default();
+        checksums.ipv4 = Checksum::Tx;
+        let ipv4_rx = Ipv4Rx {
+            listeners: Arc::new(ArcSwap::new(Arc::new(HashMap::new()))),
+            raw_listeners: Arc::new(ArcSwap::new(Arc::new(Vec::new()))),
+            buffers: CacheMap::with_capacity(MAX_CONCURRENT_REASSEMBLIES, Duration::new(FRAGMENT_TIMEOUT_SECS, 0)),
+            stack_tx: stack_tx,
+            checksums: checksums,
+        };
+
+        assert!(ipv4_rx.get_ipv4_pkg(&eth_pkg).is_ok());
+    }
+
+    #[test]
+    fn expired_reassembly_with_header_reports_reassembly_timeout() {
+        use std::thread::sleep;
+
+        let dest_ip = Ipv4Addr::new(10, 0, 0, 2);
+        let source_ip = Ipv4Addr::new(10, 0, 0, 1);
+
+        let mut buffer = vec![0u8; 28];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(28);
+            ip_pkg.set_source(source_ip);
+            ip_pkg.set_destination(dest_ip);
+            ip_pkg.set_flags(MORE_FRAGMENTS);
+            ip_pkg.set_fragment_offset(0);
+        }
+
+        let (stack_tx, stack_rx) = mpsc::channel();
+        let mut ipv4_rx = Ipv4Rx {
+            listeners: Arc::new(ArcSwap::new(Arc::new(HashMap::new()))),
+            raw_listeners: Arc::new(ArcSwap::new(Arc::new(Vec::new()))),
+            buffers: CacheMap::with_capacity(MAX_CONCURRENT_REASSEMBLIES, Duration::new(0, 10_000_000)),
+            stack_tx: stack_tx,
+            checksums: ChecksumCapabilities::default(),
+        };
+        ipv4_rx.save_fragment(Ipv4Packet::new(&buffer).unwrap()).unwrap();
+
+        sleep(Duration::new(0, 20_000_000));
+        ipv4_rx.expire_reassemblies();
+
+        match stack_rx.try_recv().unwrap() {
+            StackInterfaceMsg::IcmpUnreachable(IcmpError::TimeExceeded(_), src, dst, _) => {
+                assert_eq!(dest_ip, src);
+                assert_eq!(source_ip, dst);
+            }
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn reassembly_timeout_runs_from_the_first_fragment_and_is_not_reset_by_a_later_one() {
+        use std::thread::sleep;
+
+        let dest_ip = Ipv4Addr::new(10, 0, 0, 2);
+        let source_ip = Ipv4Addr::new(10, 0, 0, 1);
+
+        let mut first = vec![0u8; 28];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut first).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(28);
+            ip_pkg.set_source(source_ip);
+            ip_pkg.set_destination(dest_ip);
+            ip_pkg.set_flags(MORE_FRAGMENTS);
+            ip_pkg.set_fragment_offset(0);
+        }
+        let mut second = vec![0u8; 28];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut second).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(28);
+            ip_pkg.set_source(source_ip);
+            ip_pkg.set_destination(dest_ip);
+            ip_pkg.set_flags(MORE_FRAGMENTS);
+            ip_pkg.set_fragment_offset(1);
+        }
+
+        let (stack_tx, stack_rx) = mpsc::channel();
+        let mut ipv4_rx = Ipv4Rx {
+            listeners: Arc::new(ArcSwap::new(Arc::new(HashMap::new()))),
+            raw_listeners: Arc::new(ArcSwap::new(Arc::new(Vec::new()))),
+            buffers: CacheMap::with_capacity(MAX_CONCURRENT_REASSEMBLIES, Duration::new(0, 30_000_000)),
+            stack_tx: stack_tx,
+            checksums: ChecksumCapabilities::default(),
+        };
+        ipv4_rx.save_fragment(Ipv4Packet::new(&first).unwrap()).unwrap();
+
+        // A later fragment must not push the deadline out from when the
+        // first one started the reassembly.
+        sleep(Duration::new(0, 20_000_000));
+        ipv4_rx.save_fragment(Ipv4Packet::new(&second).unwrap()).unwrap();
+
+        sleep(Duration::new(0, 20_000_000));
+        ipv4_rx.expire_reassemblies();
+
+        match stack_rx.try_recv().unwrap() {
+            StackInterfaceMsg::IcmpUnreachable(IcmpError::TimeExceeded(_), src, dst, _) => {
+                assert_eq!(dest_ip, src);
+                assert_eq!(source_ip, dst);
+            }
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn expired_reassembly_without_header_reports_nothing() {
+        use std::thread::sleep;
+
+        let dest_ip = Ipv4Addr::new(10, 0, 0, 2);
+        let source_ip = Ipv4Addr::new(10, 0, 0, 1);
+
+        // A non-initial fragment, carrying no IP header of its own.
+        let mut buffer = vec![0u8; 28];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(28);
+            ip_pkg.set_source(source_ip);
+            ip_pkg.set_destination(dest_ip);
+            ip_pkg.set_flags(MORE_FRAGMENTS);
+            ip_pkg.set_fragment_offset(1);
+        }
+
+        let (stack_tx, stack_rx) = mpsc::channel();
+        let mut ipv4_rx = Ipv4Rx {
+            listeners: Arc::new(ArcSwap::new(Arc::new(HashMap::new()))),
+            raw_listeners: Arc::new(ArcSwap::new(Arc::new(Vec::new()))),
+            buffers: CacheMap::with_capacity(MAX_CONCURRENT_REASSEMBLIES, Duration::new(0, 10_000_000)),
+            stack_tx: stack_tx,
+            checksums: ChecksumCapabilities::default(),
+        };
+        ipv4_rx.save_fragment(Ipv4Packet::new(&buffer).unwrap()).unwrap();
+
+        sleep(Duration::new(0, 20_000_000));
+        ipv4_rx.expire_reassemblies();
+
+        assert!(stack_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn recv_reports_nothing_for_a_lone_non_initial_fragment() {
+        let dest_ip = Ipv4Addr::new(10, 0, 0, 2);
+        let source_ip = Ipv4Addr::new(10, 0, 0, 1);
+
+        let size = EthernetPacket::minimum_packet_size() + 28;
+        let mut buffer = vec![0u8; size];
+        {
+            let mut eth_pkg = MutableEthernetPacket::new(&mut buffer).unwrap();
+            eth_pkg.set_ethertype(EtherTypes::Ipv4);
+            let mut ip_pkg = MutableIpv4Packet::new(eth_pkg.payload_mut()).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(28);
+            ip_pkg.set_source(source_ip);
+            ip_pkg.set_destination(dest_ip);
+            ip_pkg.set_ttl(64);
+            ip_pkg.set_flags(MORE_FRAGMENTS);
+            ip_pkg.set_fragment_offset(1);
+        }
+        let eth_pkg = EthernetPacket::new(&buffer).unwrap();
+
+        let (stack_tx, stack_rx) = mpsc::channel();
+        let mut ipv4_rx = Ipv4Rx {
+            listeners: Arc::new(ArcSwap::new(Arc::new(HashMap::new()))),
+            raw_listeners: Arc::new(ArcSwap::new(Arc::new(Vec::new()))),
+            buffers: CacheMap::with_capacity(MAX_CONCURRENT_REASSEMBLIES, Duration::new(FRAGMENT_TIMEOUT_SECS, 0)),
+            stack_tx: stack_tx,
+            checksums: ChecksumCapabilities::default(),
+        };
+
+        // Still incomplete and nowhere near its reassembly timeout: `forward`
+        // must never run on this fragment directly, so no Icmp error (about
+        // a missing listener or otherwise) can be generated for it.
+        assert!(ipv4_rx.recv(SystemTime::now(), &eth_pkg).is_ok());
+        assert!(stack_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn max_concurrent_reassemblies_evicts_the_oldest_on_overflow() {
+        fn fragment(source_ip: Ipv4Addr, dest_ip: Ipv4Addr, ident: u16) -> Vec<u8> {
+            let mut buffer = vec![0u8; 28];
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(28);
+            ip_pkg.set_source(source_ip);
+            ip_pkg.set_destination(dest_ip);
+            ip_pkg.set_identification(ident);
+            ip_pkg.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+            ip_pkg.set_flags(MORE_FRAGMENTS);
+            ip_pkg.set_fragment_offset(0);
+            buffer
+        }
+
+        let dest_ip = Ipv4Addr::new(10, 0, 0, 2);
+        let source_ip = Ipv4Addr::new(10, 0, 0, 1);
+
+        let (stack_tx, _stack_rx) = mpsc::channel();
+        let config = ReassemblyConfig { timeout: Duration::new(60, 0), max_concurrent: 2 };
+        let mut ipv4_rx = Ipv4Rx {
+            listeners: Arc::new(ArcSwap::new(Arc::new(HashMap::new()))),
+            raw_listeners: Arc::new(ArcSwap::new(Arc::new(Vec::new()))),
+            buffers: CacheMap::with_capacity(config.max_concurrent, config.timeout),
+            stack_tx: stack_tx,
+            checksums: ChecksumCapabilities::default(),
+        };
+
+        ipv4_rx.save_fragment(Ipv4Packet::new(&fragment(source_ip, dest_ip, 1)).unwrap()).unwrap();
+        ipv4_rx.save_fragment(Ipv4Packet::new(&fragment(source_ip, dest_ip, 2)).unwrap()).unwrap();
+        ipv4_rx.save_fragment(Ipv4Packet::new(&fragment(source_ip, dest_ip, 3)).unwrap()).unwrap();
+
+        assert_eq!(2, ipv4_rx.buffers.len());
+        assert!(!ipv4_rx.buffers.contains_key(&(source_ip, dest_ip, 1, IpNextHeaderProtocols::Udp)));
+        assert!(ipv4_rx.buffers.contains_key(&(source_ip, dest_ip, 2, IpNextHeaderProtocols::Udp)));
+        assert!(ipv4_rx.buffers.contains_key(&(source_ip, dest_ip, 3, IpNextHeaderProtocols::Udp)));
+    }
+}
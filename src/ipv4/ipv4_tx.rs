@@ -1,4 +1,5 @@
 use {Payload, TxResult};
+use checksum::{Checksum, ChecksumCapabilities};
 use ethernet::EthernetPayload;
 use ethernet::EthernetTx;
 
@@ -9,9 +10,14 @@ use pnet::packet::ipv4::{Ipv4Packet, MutableIpv4Packet, checksum};
 
 use std::cmp;
 use std::net::Ipv4Addr;
+use std::sync::Arc;
 
 use super::{MORE_FRAGMENTS, NO_FLAGS};
 
+/// The TTL every `Ipv4TxImpl` is constructed with unless overridden via
+/// `with_ttl`.
+const DEFAULT_TTL: u8 = 40;
+
 pub trait Ipv4Payload: Payload {
     fn next_level_protocol(&self) -> IpNextHeaderProtocol;
 }
@@ -57,6 +63,28 @@ impl Payload for BasicIpv4Payload {
 pub trait Ipv4Tx {
     fn src(&self) -> Ipv4Addr;
     fn dst(&self) -> Ipv4Addr;
+
+    /// The checksum offload capabilities in effect for this `Ipv4Tx`,
+    /// inherited from the underlying `EthernetTx` unless overridden.
+    /// Carried down into the Ipv4/Icmp builders so they can skip software
+    /// checksum work the NIC already does.
+    fn checksums(&self) -> ChecksumCapabilities {
+        ChecksumCapabilities::default()
+    }
+
+    /// Overrides the Ttl used for every datagram sent from now on. No-op by
+    /// default; only `Ipv4TxImpl` acts on it. Lets `raw::RawTx` honor a
+    /// caller-chosen Ttl per datagram instead of a fixed one set up front.
+    fn set_ttl(&mut self, _ttl: u8) {}
+
+    /// Overrides the DSCP used for every datagram sent from now on. No-op by
+    /// default; only `Ipv4TxImpl` acts on it.
+    fn set_dscp(&mut self, _dscp: u8) {}
+
+    /// Overrides the ECN bits used for every datagram sent from now on.
+    /// No-op by default; only `Ipv4TxImpl` acts on it.
+    fn set_ecn(&mut self, _ecn: u8) {}
+
     fn send<P: Ipv4Payload>(&mut self, payload: P) -> TxResult;
 }
 
@@ -68,27 +96,108 @@ pub struct Ipv4TxImpl<T: EthernetTx> {
     mtu: usize,
     ethernet: T,
     next_identification: u16,
+    checksums: ChecksumCapabilities,
+    ttl: u8,
+    dscp: u8,
+    ecn: u8,
+    /// Raw IPv4 options, already padded to a multiple of 4 bytes, e.g. Router
+    /// Alert (`[0x94, 0x04, 0x00, 0x00]`) for IGMP traffic. Empty unless set
+    /// through `with_options`. Kept behind an `Arc` since it is identical for
+    /// every packet sent through this `Ipv4Tx` and is cloned into every
+    /// `Ipv4Builder` produced by `send`.
+    options: Arc<[u8]>,
 }
 
 impl<T: EthernetTx> Ipv4TxImpl<T> {
-    /// Constructs a new `Ipv4Tx`.
+    /// Constructs a new `Ipv4Tx`. Inherits its checksum offload
+    /// capabilities from `ethernet`. Defaults to no IPv4 options, a TTL of
+    /// 40 and DSCP/ECN both zero; override with `with_options`, `with_ttl`,
+    /// `with_dscp`, `with_ecn` and `with_checksums`.
     ///
     /// # Panics
     ///
     /// Panics if `mtu` is smaller than the minimum Ipv4 packet size.
     pub fn new(ethernet: T, src: Ipv4Addr, dst: Ipv4Addr, mtu: usize) -> Self {
         assert!(mtu >= Ipv4Packet::minimum_packet_size());
+        let checksums = ethernet.checksums();
         Ipv4TxImpl {
             src: src,
             dst: dst,
             mtu: mtu,
             ethernet: ethernet,
             next_identification: 0,
+            checksums: checksums,
+            ttl: DEFAULT_TTL,
+            dscp: 0,
+            ecn: 0,
+            options: Arc::from(Vec::new().into_boxed_slice()),
         }
     }
 
+    /// Size, in bytes, of the Ipv4 header this `Ipv4Tx` produces, including
+    /// whatever `options` is currently set to.
+    fn header_size(&self) -> usize {
+        Ipv4Packet::minimum_packet_size() + self.options.len()
+    }
+
     pub fn max_payload_per_fragment(&self) -> usize {
-        (self.mtu - Ipv4Packet::minimum_packet_size()) & !0b111
+        (self.mtu - self.header_size()) & !0b111
+    }
+
+    /// Overrides the checksum offload capabilities inherited from the
+    /// underlying `EthernetTx`.
+    pub fn set_checksums(&mut self, checksums: ChecksumCapabilities) {
+        self.checksums = checksums;
+    }
+
+    /// Overrides the checksum offload capabilities this `Ipv4Tx` would
+    /// otherwise inherit from its `EthernetTx`, e.g. to tell it a NIC
+    /// computes the Ipv4 header checksum in hardware so the software
+    /// `checksum()` call in `Ipv4Builder::build` can be skipped.
+    pub fn with_checksums(mut self, checksums: ChecksumCapabilities) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// Overrides the TTL (default 40) every datagram sent through this
+    /// `Ipv4Tx` carries.
+    pub fn with_ttl(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Overrides the DSCP (default 0) every datagram sent through this
+    /// `Ipv4Tx` carries. See
+    /// <https://en.wikipedia.org/wiki/Differentiated_services>.
+    pub fn with_dscp(mut self, dscp: u8) -> Self {
+        self.dscp = dscp;
+        self
+    }
+
+    /// Overrides the ECN bits (default 0) every datagram sent through this
+    /// `Ipv4Tx` carries. See
+    /// <https://en.wikipedia.org/wiki/Explicit_Congestion_Notification>.
+    pub fn with_ecn(mut self, ecn: u8) -> Self {
+        self.ecn = ecn;
+        self
+    }
+
+    /// Sets the raw IPv4 options every datagram sent through this `Ipv4Tx`
+    /// carries, e.g. Router Alert (`&[0x94, 0x04, 0x00, 0x00]`) to have
+    /// IGMP traffic intercepted by routers along the path.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `options.len()` is not a multiple of 4 (every IPv4 header
+    /// must be a whole number of 32 bit words), if it is longer than 40
+    /// bytes (the most an IPv4 header can hold), or if `mtu` no longer
+    /// leaves room for a header this size.
+    pub fn with_options(mut self, options: Vec<u8>) -> Self {
+        assert_eq!(0, options.len() % 4);
+        assert!(options.len() <= 40);
+        self.options = Arc::from(options.into_boxed_slice());
+        assert!(self.mtu >= self.header_size());
+        self
     }
 }
 
@@ -101,18 +210,43 @@ impl<T: EthernetTx> Ipv4Tx for Ipv4TxImpl<T> {
         self.dst
     }
 
+    fn checksums(&self) -> ChecksumCapabilities {
+        self.checksums
+    }
+
+    fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = ttl;
+    }
+
+    fn set_dscp(&mut self, dscp: u8) {
+        self.dscp = dscp;
+    }
+
+    fn set_ecn(&mut self, ecn: u8) {
+        self.ecn = ecn;
+    }
+
     fn send<P: Ipv4Payload>(&mut self, payload: P) -> TxResult {
         let payload_len = payload.len() as usize;
-        let builder = Ipv4Builder::new(self.src, self.dst, self.next_identification, payload);
+        let header_size = self.header_size();
+        let builder = Ipv4Builder::new(self.src,
+                                        self.dst,
+                                        self.next_identification,
+                                        self.checksums.ipv4,
+                                        self.ttl,
+                                        self.dscp,
+                                        self.ecn,
+                                        self.options.clone(),
+                                        payload);
         self.next_identification.wrapping_add(1);
 
         let max_payload_per_fragment = self.max_payload_per_fragment();
         if payload_len <= max_payload_per_fragment {
-            let size = payload_len + Ipv4Packet::minimum_packet_size();
+            let size = payload_len + header_size;
             self.ethernet.send(1, size, builder)
         } else {
             let fragments = 1 + ((payload_len - 1) / max_payload_per_fragment);
-            let size = max_payload_per_fragment + Ipv4Packet::minimum_packet_size();
+            let size = max_payload_per_fragment + header_size;
             self.ethernet.send(fragments, size, builder)
         }
     }
@@ -124,16 +258,35 @@ pub struct Ipv4Builder<P: Ipv4Payload> {
     dst: Ipv4Addr,
     offset: usize,
     identification: u16,
+    checksum: Checksum,
+    ttl: u8,
+    dscp: u8,
+    ecn: u8,
+    options: Arc<[u8]>,
     payload: P,
 }
 
 impl<P: Ipv4Payload> Ipv4Builder<P> {
-    pub fn new(src: Ipv4Addr, dst: Ipv4Addr, identification: u16, payload: P) -> Self {
+    pub fn new(src: Ipv4Addr,
+               dst: Ipv4Addr,
+               identification: u16,
+               checksum: Checksum,
+               ttl: u8,
+               dscp: u8,
+               ecn: u8,
+               options: Arc<[u8]>,
+               payload: P)
+               -> Self {
         Ipv4Builder {
             src: src,
             dst: dst,
             offset: 0,
             identification: identification,
+            checksum: checksum,
+            ttl: ttl,
+            dscp: dscp,
+            ecn: ecn,
+            options: options,
             payload: payload,
         }
     }
@@ -147,18 +300,19 @@ impl<P: Ipv4Payload> EthernetPayload for Ipv4Builder<P> {
 
 impl<P: Ipv4Payload> Payload for Ipv4Builder<P> {
     fn len(&self) -> usize {
-        Ipv4Packet::minimum_packet_size() + self.payload.len()
+        Ipv4Packet::minimum_packet_size() + self.options.len() + self.payload.len()
     }
 
     fn build(&mut self, buffer: &mut [u8]) {
         assert!(buffer.len() <= ::std::u16::MAX as usize);
+        let header_size = Ipv4Packet::minimum_packet_size() + self.options.len();
         let mut pkg = MutableIpv4Packet::new(buffer).unwrap();
         pkg.set_version(4);
-        pkg.set_dscp(0); // https://en.wikipedia.org/wiki/Differentiated_services
-        pkg.set_ecn(0); // https://en.wikipedia.org/wiki/Explicit_Congestion_Notification
-        pkg.set_ttl(40);
-        // ip_pkg.set_options(vec![]); // We currently don't support options
-        pkg.set_header_length(5); // 5 is for no option fields
+        pkg.set_dscp(self.dscp); // https://en.wikipedia.org/wiki/Differentiated_services
+        pkg.set_ecn(self.ecn); // https://en.wikipedia.org/wiki/Explicit_Congestion_Notification
+        pkg.set_ttl(self.ttl);
+        pkg.set_options(&self.options);
+        pkg.set_header_length((header_size / 4) as u8);
         pkg.set_identification(self.identification);
         pkg.set_source(self.src);
         pkg.set_destination(self.dst);
@@ -173,14 +327,16 @@ impl<P: Ipv4Payload> Payload for Ipv4Builder<P> {
             pkg.set_flags(MORE_FRAGMENTS);
             bytes_max & !0b111 // Round down to divisable by 8
         };
-        let total_length = payload_size + Ipv4Packet::minimum_packet_size();
+        let total_length = payload_size + header_size;
         pkg.set_total_length(total_length as u16);
 
         pkg.set_next_level_protocol(self.payload.next_level_protocol());
         self.payload.build(&mut pkg.payload_mut()[..payload_size]);
 
-        let checksum = checksum(&pkg.to_immutable());
-        pkg.set_checksum(checksum);
+        if self.checksum.tx() {
+            let csum = checksum(&pkg.to_immutable());
+            pkg.set_checksum(csum);
+        }
 
         self.offset += payload_size;
     }
@@ -263,6 +419,72 @@ mod ipv4_tx_tests {
         let _testee = Ipv4TxImpl::new(eth_tx, *SRC_IP, *DST_IP, 19);
     }
 
+    #[test]
+    fn mtu_accounts_for_options() {
+        let (eth_tx, _) = MockEthernetTx::new();
+        let testee = Ipv4TxImpl::new(eth_tx, *SRC_IP, *DST_IP, 28).with_options(vec![0; 4]);
+        assert_eq!(0, testee.max_payload_per_fragment());
+    }
+
+    #[test]
+    #[should_panic]
+    fn options_must_be_a_multiple_of_four_bytes() {
+        let (eth_tx, _) = MockEthernetTx::new();
+        let _testee = Ipv4TxImpl::new(eth_tx, *SRC_IP, *DST_IP, 28).with_options(vec![0; 3]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn options_cannot_shrink_the_mtu_below_the_header() {
+        let (eth_tx, _) = MockEthernetTx::new();
+        let _testee = Ipv4TxImpl::new(eth_tx, *SRC_IP, *DST_IP, 20).with_options(vec![0; 4]);
+    }
+
+    #[test]
+    fn tx_with_router_alert_option() {
+        let (eth_tx, rx) = MockEthernetTx::new();
+        let router_alert = vec![0x94, 0x04, 0x00, 0x00];
+        let mut testee = Ipv4TxImpl::new(eth_tx, *SRC_IP, *DST_IP, 1500)
+            .with_ttl(1)
+            .with_dscp(7)
+            .with_ecn(2)
+            .with_options(router_alert.clone());
+
+        let payload_data = (0..10).collect::<Vec<u8>>();
+        let payload = BasicIpv4Payload::new(IpNextHeaderProtocols::Igmp, payload_data.clone());
+        testee.send(payload).unwrap();
+
+        let pkg_buffer = rx.try_recv().unwrap();
+        assert!(rx.try_recv().is_err());
+
+        let pkg = Ipv4Packet::new(&pkg_buffer).unwrap();
+        assert_eq!(1, pkg.get_ttl());
+        assert_eq!(7, pkg.get_dscp());
+        assert_eq!(2, pkg.get_ecn());
+        assert_eq!(router_alert, pkg.get_options());
+        assert_eq!(6, pkg.get_header_length());
+        assert_eq!(payload_data.len() + Ipv4Packet::minimum_packet_size() + router_alert.len(),
+                   pkg.get_total_length() as usize);
+        assert_eq!(&payload_data[..], &pkg.payload()[0..payload_data.len()]);
+    }
+
+    #[test]
+    fn tx_skips_checksum_when_offloaded() {
+        use checksum::{Checksum, ChecksumCapabilities};
+
+        let (eth_tx, rx) = MockEthernetTx::new();
+        let mut checksums = ChecksumCapabilities::default();
+        checksums.ipv4 = Checksum::Rx;
+        let mut testee = Ipv4TxImpl::new(eth_tx, *SRC_IP, *DST_IP, 1500).with_checksums(checksums);
+
+        let payload = BasicIpv4Payload::new(IpNextHeaderProtocols::Udp, vec![0; 4]);
+        testee.send(payload).unwrap();
+
+        let pkg_buffer = rx.try_recv().unwrap();
+        let pkg = Ipv4Packet::new(&pkg_buffer).unwrap();
+        assert_eq!(0, pkg.get_checksum());
+    }
+
     #[test]
     fn tx_fragmented() {
         let (eth_tx, rx) = MockEthernetTx::new();
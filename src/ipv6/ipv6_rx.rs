@@ -0,0 +1,320 @@
+use {RxError, RxResult};
+use ethernet::EthernetListener;
+
+use arc_swap::ArcSwap;
+
+use pnet::packet::Packet;
+use pnet::packet::ethernet::{EtherType, EtherTypes, EthernetPacket};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv6::Ipv6Packet;
+
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use super::FRAGMENT_HEADER_LEN;
+use util::{Buffer, CacheMap};
+
+/// How long an incomplete, in-progress reassembly is kept around waiting
+/// for its remaining fragments before being dropped. Same default
+/// `Ipv4Rx` uses; see `ipv4::ReassemblyConfig` for the rationale.
+const FRAGMENT_TIMEOUT_SECS: u64 = 30;
+
+/// How many datagrams can be mid-reassembly at once, evicting the one
+/// closest to timing out to make room for a new one once full.
+const MAX_CONCURRENT_REASSEMBLIES: usize = 64;
+
+/// Anyone interested in receiving IPv6 packets from `Ipv6Rx` must implement
+/// this. Unlike `Ipv4Listener`, which hands over the whole `Ipv4Packet`,
+/// this hands over `payload` with every extension header already walked
+/// past (and, if the datagram arrived fragmented, already reassembled) so
+/// listeners never have to deal with the extension header chain
+/// themselves.
+pub trait Ipv6Listener: Send {
+    /// Called by the library to deliver a datagram's upper-layer payload to
+    /// a listener.
+    fn recv(&mut self,
+            time: SystemTime,
+            src: Ipv6Addr,
+            dst: Ipv6Addr,
+            protocol: IpNextHeaderProtocol,
+            payload: &[u8])
+            -> RxResult;
+}
+
+/// Type binding for how the listeners in `Ipv6Rx` are structured. Mirrors
+/// `ipv4::IpListenerLookup`.
+pub type Ipv6ListenerLookup = HashMap<Ipv6Addr, HashMap<IpNextHeaderProtocol, Arc<Mutex<Box<Ipv6Listener>>>>>;
+
+// Header fields that are used to identify fragments as belonging to the
+// same packet. IPv6 fragments don't repeat the protocol of the reassembled
+// datagram (it is only known once the fragment carrying offset 0 has
+// arrived), so unlike `ipv4::FragmentIdent`, it isn't part of the key.
+type FragmentIdent = (Ipv6Addr, Ipv6Addr, u32);
+
+/// Listener and parser for IPv6 packets. Receives ethernet frames from the
+/// `EthernetRx` it's owned by, walks the extension header chain to find the
+/// upper-layer protocol, reassembles anything split across a Fragment
+/// extension header, and forwards the result to the correct
+/// `Ipv6Listener`.
+///
+/// This only covers the packet-parsing layer described in the request that
+/// introduced it; nothing in `StackInterface` constructs one yet; wiring it
+/// in requires generalizing `UdpSocket`/`TcpSocket`/`IcmpSocket` and friends
+/// over an address family the way `util::unsupported_ipv6` already notes
+/// dual-stack support is a cross-cutting rewrite of its own.
+pub struct Ipv6Rx {
+    listeners: Arc<ArcSwap<Ipv6ListenerLookup>>,
+    buffers: CacheMap<FragmentIdent, (Buffer, usize, Option<IpNextHeaderProtocol>)>,
+}
+
+impl Ipv6Rx {
+    /// Creates a new `Ipv6Rx` with the given listeners. The caller keeps its
+    /// own clone of the `listeners` `Arc`, so listeners can be changed
+    /// later. Returns the instance casted for easy addition to the
+    /// `EthernetRx` listener `Vec`.
+    pub fn new(listeners: Arc<ArcSwap<Ipv6ListenerLookup>>) -> Box<EthernetListener> {
+        let this = Ipv6Rx {
+            listeners: listeners,
+            buffers: CacheMap::with_capacity(MAX_CONCURRENT_REASSEMBLIES,
+                                              Duration::new(FRAGMENT_TIMEOUT_SECS, 0)),
+        };
+        Box::new(this) as Box<EthernetListener>
+    }
+
+    /// Walks the extension header chain starting at `next_header`,
+    /// returning the first header that is either an upper-layer protocol
+    /// or a Fragment header, along with the offset into `payload` its data
+    /// starts at.
+    fn walk_extension_headers(mut next_header: IpNextHeaderProtocol,
+                               payload: &[u8])
+                               -> Result<(IpNextHeaderProtocol, usize), RxError> {
+        let mut offset = 0;
+        loop {
+            match next_header {
+                IpNextHeaderProtocols::Hopopt |
+                IpNextHeaderProtocols::Ipv6Route |
+                IpNextHeaderProtocols::Ipv6Opts => {
+                    let header = &payload[offset..];
+                    if header.len() < 2 {
+                        return Err(RxError::InvalidLength);
+                    }
+                    let ext_next_header = IpNextHeaderProtocol::new(header[0]);
+                    let ext_len = (header[1] as usize + 1) * 8;
+                    if header.len() < ext_len {
+                        return Err(RxError::InvalidLength);
+                    }
+                    next_header = ext_next_header;
+                    offset += ext_len;
+                }
+                IpNextHeaderProtocols::Ipv6NoNxt => return Err(RxError::NoListener("Ipv6 NoNextHeader".to_owned())),
+                _ => return Ok((next_header, offset)),
+            }
+        }
+    }
+
+    /// Saves a fragment to a buffer for reassembly, same as
+    /// `Ipv4Rx::save_fragment`, returning the reassembled upper-layer
+    /// protocol and payload once every fragment has arrived.
+    fn save_fragment(&mut self,
+                      ident: FragmentIdent,
+                      next_header: IpNextHeaderProtocol,
+                      fragment_offset: usize,
+                      more_fragments: bool,
+                      fragment_payload: &[u8])
+                      -> Result<Option<(IpNextHeaderProtocol, Vec<u8>)>, RxError> {
+        if !self.buffers.contains_key(&ident) {
+            self.buffers.insert(ident, (Buffer::new(::std::u16::MAX as usize), 0, None));
+        }
+        let done = {
+            let &mut (ref mut buffer, ref mut total_length, ref mut protocol) =
+                self.buffers.get_mut(&ident).unwrap();
+            if fragment_offset == 0 {
+                *protocol = Some(next_header);
+            }
+            if buffer.push(fragment_offset, fragment_payload).is_err() {
+                return Err(RxError::InvalidContent);
+            }
+            if !more_fragments {
+                if *total_length != 0 {
+                    return Err(RxError::InvalidContent);
+                }
+                *total_length = fragment_offset + fragment_payload.len();
+            }
+            *total_length != 0 && protocol.is_some() && buffer.is_complete(*total_length)
+        };
+        if done {
+            let (buffer, len, protocol) = self.buffers.remove(&ident).unwrap();
+            let mut payload = buffer.into_vec();
+            payload.truncate(len);
+            Ok(Some((protocol.unwrap(), payload)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Drops every reassembly whose timeout elapsed before it completed.
+    fn expire_reassemblies(&mut self) {
+        self.buffers.take_expired();
+    }
+
+    fn forward(&self,
+               time: SystemTime,
+               src: Ipv6Addr,
+               dst: Ipv6Addr,
+               protocol: IpNextHeaderProtocol,
+               payload: &[u8])
+               -> RxResult {
+        let listeners = self.listeners.load();
+        if let Some(listeners) = listeners.get(&dst) {
+            if let Some(listener) = listeners.get(&protocol) {
+                listener.lock().unwrap().recv(time, src, dst, protocol, payload)
+            } else {
+                Err(RxError::NoListener(format!("Ipv6 {:?}", protocol)))
+            }
+        } else {
+            Err(RxError::NoListener(format!("Ipv6 {}", dst)))
+        }
+    }
+}
+
+impl EthernetListener for Ipv6Rx {
+    fn recv(&mut self, time: SystemTime, eth_pkg: &EthernetPacket) -> RxResult {
+        self.expire_reassemblies();
+        let eth_payload = eth_pkg.payload();
+        if eth_payload.len() < Ipv6Packet::minimum_packet_size() {
+            return Err(RxError::InvalidLength);
+        }
+        let ip_pkg = Ipv6Packet::new(eth_payload).ok_or(RxError::InvalidLength)?;
+        let src = ip_pkg.get_source();
+        let dst = ip_pkg.get_destination();
+        let (next_header, ext_offset) = Self::walk_extension_headers(ip_pkg.get_next_header(), ip_pkg.payload())?;
+
+        if next_header == IpNextHeaderProtocols::Ipv6Frag {
+            let fragment_header = &ip_pkg.payload()[ext_offset..];
+            if fragment_header.len() < FRAGMENT_HEADER_LEN {
+                return Err(RxError::InvalidLength);
+            }
+            let frag_next_header = IpNextHeaderProtocol::new(fragment_header[0]);
+            let offset_and_flags = ((fragment_header[2] as u16) << 8) | fragment_header[3] as u16;
+            let fragment_offset = (offset_and_flags >> 3) as usize * 8;
+            let more_fragments = (offset_and_flags & 1) != 0;
+            let identification = ((fragment_header[4] as u32) << 24) | ((fragment_header[5] as u32) << 16) |
+                                  ((fragment_header[6] as u32) << 8) |
+                                  fragment_header[7] as u32;
+            let fragment_payload = &fragment_header[FRAGMENT_HEADER_LEN..];
+            let ident = (src, dst, identification);
+            if let Some((protocol, payload)) = self.save_fragment(ident,
+                                                                   frag_next_header,
+                                                                   fragment_offset,
+                                                                   more_fragments,
+                                                                   fragment_payload)? {
+                self.forward(time, src, dst, protocol, &payload)
+            } else {
+                Ok(())
+            }
+        } else {
+            self.forward(time, src, dst, next_header, &ip_pkg.payload()[ext_offset..])
+        }
+    }
+
+    fn ether_type(&self) -> EtherType {
+        EtherTypes::Ipv6
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pnet::packet::MutablePacket;
+    use pnet::packet::ethernet::MutableEthernetPacket;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use pnet::packet::ipv6::MutableIpv6Packet;
+    use pnet::util::MacAddr;
+
+    use std::sync::mpsc;
+
+    struct RecordingListener {
+        chan: mpsc::Sender<(Ipv6Addr, Ipv6Addr, IpNextHeaderProtocol, Vec<u8>)>,
+    }
+
+    impl Ipv6Listener for RecordingListener {
+        fn recv(&mut self,
+                _time: SystemTime,
+                src: Ipv6Addr,
+                dst: Ipv6Addr,
+                protocol: IpNextHeaderProtocol,
+                payload: &[u8])
+                -> RxResult {
+            self.chan.send((src, dst, protocol, payload.to_vec())).unwrap();
+            Ok(())
+        }
+    }
+
+    fn ethernet_frame_carrying(ipv6_payload: &[u8]) -> Vec<u8> {
+        let size = EthernetPacket::minimum_packet_size() + ipv6_payload.len();
+        let mut buffer = vec![0u8; size];
+        {
+            let mut eth_pkg = MutableEthernetPacket::new(&mut buffer).unwrap();
+            eth_pkg.set_source(MacAddr::new(1, 2, 3, 4, 5, 6));
+            eth_pkg.set_destination(MacAddr::new(6, 5, 4, 3, 2, 1));
+            eth_pkg.set_ethertype(EtherTypes::Ipv6);
+            eth_pkg.set_payload(ipv6_payload);
+        }
+        buffer
+    }
+
+    fn ipv6_datagram(src: Ipv6Addr, dst: Ipv6Addr, next_header: IpNextHeaderProtocol, payload: &[u8]) -> Vec<u8> {
+        let size = Ipv6Packet::minimum_packet_size() + payload.len();
+        let mut buffer = vec![0u8; size];
+        {
+            let mut ip_pkg = MutableIpv6Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_source(src);
+            ip_pkg.set_destination(dst);
+            ip_pkg.set_next_header(next_header);
+            ip_pkg.set_payload_length(payload.len() as u16);
+            ip_pkg.set_payload(payload);
+        }
+        buffer
+    }
+
+    #[test]
+    fn recv_forwards_a_non_fragmented_datagram_to_the_registered_listener() {
+        let src = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let dst = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+        let payload = [1, 2, 3, 4];
+        let frame = ethernet_frame_carrying(&ipv6_datagram(src, dst, IpNextHeaderProtocols::Udp, &payload));
+
+        let (tx, rx) = mpsc::channel();
+        let listener = RecordingListener { chan: tx };
+        let mut protocols = HashMap::new();
+        protocols.insert(IpNextHeaderProtocols::Udp,
+                          Arc::new(Mutex::new(Box::new(listener) as Box<Ipv6Listener>)));
+        let mut listeners = Ipv6ListenerLookup::new();
+        listeners.insert(dst, protocols);
+        let mut ipv6_rx = Ipv6Rx::new(Arc::new(ArcSwap::new(Arc::new(listeners))));
+
+        let eth_pkg = EthernetPacket::new(&frame).unwrap();
+        assert!(ipv6_rx.recv(SystemTime::now(), &eth_pkg).is_ok());
+
+        let (got_src, got_dst, protocol, got_payload) = rx.try_recv().unwrap();
+        assert_eq!(src, got_src);
+        assert_eq!(dst, got_dst);
+        assert_eq!(IpNextHeaderProtocols::Udp, protocol);
+        assert_eq!(&payload[..], &got_payload[..]);
+    }
+
+    #[test]
+    fn recv_reports_no_listener_for_an_unregistered_destination() {
+        let src = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let dst = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+        let frame = ethernet_frame_carrying(&ipv6_datagram(src, dst, IpNextHeaderProtocols::Udp, &[]));
+
+        let mut ipv6_rx = Ipv6Rx::new(Arc::new(ArcSwap::new(Arc::new(Ipv6ListenerLookup::new()))));
+
+        let eth_pkg = EthernetPacket::new(&frame).unwrap();
+        assert!(ipv6_rx.recv(SystemTime::now(), &eth_pkg).is_err());
+    }
+}
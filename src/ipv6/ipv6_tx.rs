@@ -0,0 +1,346 @@
+use {Payload, TxResult};
+use ethernet::EthernetPayload;
+use ethernet::EthernetTx;
+
+use pnet::packet::{MutablePacket, Packet};
+use pnet::packet::ethernet::{EtherType, EtherTypes};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv6::{Ipv6Packet, MutableIpv6Packet};
+
+use std::cmp;
+use std::net::Ipv6Addr;
+
+use super::FRAGMENT_HEADER_LEN;
+
+/// The Hop Limit every `Ipv6TxImpl` is constructed with unless overridden
+/// via `with_hop_limit`. Plays the same role Ipv4's Ttl does.
+const DEFAULT_HOP_LIMIT: u8 = 64;
+
+pub trait Ipv6Payload: Payload {
+    fn next_level_protocol(&self) -> IpNextHeaderProtocol;
+}
+
+
+pub struct BasicIpv6Payload {
+    next_level_protocol: IpNextHeaderProtocol,
+    offset: usize,
+    payload: Vec<u8>,
+}
+
+impl BasicIpv6Payload {
+    pub fn new(next_level_protocol: IpNextHeaderProtocol, payload: Vec<u8>) -> Self {
+        assert!(payload.len() <= ::std::u16::MAX as usize);
+        BasicIpv6Payload {
+            next_level_protocol: next_level_protocol,
+            offset: 0,
+            payload: payload,
+        }
+    }
+}
+
+impl Ipv6Payload for BasicIpv6Payload {
+    fn next_level_protocol(&self) -> IpNextHeaderProtocol {
+        self.next_level_protocol
+    }
+}
+
+impl Payload for BasicIpv6Payload {
+    fn len(&self) -> usize {
+        self.payload.len()
+    }
+
+    fn build(&mut self, buffer: &mut [u8]) {
+        let start = self.offset;
+        let end = cmp::min(start + buffer.len(), self.payload.len());
+        self.offset = end;
+        buffer.copy_from_slice(&self.payload[start..end]);
+    }
+}
+
+
+pub trait Ipv6Tx {
+    fn src(&self) -> Ipv6Addr;
+    fn dst(&self) -> Ipv6Addr;
+
+    fn send<P: Ipv6Payload>(&mut self, payload: P) -> TxResult;
+}
+
+/// IPv6 packet builder and sender. Unlike `Ipv4TxImpl`, which sets the
+/// `Don't Fragment`-less default and lets any router along the path split a
+/// datagram further, this fragments up front through an IPv6 Fragment
+/// extension header whenever a datagram doesn't fit the MTU reported by the
+/// underlying `EthernetTx`, since IPv6 routers never fragment in flight.
+pub struct Ipv6TxImpl<T: EthernetTx> {
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    mtu: usize,
+    ethernet: T,
+    next_identification: u32,
+    hop_limit: u8,
+}
+
+impl<T: EthernetTx> Ipv6TxImpl<T> {
+    /// Constructs a new `Ipv6Tx`. Defaults to a Hop Limit of 64; override
+    /// with `with_hop_limit`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mtu` is smaller than the minimum Ipv6 packet size.
+    pub fn new(ethernet: T, src: Ipv6Addr, dst: Ipv6Addr, mtu: usize) -> Self {
+        assert!(mtu >= Ipv6Packet::minimum_packet_size());
+        Ipv6TxImpl {
+            src: src,
+            dst: dst,
+            mtu: mtu,
+            ethernet: ethernet,
+            next_identification: 0,
+            hop_limit: DEFAULT_HOP_LIMIT,
+        }
+    }
+
+    /// Overrides the Hop Limit (default 64) every datagram sent through this
+    /// `Ipv6Tx` carries.
+    pub fn with_hop_limit(mut self, hop_limit: u8) -> Self {
+        self.hop_limit = hop_limit;
+        self
+    }
+
+    /// The largest payload a single, unfragmented datagram can carry.
+    fn max_unfragmented_payload(&self) -> usize {
+        self.mtu - Ipv6Packet::minimum_packet_size()
+    }
+
+    /// The largest payload a single fragment can carry once the Fragment
+    /// extension header is accounted for, rounded down to a multiple of 8
+    /// bytes as required by the Fragment Offset field's units.
+    fn max_payload_per_fragment(&self) -> usize {
+        (self.mtu - Ipv6Packet::minimum_packet_size() - FRAGMENT_HEADER_LEN) & !0b111
+    }
+}
+
+impl<T: EthernetTx> Ipv6Tx for Ipv6TxImpl<T> {
+    fn src(&self) -> Ipv6Addr {
+        self.src
+    }
+
+    fn dst(&self) -> Ipv6Addr {
+        self.dst
+    }
+
+    fn send<P: Ipv6Payload>(&mut self, payload: P) -> TxResult {
+        let payload_len = payload.len();
+        let fixed_header_size = Ipv6Packet::minimum_packet_size();
+
+        if payload_len <= self.max_unfragmented_payload() {
+            let builder = Ipv6Builder::new(self.src,
+                                            self.dst,
+                                            self.hop_limit,
+                                            None,
+                                            payload);
+            let size = fixed_header_size + payload_len;
+            self.ethernet.send(1, size, builder)
+        } else {
+            let max_payload_per_fragment = self.max_payload_per_fragment();
+            let identification = self.next_identification;
+            self.next_identification = self.next_identification.wrapping_add(1);
+            let builder = Ipv6Builder::new(self.src,
+                                            self.dst,
+                                            self.hop_limit,
+                                            Some(identification),
+                                            payload);
+            let fragments = 1 + ((payload_len - 1) / max_payload_per_fragment);
+            let size = fixed_header_size + FRAGMENT_HEADER_LEN + max_payload_per_fragment;
+            self.ethernet.send(fragments, size, builder)
+        }
+    }
+}
+
+
+pub struct Ipv6Builder<P: Ipv6Payload> {
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    hop_limit: u8,
+    offset: usize,
+    /// `Some(identification)` when this datagram is split across a Fragment
+    /// extension header, `None` when it fits a single packet and no
+    /// Fragment header is emitted at all.
+    fragment_identification: Option<u32>,
+    payload: P,
+}
+
+impl<P: Ipv6Payload> Ipv6Builder<P> {
+    pub fn new(src: Ipv6Addr,
+               dst: Ipv6Addr,
+               hop_limit: u8,
+               fragment_identification: Option<u32>,
+               payload: P)
+               -> Self {
+        Ipv6Builder {
+            src: src,
+            dst: dst,
+            hop_limit: hop_limit,
+            offset: 0,
+            fragment_identification: fragment_identification,
+            payload: payload,
+        }
+    }
+}
+
+impl<P: Ipv6Payload> EthernetPayload for Ipv6Builder<P> {
+    fn ether_type(&self) -> EtherType {
+        EtherTypes::Ipv6
+    }
+}
+
+impl<P: Ipv6Payload> Payload for Ipv6Builder<P> {
+    fn len(&self) -> usize {
+        let fragment_header_len = if self.fragment_identification.is_some() { FRAGMENT_HEADER_LEN } else { 0 };
+        Ipv6Packet::minimum_packet_size() + fragment_header_len + self.payload.len()
+    }
+
+    fn build(&mut self, buffer: &mut [u8]) {
+        assert!(buffer.len() <= ::std::u16::MAX as usize);
+        let mut pkg = MutableIpv6Packet::new(buffer).unwrap();
+        pkg.set_version(6);
+        pkg.set_traffic_class(0);
+        pkg.set_flow_label(0);
+        pkg.set_hop_limit(self.hop_limit);
+        pkg.set_source(self.src);
+        pkg.set_destination(self.dst);
+
+        let bytes_remaining = self.payload.len() - self.offset;
+
+        if let Some(identification) = self.fragment_identification {
+            pkg.set_next_header(IpNextHeaderProtocols::Ipv6Frag);
+            let bytes_max = pkg.payload().len() - FRAGMENT_HEADER_LEN;
+            let payload_size = if bytes_remaining <= bytes_max {
+                bytes_remaining
+            } else {
+                bytes_max & !0b111 // Round down to divisable by 8
+            };
+            let more_fragments = bytes_remaining > payload_size;
+
+            {
+                let fragment_header = &mut pkg.payload_mut()[..FRAGMENT_HEADER_LEN];
+                fragment_header[0] = self.payload.next_level_protocol().0;
+                fragment_header[1] = 0; // Reserved
+                let offset_and_flags = ((self.offset as u16 / 8) << 3) | (more_fragments as u16);
+                fragment_header[2] = (offset_and_flags >> 8) as u8;
+                fragment_header[3] = offset_and_flags as u8;
+                fragment_header[4] = (identification >> 24) as u8;
+                fragment_header[5] = (identification >> 16) as u8;
+                fragment_header[6] = (identification >> 8) as u8;
+                fragment_header[7] = identification as u8;
+            }
+
+            self.payload.build(&mut pkg.payload_mut()[FRAGMENT_HEADER_LEN..FRAGMENT_HEADER_LEN + payload_size]);
+            pkg.set_payload_length((FRAGMENT_HEADER_LEN + payload_size) as u16);
+            self.offset += payload_size;
+        } else {
+            pkg.set_next_header(self.payload.next_level_protocol());
+            self.payload.build(&mut pkg.payload_mut()[..bytes_remaining]);
+            pkg.set_payload_length(bytes_remaining as u16);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod ipv6_tx_tests {
+    use TxResult;
+    use ethernet::{EthernetPayload, EthernetTx};
+
+    use pnet::packet::Packet;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use pnet::packet::ipv6::Ipv6Packet;
+    use pnet::util::MacAddr;
+
+    use std::net::Ipv6Addr;
+    use std::sync::mpsc;
+
+    use super::*;
+
+    lazy_static! {
+        static ref SRC_IP: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        static ref DST_IP: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+    }
+
+    #[derive(Debug)]
+    pub struct MockEthernetTx {
+        chan: mpsc::Sender<Box<[u8]>>,
+    }
+
+    impl MockEthernetTx {
+        pub fn new() -> (MockEthernetTx, mpsc::Receiver<Box<[u8]>>) {
+            let (tx, rx) = mpsc::channel();
+            (MockEthernetTx { chan: tx }, rx)
+        }
+    }
+
+    impl EthernetTx for MockEthernetTx {
+        fn src(&self) -> MacAddr {
+            MacAddr::new(0, 0, 0, 0, 0, 0)
+        }
+
+        fn dst(&self) -> MacAddr {
+            MacAddr::new(0, 0, 0, 0, 0, 0)
+        }
+
+        fn send<P>(&mut self, packets: usize, packet_size: usize, mut payload: P) -> TxResult
+            where P: EthernetPayload
+        {
+            for _ in 0..packets {
+                let mut buffer = vec![0; packet_size];
+                payload.build(&mut buffer[..]);
+                self.chan.send(buffer.into_boxed_slice()).unwrap();
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tx_not_fragmented_carries_no_fragment_header() {
+        let (eth_tx, rx) = MockEthernetTx::new();
+        let mut testee = Ipv6TxImpl::new(eth_tx, *SRC_IP, *DST_IP, 1500);
+
+        let payload_data = (0..100).collect::<Vec<u8>>();
+        let payload = BasicIpv6Payload::new(IpNextHeaderProtocols::Udp, payload_data.clone());
+        testee.send(payload).unwrap();
+
+        let pkg_buffer = rx.try_recv().unwrap();
+        assert!(rx.try_recv().is_err());
+
+        let pkg = Ipv6Packet::new(&pkg_buffer).unwrap();
+        assert_eq!(*SRC_IP, pkg.get_source());
+        assert_eq!(*DST_IP, pkg.get_destination());
+        assert_eq!(IpNextHeaderProtocols::Udp, pkg.get_next_header());
+        assert_eq!(payload_data.len() as u16, pkg.get_payload_length());
+        assert_eq!(&payload_data[..], &pkg.payload()[0..payload_data.len()]);
+    }
+
+    #[test]
+    fn tx_fragmented_inserts_a_fragment_header() {
+        let (eth_tx, rx) = MockEthernetTx::new();
+        // 24 bytes fit unfragmented and 16 per fragment, so 30 bytes take
+        // exactly two fragments.
+        let mut testee = Ipv6TxImpl::new(eth_tx, *SRC_IP, *DST_IP, Ipv6Packet::minimum_packet_size() + FRAGMENT_HEADER_LEN + 16);
+
+        let payload_data = (0..30).collect::<Vec<u8>>();
+        let payload = BasicIpv6Payload::new(IpNextHeaderProtocols::Udp, payload_data.clone());
+        testee.send(payload).unwrap();
+
+        let pkg1 = rx.try_recv().unwrap();
+        let pkg2 = rx.try_recv().unwrap();
+        assert!(rx.try_recv().is_err());
+
+        let ip_pkg1 = Ipv6Packet::new(&pkg1).unwrap();
+        assert_eq!(IpNextHeaderProtocols::Ipv6Frag, ip_pkg1.get_next_header());
+        let fragment_header1 = &ip_pkg1.payload()[..FRAGMENT_HEADER_LEN];
+        assert_eq!(IpNextHeaderProtocols::Udp.0, fragment_header1[0]);
+        assert_eq!(1, fragment_header1[3] & 1); // More Fragments set
+
+        let ip_pkg2 = Ipv6Packet::new(&pkg2).unwrap();
+        let fragment_header2 = &ip_pkg2.payload()[..FRAGMENT_HEADER_LEN];
+        assert_eq!(0, fragment_header2[3] & 1); // Last fragment
+    }
+}
@@ -0,0 +1,20 @@
+//! A parallel IPv6 receive/transmit subsystem, registered under
+//! `EtherTypes::Ipv6` alongside `ipv4`'s `Ipv4Rx`/`Ipv4Tx`.
+//!
+//! This only covers parsing/building IPv6 datagrams, including walking the
+//! extension header chain and reassembling anything split across a
+//! Fragment extension header. Nothing here is wired into `StackInterface`
+//! yet: doing so needs `UdpSocket`/`TcpSocket`/`IcmpSocket` generalized
+//! over an address family first, which `util::unsupported_ipv6` already
+//! calls out as a cross-cutting rewrite rather than a single addressable
+//! change.
+
+mod ipv6_rx;
+mod ipv6_tx;
+
+pub use self::ipv6_rx::{Ipv6Listener, Ipv6ListenerLookup, Ipv6Rx};
+pub use self::ipv6_tx::{BasicIpv6Payload, Ipv6Builder, Ipv6Payload, Ipv6Tx, Ipv6TxImpl};
+
+/// Length, in bytes, of an IPv6 Fragment extension header: Next Header (1),
+/// Reserved (1), Fragment Offset + flags (2) and Identification (4).
+pub const FRAGMENT_HEADER_LEN: usize = 8;
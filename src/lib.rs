@@ -160,9 +160,12 @@
 
 // #![deny(missing_docs)]
 
+extern crate arc_swap;
 extern crate rand;
 extern crate pnet;
 extern crate ipnetwork;
+#[cfg(target_os = "linux")]
+extern crate libc;
 
 use std::io;
 use std::sync::{Arc, Mutex};
@@ -179,6 +182,11 @@ mod macros;
 
 pub mod ethernet;
 
+/// Module containing checksum offload capabilities, shared by the
+/// Ethernet/IPv4/Icmp layers so they can skip software checksum work a NIC
+/// already does in hardware.
+pub mod checksum;
+
 /// Module containing everything related to the address resolution protocol
 /// (Arp)
 pub mod arp;
@@ -186,19 +194,53 @@ pub mod arp;
 /// Module containing IPv4 functionality
 pub mod ipv4;
 
+/// Module containing IPv6 functionality. See the module docs for how far
+/// along this is compared to `ipv4`.
+pub mod ipv6;
+
 /// Module containing internet control message procotol (icmp) functionality
 pub mod icmp;
 
+/// Module containing the internet group management protocol (Igmp),
+/// used to join/leave IPv4 multicast groups.
+pub mod igmp;
+
 /// Module containing Udp functionality.
 pub mod udp;
 
+/// Module containing Tcp functionality: `TcpSocket` connections, a
+/// `TcpAcceptor` for incoming ones, and a RST reply to any segment
+/// addressed to a closed port, per RFC 793.
+pub mod tcp;
+
+/// Module containing raw IPv4 socket functionality, for sending and
+/// receiving whole datagrams of protocols the crate has no dedicated
+/// support for (e.g. Ospf, Gre, or a custom one).
+pub mod raw;
+
+/// Module containing a DHCPv4 client for automatic interface address
+/// configuration.
+pub mod dhcp;
+
 mod routing;
 pub use routing::RoutingTable;
 
+pub mod pcap;
+
+pub mod trace;
+
+mod internal;
+pub use internal::PnetEthernetProvider;
+
 mod util;
 
 #[cfg(any(test, feature = "unit-tests", feature = "integration-tests", feature = "benchmarks"))]
-pub mod testing;
+pub mod testing {
+    pub mod ethernet;
+    pub mod fault_injector;
+    pub mod faulty;
+    pub mod ipv4;
+}
 
 #[cfg(not(feature = "unit-tests"))]
 mod stack;
@@ -334,6 +376,24 @@ pub enum RxError {
     Other(String),
 }
 
+impl RxError {
+    /// Whether this is a "this particular packet couldn't be delivered"
+    /// outcome — no listener was registered for it, its checksum didn't
+    /// match, it was too short or otherwise malformed — as opposed to
+    /// something being wrong with the receive pipeline itself. Recoverable
+    /// errors are for `EthernetRx`'s top level `recv` to drop and continue
+    /// on, not to propagate to its caller.
+    pub fn is_recoverable(&self) -> bool {
+        match *self {
+            RxError::NoListener(_) |
+            RxError::InvalidChecksum |
+            RxError::InvalidLength |
+            RxError::InvalidContent => true,
+            RxError::PoisonedLock | RxError::Other(_) => false,
+        }
+    }
+}
+
 /// Simple type definition for return type of `recv` on `*Rx` objects.
 pub type RxResult = Result<(), RxError>;
 
@@ -434,6 +494,52 @@ impl Tx {
         let result = sender.build_and_send(num_packets, size, &mut builder);
         io_result_to_tx_result(result)
     }
+
+    /// Reserves a `TxToken` for `num_packets` packets of `size` bytes each.
+    /// If this `Tx` is versioned the `VersionedTx` is locked and the
+    /// revision compared right away, so a stale `Tx` is caught here, before
+    /// a caller has spent any work building packet headers, instead of
+    /// after, as `send` would have it discover once the whole header chain
+    /// has already been built up.
+    pub fn token(&mut self, num_packets: usize, size: usize) -> Result<TxToken, TxError> {
+        match self.sender {
+            TxSender::Versioned(ref vtx) => {
+                match vtx.lock() {
+                    Ok(sender) => {
+                        if self.rev != sender.current_rev {
+                            return Err(TxError::InvalidTx);
+                        }
+                    }
+                    Err(_) => return Err(TxError::PoisonedLock),
+                }
+            }
+            TxSender::Direct(_) => (),
+        }
+        Ok(TxToken {
+            tx: self,
+            num_packets: num_packets,
+            size: size,
+        })
+    }
+}
+
+/// A send reserved by `Tx::token`. The revision check has already happened
+/// by the time a `TxToken` exists, so `consume` only has to hand the
+/// backing buffer to `f` once and transmit it.
+pub struct TxToken<'a> {
+    tx: &'a mut Tx,
+    num_packets: usize,
+    size: usize,
+}
+
+impl<'a> TxToken<'a> {
+    /// Lets `f` fill in the packet(s) this token reserved room for, exactly
+    /// once, then sends them.
+    pub fn consume<T>(self, f: T) -> TxResult
+        where T: FnMut(MutableEthernetPacket)
+    {
+        self.tx.send(self.num_packets, self.size, f)
+    }
 }
 
 /// Create a default stack managing all interfaces given by
@@ -457,6 +563,20 @@ pub fn default_stack() -> StackResult<NetworkStack> {
     Ok(stack)
 }
 
+/// Creates a stack with a single interface backed by a TAP device instead
+/// of a real, root-requiring NIC. `ifname` is the name of the TAP
+/// interface to create (or attach to, if it already exists), e.g. `"tap0"`.
+/// Lets the whole stack run unprivileged, which is handy for CI, fuzzing,
+/// and connecting two stacks back to back.
+#[cfg(target_os = "linux")]
+pub fn tap_stack(ifname: &str) -> StackResult<NetworkStack> {
+    let (mac, mtu, channel) = try!(ethernet::tap::open(ifname).map_err(StackError::from));
+    let interface = Interface::new(ifname.to_owned(), mac);
+    let mut stack = NetworkStack::new();
+    try!(stack.add_interface_with_mtu(interface, channel, mtu));
+    Ok(stack)
+}
+
 // #[cfg(not(feature = "unit-tests"))]
 // pub fn stack<Datalink>(_datalink_provider: Datalink) ->
 // StackResult<NetworkStack>
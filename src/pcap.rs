@@ -0,0 +1,325 @@
+//! Capturing the frames librips sends and receives to a standard libpcap
+//! file, readable by Wireshark/tcpdump, for offline debugging.
+
+use {EthernetChannel, RxResult, Tx, TxError, TxResult};
+use ethernet::EthernetListener;
+
+use pnet::datalink::{EthernetDataLinkChannelIterator, EthernetDataLinkReceiver,
+                     EthernetDataLinkSender};
+use pnet::packet::{MutablePacket, Packet};
+use pnet::packet::ethernet::{EtherType, EthernetPacket, MutableEthernetPacket};
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const LINKTYPE_ETHERNET: u32 = 1;
+const SNAPLEN: u32 = 65535;
+
+/// Writes frames to an underlying `io::Write` in the standard libpcap file
+/// format. Generic over `W` so tests can capture into a `Vec<u8>` the same
+/// way `MockEthernetListener` captures into an mpsc channel.
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Creates a new `PcapWriter`, immediately writing the 24 byte global
+    /// pcap header to `writer`.
+    pub fn new(mut writer: W) -> io::Result<PcapWriter<W>> {
+        let mut header = [0u8; 24];
+        write_u32(&mut header[0..4], PCAP_MAGIC);
+        write_u16(&mut header[4..6], PCAP_VERSION_MAJOR);
+        write_u16(&mut header[6..8], PCAP_VERSION_MINOR);
+        write_u32(&mut header[8..12], 0); // thiszone
+        write_u32(&mut header[12..16], 0); // sigfigs
+        write_u32(&mut header[16..20], SNAPLEN);
+        write_u32(&mut header[20..24], LINKTYPE_ETHERNET);
+        writer.write_all(&header)?;
+        Ok(PcapWriter { writer: writer })
+    }
+
+    /// Appends a single frame to the capture, truncating it to `SNAPLEN`
+    /// bytes if it is larger.
+    pub fn write_frame(&mut self, time: SystemTime, data: &[u8]) -> io::Result<()> {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or(::std::time::Duration::new(0, 0));
+        let captured = &data[..::std::cmp::min(data.len(), SNAPLEN as usize)];
+
+        let mut record_header = [0u8; 16];
+        write_u32(&mut record_header[0..4], since_epoch.as_secs() as u32);
+        write_u32(&mut record_header[4..8], since_epoch.subsec_nanos() / 1000);
+        write_u32(&mut record_header[8..12], captured.len() as u32);
+        write_u32(&mut record_header[12..16], data.len() as u32);
+
+        self.writer.write_all(&record_header)?;
+        self.writer.write_all(captured)
+    }
+}
+
+fn write_u32(buffer: &mut [u8], value: u32) {
+    buffer[0] = (value & 0xff) as u8;
+    buffer[1] = ((value >> 8) & 0xff) as u8;
+    buffer[2] = ((value >> 16) & 0xff) as u8;
+    buffer[3] = ((value >> 24) & 0xff) as u8;
+}
+
+fn write_u16(buffer: &mut [u8], value: u16) {
+    buffer[0] = (value & 0xff) as u8;
+    buffer[1] = ((value >> 8) & 0xff) as u8;
+}
+
+/// `EthernetListener` wrapping another listener, appending every frame it
+/// sees to a `PcapWriter` before forwarding it on unchanged.
+pub struct PcapListener<L: EthernetListener, W: Write> {
+    inner: L,
+    writer: Arc<Mutex<PcapWriter<W>>>,
+}
+
+impl<L: EthernetListener, W: Write> PcapListener<L, W> {
+    pub fn new(inner: L, writer: Arc<Mutex<PcapWriter<W>>>) -> Self {
+        PcapListener {
+            inner: inner,
+            writer: writer,
+        }
+    }
+}
+
+impl<L: EthernetListener, W: Write + Send> EthernetListener for PcapListener<L, W> {
+    fn recv(&mut self, time: SystemTime, packet: &EthernetPacket) -> RxResult {
+        if let Ok(mut writer) = self.writer.lock() {
+            writer.write_frame(time, packet.packet()).unwrap_or(());
+        }
+        self.inner.recv(time, packet)
+    }
+
+    fn ether_type(&self) -> EtherType {
+        self.inner.ether_type()
+    }
+}
+
+/// `Tx` wrapping another `Tx`, recording every frame sent through it to a
+/// `PcapWriter` before passing it on unchanged. Pairs with `PcapListener` so
+/// both halves of the traffic librips sees end up in the same capture file.
+pub struct PcapTx<T: Tx, W: Write> {
+    inner: T,
+    writer: Arc<Mutex<PcapWriter<W>>>,
+}
+
+impl<T: Tx, W: Write> PcapTx<T, W> {
+    pub fn new(inner: T, writer: Arc<Mutex<PcapWriter<W>>>) -> Self {
+        PcapTx {
+            inner: inner,
+            writer: writer,
+        }
+    }
+}
+
+impl<T: Tx, W: Write + Send> Tx for PcapTx<T, W> {
+    fn send<F>(&mut self, num_packets: usize, packet_size: usize, mut builder: F) -> TxResult
+        where F: FnMut(&mut [u8])
+    {
+        let writer = self.writer.clone();
+        let mut tap = move |buffer: &mut [u8]| {
+            builder(buffer);
+            if let Ok(mut writer) = writer.lock() {
+                writer.write_frame(SystemTime::now(), buffer)
+                    .map_err(|e| TxError::Other(e.to_string()))
+                    .unwrap_or(());
+            }
+        };
+        self.inner.send(num_packets, packet_size, tap)
+    }
+}
+
+/// `EthernetDataLinkSender` wrapping another sender, recording every frame
+/// passed through `build_and_send` to a `PcapWriter` before handing it on
+/// unchanged. Unlike `PcapTx`, which taps the crate's own `Tx`
+/// abstraction, this sits one layer lower, directly on the
+/// `pnet::datalink` channel, so `EthernetChannel`/`default_stack` can opt
+/// every interface into capture without any of `EthernetTx`, `ArpTx`,
+/// `Ipv4Tx` or `UdpTx` knowing about it.
+///
+/// Since the inner sender owns the buffer `func` builds into, each frame
+/// is first built into a buffer of our own so it can be captured, then
+/// replayed into the inner sender's buffer, the same way `FaultyTx`
+/// rebuilds a frame from a `RawPayload` before resending it.
+pub struct PcapSender<W: Write> {
+    inner: Box<EthernetDataLinkSender>,
+    writer: Arc<Mutex<PcapWriter<W>>>,
+}
+
+impl<W: Write> PcapSender<W> {
+    pub fn new(inner: Box<EthernetDataLinkSender>,
+               writer: Arc<Mutex<PcapWriter<W>>>)
+               -> Self {
+        PcapSender {
+            inner: inner,
+            writer: writer,
+        }
+    }
+}
+
+impl<W: Write + Send> EthernetDataLinkSender for PcapSender<W> {
+    fn build_and_send(&mut self,
+                       num_packets: usize,
+                       packet_size: usize,
+                       func: &mut FnMut(MutableEthernetPacket))
+                       -> Option<io::Result<()>> {
+        let mut frames = Vec::with_capacity(num_packets);
+        for _ in 0..num_packets {
+            let mut buffer = vec![0; packet_size];
+            {
+                let packet = MutableEthernetPacket::new(&mut buffer).unwrap();
+                func(packet);
+            }
+            if let Ok(mut writer) = self.writer.lock() {
+                writer.write_frame(SystemTime::now(), &buffer).unwrap_or(());
+            }
+            frames.push(buffer);
+        }
+        let mut frames = frames.into_iter();
+        let mut relay = |mut packet: MutableEthernetPacket| {
+            if let Some(frame) = frames.next() {
+                packet.packet_mut().copy_from_slice(&frame);
+            }
+        };
+        self.inner.build_and_send(num_packets, packet_size, &mut relay)
+    }
+}
+
+/// `EthernetDataLinkReceiver` wrapping another receiver, recording every
+/// frame read through its iterator to a `PcapWriter` before handing it on
+/// unchanged. Pairs with `PcapSender` so both halves of an
+/// `EthernetChannel` end up in the same capture file.
+pub struct PcapReceiver<W: Write> {
+    inner: Box<EthernetDataLinkReceiver>,
+    writer: Arc<Mutex<PcapWriter<W>>>,
+}
+
+impl<W: Write> PcapReceiver<W> {
+    pub fn new(inner: Box<EthernetDataLinkReceiver>,
+               writer: Arc<Mutex<PcapWriter<W>>>)
+               -> Self {
+        PcapReceiver {
+            inner: inner,
+            writer: writer,
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> EthernetDataLinkReceiver for PcapReceiver<W> {
+    fn iter<'a>(&'a mut self) -> Box<EthernetDataLinkChannelIterator<'a> + 'a> {
+        Box::new(PcapIter {
+            inner: self.inner.iter(),
+            writer: self.writer.clone(),
+        })
+    }
+}
+
+struct PcapIter<'a, W: Write + 'a> {
+    inner: Box<EthernetDataLinkChannelIterator<'a> + 'a>,
+    writer: Arc<Mutex<PcapWriter<W>>>,
+}
+
+impl<'a, W: Write> EthernetDataLinkChannelIterator<'a> for PcapIter<'a, W> {
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        let packet = self.inner.next()?;
+        if let Ok(mut writer) = self.writer.lock() {
+            writer.write_frame(SystemTime::now(), packet.packet()).unwrap_or(());
+        }
+        Ok(packet)
+    }
+}
+
+/// Wraps both halves of `channel` in `PcapSender`/`PcapReceiver` so every
+/// frame it sends or receives is recorded to `writer`. Lets `default_stack`
+/// (or any other caller constructing an `EthernetChannel`) opt an
+/// interface into a pcap capture without the rest of the stack knowing
+/// about it.
+pub fn capture_channel<W>(channel: EthernetChannel,
+                           writer: Arc<Mutex<PcapWriter<W>>>)
+                           -> EthernetChannel
+    where W: Write + Send + 'static
+{
+    let EthernetChannel(tx, rx) = channel;
+    let tx = Box::new(PcapSender::new(tx, writer.clone()));
+    let rx = Box::new(PcapReceiver::new(rx, writer));
+    EthernetChannel(tx, rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn global_header_has_correct_magic_and_linktype() {
+        let buffer: Vec<u8> = Vec::new();
+        let writer = PcapWriter::new(buffer).unwrap();
+        let header = writer.writer;
+        assert_eq!(24, header.len());
+        assert_eq!([0xd4, 0xc3, 0xb2, 0xa1], header[0..4]);
+        assert_eq!([1, 0, 0, 0], header[20..24]);
+    }
+
+    #[test]
+    fn write_frame_appends_record_header_and_payload() {
+        let buffer: Vec<u8> = Vec::new();
+        let mut writer = PcapWriter::new(buffer).unwrap();
+        writer.write_frame(UNIX_EPOCH, &[1, 2, 3]).unwrap();
+
+        let data = writer.writer;
+        assert_eq!(24 + 16 + 3, data.len());
+        let incl_len = &data[24 + 8..24 + 12];
+        let orig_len = &data[24 + 12..24 + 16];
+        assert_eq!([3, 0, 0, 0], incl_len);
+        assert_eq!([3, 0, 0, 0], orig_len);
+        assert_eq!(&[1, 2, 3], &data[24 + 16..]);
+    }
+
+    /// Minimal `EthernetDataLinkSender` that just records the frames handed
+    /// to it, standing in for a real `pnet` sender so `PcapSender` can be
+    /// exercised without a live network adapter.
+    struct RecordingSender {
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl EthernetDataLinkSender for RecordingSender {
+        fn build_and_send(&mut self,
+                           num_packets: usize,
+                           packet_size: usize,
+                           func: &mut FnMut(MutableEthernetPacket))
+                           -> Option<io::Result<()>> {
+            for _ in 0..num_packets {
+                let mut buffer = vec![0; packet_size];
+                {
+                    let packet = MutableEthernetPacket::new(&mut buffer).unwrap();
+                    func(packet);
+                }
+                self.sent.lock().unwrap().push(buffer);
+            }
+            Some(Ok(()))
+        }
+    }
+
+    #[test]
+    fn pcap_sender_captures_frames_and_still_forwards_them() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let inner = Box::new(RecordingSender { sent: sent.clone() });
+        let writer = Arc::new(Mutex::new(PcapWriter::new(Vec::new()).unwrap()));
+        let mut sender = PcapSender::new(inner, writer.clone());
+
+        sender.build_and_send(1, 14, &mut |mut packet: MutableEthernetPacket| {
+            packet.packet_mut()[0] = 0xab;
+        });
+
+        assert_eq!(1, sent.lock().unwrap().len());
+        assert_eq!(0xab, sent.lock().unwrap()[0][0]);
+
+        let captured = writer.lock().unwrap().writer.clone();
+        assert_eq!(24 + 16 + 14, captured.len());
+        assert_eq!(0xab, captured[24 + 16]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::net::Ipv4Addr;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use pnet::packet::ip::IpNextHeaderProtocol;
+
+use {TxError, TxResult};
+#[cfg(not(feature = "unit-tests"))]
+use {NetworkStack, StackError, StackResult};
+
+use ethernet::EthernetTxImpl;
+use ipv4::Ipv4TxImpl;
+use tx::TxImpl;
+
+mod raw_rx;
+mod raw_tx;
+
+pub use self::raw_rx::{RawListener, RawListenerLookup, RawRx};
+pub use self::raw_tx::RawTx;
+
+use self::raw_rx::RawSocketReader;
+
+/// Concrete `RawTx` type handed out by `NetworkStack::raw_tx`. Named here so
+/// `RawSocket` does not have to spell out the full `Ipv4Tx`/`EthernetTx`
+/// stack every time, the same way `udp::UdpSocket` caches a concrete
+/// `UdpTx`.
+type StackRawTx = RawTx<Ipv4TxImpl<EthernetTxImpl<TxImpl>>>;
+
+/// A socket sending and receiving whole Ipv4 datagrams for a protocol this
+/// crate has no dedicated support for (e.g. Ospf, Gre, or a custom one),
+/// modeled on `UdpSocket` but keyed by `Ipv4Addr` alone since a raw socket
+/// has no port to demultiplex on.
+#[cfg(not(feature = "unit-tests"))]
+pub struct RawSocket {
+    local_ip: Ipv4Addr,
+    protocol: IpNextHeaderProtocol,
+    stack: Arc<Mutex<NetworkStack>>,
+    tx_cache: HashMap<Ipv4Addr, StackRawTx>,
+    rx: Option<RawSocketReader>,
+}
+
+#[cfg(not(feature = "unit-tests"))]
+impl RawSocket {
+    /// Binds a new `RawSocket` to `local_ip`, registering it with `stack`
+    /// to receive every Ipv4 datagram carrying `protocol` addressed there.
+    pub fn bind(local_ip: Ipv4Addr,
+                protocol: IpNextHeaderProtocol,
+                stack: Arc<Mutex<NetworkStack>>)
+                -> io::Result<RawSocket> {
+        let mut socket_reader = RawSocketReader::new();
+        {
+            let mut stack = stack.lock().unwrap();
+            stack.raw_listen(local_ip, protocol, socket_reader.listener())?;
+        }
+        Ok(RawSocket {
+            local_ip: local_ip,
+            protocol: protocol,
+            stack: stack,
+            tx_cache: HashMap::new(),
+            rx: Some(socket_reader),
+        })
+    }
+
+    /// The local address this socket is bound to.
+    pub fn local_addr(&self) -> Ipv4Addr {
+        self.local_ip
+    }
+
+    /// The protocol this socket is bound to.
+    pub fn protocol(&self) -> IpNextHeaderProtocol {
+        self.protocol
+    }
+
+    /// Blocks until a datagram carrying this socket's protocol arrives,
+    /// copying the full Ipv4 datagram -- header included -- into `buf`.
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, Ipv4Addr)> {
+        self.rx.as_ref().unwrap().recv(buf)
+    }
+
+    /// Sends `buf`, a caller-built Ipv4 datagram, to `dst`. Only the payload
+    /// following the header is kept; see `RawTx::send` for details.
+    pub fn send_to(&mut self, buf: &[u8], dst: Ipv4Addr) -> io::Result<usize> {
+        self.internal_send(buf, dst).map(|_| buf.len()).map_err(|e| e.into())
+    }
+
+    pub fn try_clone(&self) -> io::Result<RawSocket> {
+        Ok(RawSocket {
+            local_ip: self.local_ip,
+            protocol: self.protocol,
+            stack: self.stack.clone(),
+            tx_cache: HashMap::new(),
+            rx: None,
+        })
+    }
+
+    fn internal_send(&mut self, buf: &[u8], dst: Ipv4Addr) -> StackResult<()> {
+        match self.internal_send_on_cached_tx(buf, dst) {
+            Err(TxError::InvalidTx) => {
+                let new_raw_tx = {
+                    let mut stack = self.stack.lock().unwrap();
+                    stack.raw_tx(self.protocol, dst)?
+                };
+                self.tx_cache.insert(dst, new_raw_tx);
+                self.internal_send(buf, dst)
+            }
+            result => result.map_err(StackError::TxError),
+        }
+    }
+
+    fn internal_send_on_cached_tx(&mut self, buf: &[u8], dst: Ipv4Addr) -> TxResult {
+        if let Some(raw_tx) = self.tx_cache.get_mut(&dst) {
+            raw_tx.send(buf)
+        } else {
+            // No cached RawTx is treated as an existing but outdated one
+            Err(TxError::InvalidTx)
+        }
+    }
+}
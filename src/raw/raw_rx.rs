@@ -0,0 +1,153 @@
+use arc_swap::ArcSwap;
+
+use {RxError, RxResult};
+use ipv4::Ipv4Listener;
+
+use pnet::packet::Packet;
+use pnet::packet::ip::IpNextHeaderProtocol;
+use pnet::packet::ipv4::Ipv4Packet;
+
+use std::collections::HashMap;
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::SystemTime;
+
+pub trait RawListener: Send {
+    fn recv(&mut self, time: SystemTime, packet: &Ipv4Packet) -> (RxResult, bool);
+}
+
+/// Each listener is individually wrapped in its own `Mutex` so that
+/// `RawRx::recv` can dispatch via a lock-free `ArcSwap::load` and only ever
+/// has to take a fine grained, almost never contended, per-listener lock.
+pub type RawListenerLookup = HashMap<IpNextHeaderProtocol, Arc<Mutex<Box<RawListener>>>>;
+
+/// Listener and parser of arbitrary Ipv4 datagrams. Unlike `UdpRx`/`IcmpRx`/
+/// `TcpRx`, which are each registered for one fixed, well-known protocol, a
+/// single `RawRx` is registered under whatever protocol numbers
+/// `StackInterface::raw_listen` has bound a `RawSocket` to (e.g. Ospf, Gre,
+/// or a custom one), so it demultiplexes on `IpNextHeaderProtocol` the same
+/// way `UdpRx` demultiplexes on port.
+pub struct RawRx {
+    listeners: Arc<ArcSwap<RawListenerLookup>>,
+}
+
+impl RawRx {
+    pub fn new(listeners: Arc<ArcSwap<RawListenerLookup>>) -> RawRx {
+        RawRx { listeners: listeners }
+    }
+}
+
+impl Ipv4Listener for RawRx {
+    fn recv(&mut self, time: SystemTime, ip_pkg: Ipv4Packet) -> RxResult {
+        let protocol = ip_pkg.get_next_level_protocol();
+        let listeners = self.listeners.load();
+        if let Some(listener) = listeners.get(&protocol) {
+            let (result, _resume) = listener.lock().unwrap().recv(time, &ip_pkg);
+            result
+            // TODO: When resume turns false, remove this socket.
+        } else {
+            Err(RxError::NoListener(format!("Raw, no listener for protocol {:?}", protocol)))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RawSocketListener {
+    chan: mpsc::Sender<(SystemTime, Box<[u8]>)>,
+}
+
+impl RawListener for RawSocketListener {
+    fn recv(&mut self, time: SystemTime, packet: &Ipv4Packet) -> (RxResult, bool) {
+        let data = packet.packet().to_vec().into_boxed_slice();
+        let resume = self.chan.send((time, data)).is_ok();
+        (Ok(()), resume)
+    }
+}
+
+/// Read half of a `RawSocket`. Mirrors `UdpSocketReader`, but hands back
+/// the full Ipv4 datagram -- header included -- since a raw socket has no
+/// transport layer of its own to strip one off.
+pub struct RawSocketReader {
+    chan: mpsc::Receiver<(SystemTime, Box<[u8]>)>,
+    listener: RawSocketListener,
+}
+
+impl RawSocketReader {
+    pub fn new() -> RawSocketReader {
+        let (tx, rx) = mpsc::channel();
+        RawSocketReader {
+            chan: rx,
+            listener: RawSocketListener { chan: tx },
+        }
+    }
+
+    /// Blocks until a datagram for the bound protocol arrives, copying the
+    /// full Ipv4 datagram into `buf`.
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, Ipv4Addr)> {
+        let (_time, data) = self.chan.recv().unwrap();
+        let ip_pkg = Ipv4Packet::new(&data).unwrap();
+        let src = ip_pkg.get_source();
+        if data.len() > buf.len() {
+            Err(io::Error::new(io::ErrorKind::InvalidInput,
+                               "Data does not fit buffer".to_owned()))
+        } else {
+            buf[..data.len()].copy_from_slice(&data);
+            Ok((data.len(), src))
+        }
+    }
+
+    pub fn listener(&mut self) -> RawSocketListener {
+        self.listener.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pnet::packet::MutablePacket;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use pnet::packet::ipv4::MutableIpv4Packet;
+
+    #[test]
+    fn recv_dispatches_to_the_listener_registered_for_the_protocol() {
+        let size = Ipv4Packet::minimum_packet_size();
+        let mut buffer = vec![0u8; size];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(size as u16);
+            ip_pkg.set_next_level_protocol(IpNextHeaderProtocols::Igmp);
+        }
+        let ip_pkg = Ipv4Packet::owned(buffer).unwrap();
+
+        let mut reader = RawSocketReader::new();
+        let mut listeners = RawListenerLookup::new();
+        listeners.insert(IpNextHeaderProtocols::Igmp,
+                          Arc::new(Mutex::new(Box::new(reader.listener()) as Box<RawListener>)));
+        let mut raw_rx = RawRx::new(Arc::new(ArcSwap::new(Arc::new(listeners))));
+
+        assert!(raw_rx.recv(SystemTime::now(), ip_pkg).is_ok());
+
+        let mut buf = [0u8; 64];
+        assert!(reader.recv(&mut buf).is_ok());
+    }
+
+    #[test]
+    fn recv_reports_no_listener_for_an_unregistered_protocol() {
+        let size = Ipv4Packet::minimum_packet_size();
+        let mut buffer = vec![0u8; size];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(size as u16);
+            ip_pkg.set_next_level_protocol(IpNextHeaderProtocols::Gre);
+        }
+        let ip_pkg = Ipv4Packet::owned(buffer).unwrap();
+
+        let mut raw_rx = RawRx::new(Arc::new(ArcSwap::new(Arc::new(RawListenerLookup::new()))));
+
+        assert!(raw_rx.recv(SystemTime::now(), ip_pkg).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use {TxError, TxResult};
+use ipv4::{BasicIpv4Payload, Ipv4Tx};
+
+use pnet::packet::Packet;
+use pnet::packet::ip::IpNextHeaderProtocol;
+use pnet::packet::ipv4::Ipv4Packet;
+
+/// Sends pre-built Ipv4 datagrams bypassing the usual per-protocol Tx
+/// layers (`UdpTx`, `IcmpTx`, `TcpTx`), for protocols this crate has no
+/// dedicated support for (e.g. Ospf, Gre, or a custom one).
+pub struct RawTx<T: Ipv4Tx> {
+    protocol: IpNextHeaderProtocol,
+    ipv4: T,
+}
+
+impl<T: Ipv4Tx> RawTx<T> {
+    pub fn new(protocol: IpNextHeaderProtocol, ipv4: T) -> Self {
+        RawTx {
+            protocol: protocol,
+            ipv4: ipv4,
+        }
+    }
+
+    pub fn protocol(&self) -> IpNextHeaderProtocol {
+        self.protocol
+    }
+
+    /// Sends `buf`, a caller-built Ipv4 datagram (header included). Only the
+    /// payload following the header is kept; the source address,
+    /// identification, total length, fragmentation and checksum are always
+    /// re-derived by the underlying `Ipv4Tx` rather than trusting whatever
+    /// the caller happened to put there. The caller's Ttl and DSCP/ECN are
+    /// carried through, though, since those have no single correct value
+    /// for this `Ipv4Tx` to fall back on the way the others do. If `buf`
+    /// does not parse as an Ipv4 packet, or its declared version or next
+    /// level protocol does not match this socket's, it is silently dropped
+    /// instead of transmitted, the same "garbage in, garbage dropped"
+    /// semantics `smoltcp`'s raw socket uses. Returns
+    /// `TxError::TooLargePayload` if the embedded payload is too large to
+    /// fit a `u16` length field.
+    pub fn send(&mut self, buf: &[u8]) -> TxResult {
+        let ip_pkg = match Ipv4Packet::new(buf) {
+            Some(ip_pkg) => ip_pkg,
+            None => return Ok(()),
+        };
+        if ip_pkg.get_version() != 4 || ip_pkg.get_next_level_protocol() != self.protocol {
+            return Ok(());
+        }
+        let payload = ip_pkg.payload().to_vec();
+        if payload.len() > ::std::u16::MAX as usize {
+            return Err(TxError::TooLargePayload);
+        }
+        self.ipv4.set_ttl(ip_pkg.get_ttl());
+        self.ipv4.set_dscp(ip_pkg.get_dscp());
+        self.ipv4.set_ecn(ip_pkg.get_ecn());
+        let payload = BasicIpv4Payload::new(self.protocol, payload);
+        self.ipv4.send(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TxResult;
+    use checksum::ChecksumCapabilities;
+    use ipv4::Ipv4Payload;
+
+    use pnet::packet::MutablePacket;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use pnet::packet::ipv4::MutableIpv4Packet;
+
+    use std::net::Ipv4Addr;
+    use std::sync::mpsc;
+
+    struct MockIpv4Tx {
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        chan: mpsc::Sender<Box<[u8]>>,
+    }
+
+    impl MockIpv4Tx {
+        fn new() -> (Self, mpsc::Receiver<Box<[u8]>>) {
+            let (tx, rx) = mpsc::channel();
+            let mock = MockIpv4Tx {
+                src: Ipv4Addr::new(10, 0, 0, 1),
+                dst: Ipv4Addr::new(10, 0, 0, 2),
+                chan: tx,
+            };
+            (mock, rx)
+        }
+    }
+
+    impl Ipv4Tx for MockIpv4Tx {
+        fn src(&self) -> Ipv4Addr {
+            self.src
+        }
+
+        fn dst(&self) -> Ipv4Addr {
+            self.dst
+        }
+
+        fn checksums(&self) -> ChecksumCapabilities {
+            ChecksumCapabilities::default()
+        }
+
+        fn send<P: Ipv4Payload>(&mut self, mut payload: P) -> TxResult {
+            let mut buffer = vec![0; payload.len()];
+            payload.build(&mut buffer);
+            self.chan.send(buffer.into_boxed_slice()).unwrap();
+            Ok(())
+        }
+    }
+
+    fn ipv4_datagram(protocol: IpNextHeaderProtocol, payload: &[u8]) -> Vec<u8> {
+        let size = Ipv4Packet::minimum_packet_size() + payload.len();
+        let mut buffer = vec![0u8; size];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_version(4);
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(size as u16);
+            ip_pkg.set_next_level_protocol(protocol);
+            ip_pkg.set_payload(payload);
+        }
+        buffer
+    }
+
+    #[test]
+    fn send_forwards_the_payload_of_a_matching_protocol() {
+        let (ipv4_tx, rx) = MockIpv4Tx::new();
+        let mut testee = RawTx::new(IpNextHeaderProtocols::Igmp, ipv4_tx);
+
+        let data = [1, 2, 3, 4];
+        let buf = ipv4_datagram(IpNextHeaderProtocols::Igmp, &data);
+        testee.send(&buf).unwrap();
+
+        let sent = rx.try_recv().unwrap();
+        assert_eq!(&data[..], &sent[..]);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn send_drops_a_datagram_with_a_different_protocol() {
+        let (ipv4_tx, rx) = MockIpv4Tx::new();
+        let mut testee = RawTx::new(IpNextHeaderProtocols::Igmp, ipv4_tx);
+
+        let buf = ipv4_datagram(IpNextHeaderProtocols::Gre, &[1, 2, 3]);
+        testee.send(&buf).unwrap();
+
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn send_drops_a_buffer_that_does_not_parse_as_ipv4() {
+        let (ipv4_tx, rx) = MockIpv4Tx::new();
+        let mut testee = RawTx::new(IpNextHeaderProtocols::Igmp, ipv4_tx);
+
+        testee.send(&[]).unwrap();
+
+        assert!(rx.try_recv().is_err());
+    }
+}
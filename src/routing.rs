@@ -5,12 +5,12 @@ use ipnetwork::Ipv4Network;
 use std::collections::BTreeMap;
 use std::net::Ipv4Addr;
 
-// TODO: Add metric
 #[derive(Debug)]
 struct RouteEntry {
     pub net: Ipv4Network,
     pub gw: Option<Ipv4Addr>,
     pub interface: Interface,
+    pub metric: u32,
 }
 
 #[derive(Default)]
@@ -26,21 +26,34 @@ impl RoutingTable {
     // TODO: Check for collision
     // TODO: Increment Tx version counter
     pub fn add_route(&mut self, net: Ipv4Network, gw: Option<Ipv4Addr>, interface: Interface) {
+        self.add_route_with_metric(net, gw, interface, 0);
+    }
+
+    /// Like `add_route`, but with an explicit metric. Among routes whose
+    /// networks match an address at the same (longest) prefix length,
+    /// `route` prefers the one with the lowest metric, letting callers
+    /// express a preferred vs. backup gateway for otherwise-overlapping
+    /// routes (e.g. two default routes out different interfaces).
+    pub fn add_route_with_metric(&mut self,
+                                  net: Ipv4Network,
+                                  gw: Option<Ipv4Addr>,
+                                  interface: Interface,
+                                  metric: u32) {
         let prefix = net.prefix();
         let entry = RouteEntry {
             net: net,
             gw: gw,
             interface: interface,
+            metric: metric,
         };
         self.table.entry(prefix).or_insert(vec![]).push(entry);
     }
 
     pub fn route(&self, ip: Ipv4Addr) -> Option<(Option<Ipv4Addr>, Interface)> {
         for (_prefix, entries) in self.table.iter().rev() {
-            for entry in entries {
-                if entry.net.contains(ip) {
-                    return Some((entry.gw, entry.interface.clone()));
-                }
+            let best = entries.iter().filter(|entry| entry.net.contains(ip)).min_by_key(|entry| entry.metric);
+            if let Some(entry) = best {
+                return Some((entry.gw, entry.interface.clone()));
             }
         }
         None
@@ -113,6 +126,26 @@ mod tests {
         assert_eq!(out_eth2, iface("eth1"));
     }
 
+    #[test]
+    fn lowest_metric_wins_on_overlapping_prefix() {
+        let gw1 = Ipv4Addr::new(10, 0, 0, 1);
+        let gw2 = Ipv4Addr::new(10, 0, 0, 2);
+
+        let mut table = RoutingTable::new();
+        table.add_route_with_metric(Ipv4Network::from_cidr("0/0").unwrap(),
+                                     Some(gw1),
+                                     iface("eth0"),
+                                     10);
+        table.add_route_with_metric(Ipv4Network::from_cidr("0/0").unwrap(),
+                                     Some(gw2),
+                                     iface("eth1"),
+                                     5);
+
+        let (out_gw, out_eth) = table.route(Ipv4Addr::new(192, 168, 0, 0)).unwrap();
+        assert_eq!(out_gw, Some(gw2));
+        assert_eq!(out_eth, iface("eth1"));
+    }
+
     fn iface(name: &str) -> Interface {
         Interface {
             name: name.to_string(),
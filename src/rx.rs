@@ -1,8 +1,10 @@
 use RxResult;
 
 use pnet::datalink::EthernetDataLinkReceiver;
+use pnet::packet::Packet;
 use pnet::packet::ethernet::EthernetPacket;
 
+use std::io;
 use std::thread;
 use std::time::SystemTime;
 
@@ -22,6 +24,10 @@ pub fn spawn<L>(receiver: Box<EthernetDataLinkReceiver>, listener: L)
 struct RxThread<L: RxListener> {
     receiver: Box<EthernetDataLinkReceiver>,
     listener: L,
+    /// Number of frames dropped so far for being too short to even hold an
+    /// Ethernet header. Only ever grows; logged alongside every new drop so
+    /// an operator can tell a flood of garbage from a one-off glitch.
+    malformed_frames: u64,
 }
 
 impl<L: RxListener> RxThread<L> {
@@ -29,6 +35,7 @@ impl<L: RxListener> RxThread<L> {
         RxThread {
             receiver: receiver,
             listener: listener,
+            malformed_frames: 0,
         }
     }
 
@@ -37,13 +44,93 @@ impl<L: RxListener> RxThread<L> {
         loop {
             match rx_iter.next() {
                 Ok(packet) => {
+                    if !Self::check_len(&packet) {
+                        self.malformed_frames += 1;
+                        warn!("RxThread: dropping frame shorter than an Ethernet header ({} \
+                               bytes, {} malformed frames so far)",
+                              packet.packet().len(),
+                              self.malformed_frames);
+                        continue;
+                    }
                     let time = SystemTime::now();
                     if let Err(e) = self.listener.recv(time, &packet) {
                         warn!("RxError: {:?}", e);
                     }
                 }
-                Err(e) => panic!("RxThread crash: {}", e),
+                Err(ref e) if Self::is_transient(e) => {
+                    warn!("RxThread: transient receive error, retrying: {}", e);
+                }
+                Err(e) => {
+                    warn!("RxThread: unrecoverable receive error, stopping: {}", e);
+                    break;
+                }
             }
         }
     }
+
+    /// Verifies `packet` is at least long enough to hold a minimal Ethernet
+    /// header before anything downstream is allowed to touch it.
+    fn check_len(packet: &EthernetPacket) -> bool {
+        packet.packet().len() >= EthernetPacket::minimum_packet_size()
+    }
+
+    /// Whether `e` is the kind of hiccup a raw socket read can recover from
+    /// on the next call, as opposed to the backing device being gone for
+    /// good.
+    fn is_transient(e: &io::Error) -> bool {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted | io::ErrorKind::TimedOut => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pnet::packet::ethernet::MutableEthernetPacket;
+
+    use std::io;
+
+    use super::*;
+
+    type TestRxThread = RxThread<NullListener>;
+
+    struct NullListener;
+
+    impl RxListener for NullListener {
+        fn recv(&mut self, _time: SystemTime, _packet: &EthernetPacket) -> RxResult {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn check_len_accepts_a_full_header() {
+        let mut buffer = vec![0; EthernetPacket::minimum_packet_size()];
+        let packet = MutableEthernetPacket::new(&mut buffer).unwrap();
+        assert!(TestRxThread::check_len(&packet.to_immutable()));
+    }
+
+    #[test]
+    fn a_buffer_shorter_than_a_header_cannot_even_become_a_packet() {
+        // `EthernetPacket::new` itself refuses to hand out a packet backed
+        // by too little data, so `check_len` is a second line of defense in
+        // case a datalink backend is ever less careful than that.
+        let buffer = vec![0; EthernetPacket::minimum_packet_size() - 1];
+        assert!(EthernetPacket::new(&buffer[..]).is_none());
+    }
+
+    #[test]
+    fn is_transient_retries_recoverable_errors() {
+        assert!(TestRxThread::is_transient(&io::Error::new(io::ErrorKind::WouldBlock, "x")));
+        assert!(TestRxThread::is_transient(&io::Error::new(io::ErrorKind::Interrupted, "x")));
+        assert!(TestRxThread::is_transient(&io::Error::new(io::ErrorKind::TimedOut, "x")));
+    }
+
+    #[test]
+    fn is_transient_gives_up_on_other_errors() {
+        assert!(!TestRxThread::is_transient(&io::Error::new(io::ErrorKind::NotFound, "x")));
+        assert!(!TestRxThread::is_transient(&io::Error::new(io::ErrorKind::Other, "x")));
+    }
 }
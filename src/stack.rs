@@ -1,41 +1,80 @@
 use ::{EthernetChannel, Interface, RoutingTable, TxError};
-use ::arp::{self, ArpTx, TableData};
-use ::ethernet::{EthernetRx, EthernetTxImpl};
+use ::arp::{self, ArpReplyTx, ArpTx, TableData};
+use ::ethernet::{DropCounts, EthernetRx, EthernetTxImpl};
 use ::tx::{TxBarrier, TxImpl};
 use ::ipv4::{self, Ipv4TxImpl};
-use ::icmp::{self, IcmpTx};
+use ::icmp::{self, IcmpError, IcmpListenerKey, IcmpTx};
+use ::igmp::{self, IgmpTx};
 use ::udp::{self, UdpTx};
+use ::tcp::{self, TcpTx};
+use ::raw::{self, RawTx};
 use ::util;
 use ::rx;
 
+use arc_swap::ArcSwap;
+
 use ipnetwork::Ipv4Network;
 
-use pnet::packet::icmp::IcmpType;
-use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::Packet;
+use pnet::packet::icmp::IcmpTypes;
+use pnet::packet::icmp::echo_request::EchoRequestPacket;
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::tcp::{TcpFlags, TcpPacket};
 use pnet::util::MacAddr;
 
 use rand;
 use rand::distributions::{IndependentSample, Range};
 
+use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry;
 use std::io;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs};
 use std::sync::{Arc, Mutex};
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
 use std::thread;
+use std::time::{Duration, Instant};
 
 
 pub static DEFAULT_MTU: usize = 1500;
 pub static LOCAL_PORT_RANGE_START: u16 = 32768;
 pub static LOCAL_PORT_RANGE_END: u16 = 61000;
 
+/// How many times in all `StackInterface::resolve` waits for an Arp reply,
+/// (re)sending the request before each wait, before giving up with
+/// `StackError::NoRouteToHost`.
+const ARP_RESOLVE_MAX_ATTEMPTS: u32 = 4;
+
+/// How long `StackInterface::resolve` waits for the first Arp reply before
+/// retransmitting the request.
+fn arp_resolve_initial_timeout() -> Duration {
+    Duration::from_secs(1)
+}
+
+/// The cap `arp_resolve_initial_timeout` is doubled up to between
+/// retransmissions.
+fn arp_resolve_max_timeout() -> Duration {
+    Duration::from_secs(4)
+}
+
+/// How long `StackInterface::probe_ipv4` waits for a reply to an Arp probe
+/// before concluding no other host has the address and it is safe to claim.
+fn arp_probe_timeout() -> Duration {
+    Duration::from_secs(1)
+}
+
 /// Error returned upon invalid usage or state of the stack.
 #[derive(Debug)]
 pub enum StackError {
     IllegalArgument,
     NoRouteToHost,
     InvalidInterface,
+    /// `StackInterface::add_ipv4` probed the address being added and
+    /// another host already answered for it, carrying the address and the
+    /// MAC it was claimed by.
+    AddressConflict(Ipv4Addr, MacAddr),
     TxError(TxError),
     IoError(io::Error),
 }
@@ -59,6 +98,9 @@ impl From<StackError> for io::Error {
             StackError::IllegalArgument => other("Illegal argument".to_owned()),
             StackError::NoRouteToHost => other("No route to host".to_owned()),
             StackError::InvalidInterface => other("Invalid interface".to_owned()),
+            StackError::AddressConflict(ip, mac) => {
+                other(format!("{} is already in use by {}", ip, mac))
+            }
             StackError::IoError(io_e) => io_e,
             StackError::TxError(txe) => txe.into(),
         }
@@ -67,30 +109,67 @@ impl From<StackError> for io::Error {
 
 pub type StackResult<T> = Result<T, StackError>;
 
+#[derive(Debug)]
 pub enum StackInterfaceMsg {
     UpdateArpTable(Ipv4Addr, MacAddr),
     ArpRequest(Ipv4Addr, MacAddr, Ipv4Addr),
+    /// Reported by `Ipv4Rx` whenever a datagram addressed to us had no
+    /// listener for its next level protocol or port. Carries the error to
+    /// reply with, the address to send it from (us), the address to send
+    /// it to (the original sender) and the offending datagram's raw bytes.
+    IcmpUnreachable(IcmpError, Ipv4Addr, Ipv4Addr, Vec<u8>),
+    /// Reported by `icmp::EchoResponder` on an incoming Echo Request
+    /// addressed to us. Carries the address to send the reply from (us),
+    /// the address to send it to (the original sender) and the request's
+    /// raw IPv4 datagram bytes, so the thread can copy its identifier,
+    /// sequence number and payload verbatim into the reply.
+    IcmpEchoReply(Ipv4Addr, Ipv4Addr, Vec<u8>),
+    /// Reported by `TcpRx` whenever a segment addressed to us had no
+    /// listener for its destination port. Carries the offending segment's
+    /// raw IPv4 datagram bytes, so the thread can parse it, swap
+    /// source/destination and reply with a RST.
+    TcpRst(Vec<u8>),
+    /// Reported by `IgmpRx` on an incoming Membership Query. Carries the
+    /// group being queried (`0.0.0.0` for a General Query) and the Max
+    /// Resp Time from the packet, in units of 1/10 second.
+    IgmpQuery(Ipv4Addr, u8),
+    /// Reported by `IgmpRx` on an incoming Membership Report from another
+    /// host, so our own pending report for the same group can be
+    /// suppressed.
+    IgmpReportSeen(Ipv4Addr),
     Shutdown,
 }
 
 struct StackInterfaceThread {
     queue: Receiver<StackInterfaceMsg>,
     arp_table: Arc<Mutex<TableData>>,
+    igmp_table: Arc<Mutex<igmp::IgmpTable>>,
     ipv4_addresses: Arc<Mutex<HashSet<Ipv4Addr>>>,
     tx: Arc<Mutex<TxBarrier>>,
+    own_mac: MacAddr,
+    mtu: usize,
+    icmp_error_replies: Arc<AtomicBool>,
 }
 
 impl StackInterfaceThread {
     pub fn spawn(arp_table: Arc<Mutex<TableData>>,
+                 igmp_table: Arc<Mutex<igmp::IgmpTable>>,
                  ipv4_addresses: Arc<Mutex<HashSet<Ipv4Addr>>>,
-                 tx: Arc<Mutex<TxBarrier>>)
+                 tx: Arc<Mutex<TxBarrier>>,
+                 own_mac: MacAddr,
+                 mtu: usize,
+                 icmp_error_replies: Arc<AtomicBool>)
                  -> Sender<StackInterfaceMsg> {
         let (thread_handle, rx) = mpsc::channel();
         let stack_interface_thread = StackInterfaceThread {
             queue: rx,
             arp_table: arp_table,
+            igmp_table: igmp_table,
             ipv4_addresses: ipv4_addresses,
             tx: tx,
+            own_mac: own_mac,
+            mtu: mtu,
+            icmp_error_replies: icmp_error_replies,
         };
         thread::spawn(move || {
             stack_interface_thread.run();
@@ -114,6 +193,13 @@ impl StackInterfaceThread {
             ArpRequest(sender_ip, sender_mac, target_ip) => {
                 self.arp_request(sender_ip, sender_mac, target_ip)
             },
+            IcmpUnreachable(error, src, dst, orig_pkg) => {
+                self.send_icmp_unreachable(error, src, dst, orig_pkg)
+            },
+            IcmpEchoReply(src, dst, orig_pkg) => self.send_icmp_echo_reply(src, dst, orig_pkg),
+            TcpRst(orig_pkg) => self.send_tcp_rst(orig_pkg),
+            IgmpQuery(group, max_resp_time) => self.igmp_query_received(group, max_resp_time),
+            IgmpReportSeen(group) => self.igmp_report_seen(group),
             Shutdown => return false,
         }
         true
@@ -121,30 +207,158 @@ impl StackInterfaceThread {
 
     fn update_arp(&mut self, ip: Ipv4Addr, mac: MacAddr) {
         let mut data = self.arp_table.lock().unwrap();
-        let old_mac = data.table.insert(ip, mac);
-        if old_mac.is_none() || old_mac != Some(mac) {
+        let now = Instant::now();
+        let changed = data.insert(ip, mac, now, Duration::new(arp::DEFAULT_ENTRY_TTL_SECS, 0));
+        if changed {
             // The new MAC is different from the old one, bump tx VersionedTx
             self.tx.lock().unwrap().inc();
         }
-        if let Some(listeners) = data.listeners.remove(&ip) {
-            for listener in listeners {
-                listener.send(mac).unwrap_or(());
-            }
+    }
+
+    /// Replies to an incoming Arp request for `target_ip` if it is one of
+    /// our own addresses, and opportunistically learns the requester's
+    /// `(sender_ip, sender_mac)` along the way, the same as a real host
+    /// would since the requester will need to resolve us right back.
+    fn arp_request(&mut self, sender_ip: Ipv4Addr, sender_mac: MacAddr, target_ip: Ipv4Addr) {
+        let is_ours = self.ipv4_addresses.lock().unwrap().contains(&target_ip);
+        if !is_ours {
+            return;
         }
+        debug!("Incoming Arp request for me!! {}", target_ip);
+        self.update_arp(sender_ip, sender_mac);
+        let version = self.tx.lock().unwrap().version();
+        let tx_impl = TxImpl::new(self.tx.clone(), version);
+        let ethernet_tx = EthernetTxImpl::new(tx_impl, self.own_mac, sender_mac);
+        let mut arp_tx = ArpReplyTx::new(ethernet_tx);
+        arp_tx.send(target_ip, sender_mac, sender_ip).unwrap_or(());
     }
 
-    fn arp_request(&mut self, _sender_ip: Ipv4Addr, _sender_mac: MacAddr, target_ip: Ipv4Addr) {
-        let ipv4_addresses = self.ipv4_addresses.lock().unwrap();
-        if ipv4_addresses.contains(&target_ip) {
-            debug!("Incoming Arp request for me!! {}", target_ip);
+    /// Builds and sends the Icmp error packet `error` from `src` to `dst`,
+    /// embedding `orig_pkg`. Silently does nothing if error replies are
+    /// disabled, if `dst`'s MAC is not already in the Arp table (we will
+    /// not block this thread on a resolution just to report an error), if
+    /// `src` (the offending datagram's original destination) was a
+    /// broadcast or multicast address, or if the offending datagram was
+    /// itself an Icmp packet. Per RFC 1122 §3.2.2 an Icmp error must never
+    /// be generated for a non-unicast destination, nor in reply to another
+    /// Icmp error, to avoid broadcast storms (the `test_no_icmp_no_unicast`
+    /// behavior smoltcp's test suite checks for).
+    fn send_icmp_unreachable(&mut self, error: IcmpError, src: Ipv4Addr, dst: Ipv4Addr, orig_pkg: Vec<u8>) {
+        if !self.icmp_error_replies.load(Ordering::Relaxed) {
+            return;
         }
+        if src.is_broadcast() || src.is_multicast() {
+            return;
+        }
+        let orig_pkg = match Ipv4Packet::new(&orig_pkg) {
+            Some(orig_pkg) => orig_pkg,
+            None => return,
+        };
+        if orig_pkg.get_next_level_protocol() == IpNextHeaderProtocols::Icmp {
+            return;
+        }
+        let dst_mac = match self.arp_table.lock().unwrap().table.get(&dst) {
+            Some(entry) => entry.mac,
+            None => return,
+        };
+        let version = self.tx.lock().unwrap().version();
+        let tx_impl = TxImpl::new(self.tx.clone(), version);
+        let ethernet_tx = EthernetTxImpl::new(tx_impl, self.own_mac, dst_mac);
+        let ipv4_tx = Ipv4TxImpl::new(ethernet_tx, src, dst, self.mtu);
+        let mut icmp_tx = IcmpTx::new(ipv4_tx);
+        icmp_tx.send_error(error, &orig_pkg).unwrap_or(());
+    }
+
+    /// Builds and sends an Echo Reply answering the Echo Request embedded
+    /// in `orig_pkg`, copying its identifier, sequence number and payload
+    /// verbatim, the way a real host echoes a ping straight back to its
+    /// sender. Silently does nothing if `dst`'s MAC is not already in the
+    /// Arp table (we will not block this thread on a resolution just to
+    /// answer a ping) or if `orig_pkg` does not actually parse as an Echo
+    /// Request.
+    fn send_icmp_echo_reply(&mut self, src: Ipv4Addr, dst: Ipv4Addr, orig_pkg: Vec<u8>) {
+        let orig_pkg = match Ipv4Packet::new(&orig_pkg) {
+            Some(orig_pkg) => orig_pkg,
+            None => return,
+        };
+        let echo_request = match EchoRequestPacket::new(orig_pkg.payload()) {
+            Some(echo_request) => echo_request,
+            None => return,
+        };
+        let dst_mac = match self.arp_table.lock().unwrap().table.get(&dst) {
+            Some(entry) => entry.mac,
+            None => return,
+        };
+        let version = self.tx.lock().unwrap().version();
+        let tx_impl = TxImpl::new(self.tx.clone(), version);
+        let ethernet_tx = EthernetTxImpl::new(tx_impl, self.own_mac, dst_mac);
+        let ipv4_tx = Ipv4TxImpl::new(ethernet_tx, src, dst, self.mtu);
+        let mut icmp_tx = IcmpTx::new(ipv4_tx);
+        icmp_tx.send_echo_reply(echo_request.get_identifier(),
+                                 echo_request.get_sequence_number(),
+                                 echo_request.payload())
+            .unwrap_or(());
+    }
+
+    /// Builds and sends a RST in response to the Tcp segment embedded in
+    /// `orig_pkg`, per RFC 793: if the segment has ACK set, the reply's
+    /// sequence number is the segment's acknowledgement number and ACK is
+    /// left unset; otherwise the reply acknowledges the segment's sequence
+    /// number plus its length, with ACK set and sequence number zero.
+    /// Silently does nothing if `dst`'s MAC is not already in the Arp table
+    /// (we will not block this thread on a resolution just to reply to a
+    /// segment nothing is listening for).
+    fn send_tcp_rst(&mut self, orig_pkg: Vec<u8>) {
+        let ip_pkg = match Ipv4Packet::new(&orig_pkg) {
+            Some(ip_pkg) => ip_pkg,
+            None => return,
+        };
+        let tcp_pkg = match TcpPacket::new(ip_pkg.payload()) {
+            Some(tcp_pkg) => tcp_pkg,
+            None => return,
+        };
+        let (src, dst) = (ip_pkg.get_destination(), ip_pkg.get_source());
+        let dst_mac = match self.arp_table.lock().unwrap().table.get(&dst) {
+            Some(entry) => entry.mac,
+            None => return,
+        };
+        let (seq, ack, flags) = if tcp_pkg.get_flags() & TcpFlags::ACK != 0 {
+            (tcp_pkg.get_acknowledgement(), 0, TcpFlags::RST)
+        } else {
+            let segment_len = tcp_pkg.payload().len() as u32;
+            (0, tcp_pkg.get_sequence().wrapping_add(segment_len), TcpFlags::RST | TcpFlags::ACK)
+        };
+        let version = self.tx.lock().unwrap().version();
+        let tx_impl = TxImpl::new(self.tx.clone(), version);
+        let ethernet_tx = EthernetTxImpl::new(tx_impl, self.own_mac, dst_mac);
+        let ipv4_tx = Ipv4TxImpl::new(ethernet_tx, src, dst, self.mtu);
+        let mut tcp_tx = TcpTx::new(ipv4_tx);
+        let (src_port, dst_port) = (tcp_pkg.get_destination(), tcp_pkg.get_source());
+        tcp_tx.send(src_port, dst_port, seq, ack, flags).unwrap_or(());
+    }
+
+    /// Records that a Membership Query was seen for `group` (`0.0.0.0` for
+    /// a General Query), scheduling our own delayed report for it. The
+    /// report itself is sent later, when the application calls
+    /// `StackInterface::igmp_flush_due_reports`.
+    fn igmp_query_received(&mut self, group: Ipv4Addr, max_resp_time: u8) {
+        self.igmp_table.lock().unwrap().query_received(group, max_resp_time);
+    }
+
+    /// Records that another host already reported `group`, suppressing our
+    /// own pending report for it, if any.
+    fn igmp_report_seen(&mut self, group: Ipv4Addr) {
+        self.igmp_table.lock().unwrap().report_seen(group);
     }
 }
 
 struct Ipv4Data {
     net: Ipv4Network,
-    udp_listeners: Arc<Mutex<udp::UdpListenerLookup>>,
-    icmp_listeners: Arc<Mutex<icmp::IcmpListenerLookup>>,
+    udp_listeners: Arc<ArcSwap<udp::UdpListenerLookup>>,
+    icmp_listeners: Arc<ArcSwap<icmp::IcmpListenerLookup>>,
+    tcp_listeners: Arc<ArcSwap<tcp::TcpListenerLookup>>,
+    raw_listeners: Arc<ArcSwap<raw::RawListenerLookup>>,
+    raw_listener: Arc<Mutex<Box<ipv4::Ipv4Listener>>>,
 }
 
 /// Represents the stack on one physical interface.
@@ -155,40 +369,66 @@ pub struct StackInterface {
     thread_handle: Sender<StackInterfaceMsg>,
     tx: Arc<Mutex<TxBarrier>>,
     arp_table: arp::ArpTable,
+    igmp_table: Arc<Mutex<igmp::IgmpTable>>,
     ipv4_addresses: Arc<Mutex<HashSet<Ipv4Addr>>>,
     ipv4_datas: HashMap<Ipv4Addr, Ipv4Data>,
-    ipv4_listeners: Arc<Mutex<ipv4::IpListenerLookup>>,
+    ipv4_listeners: Arc<ArcSwap<ipv4::IpListenerLookup>>,
+    icmp_error_replies: Arc<AtomicBool>,
+    drop_counts: Arc<DropCounts>,
 }
 
 impl StackInterface {
+    /// Creates a new `StackInterface` assuming the default MTU. Use
+    /// `with_mtu` instead for a backend, like a TAP device, that can report
+    /// its own real MTU.
     pub fn new(interface: Interface, channel: EthernetChannel) -> StackInterface {
+        Self::with_mtu(interface, channel, DEFAULT_MTU)
+    }
+
+    /// Creates a new `StackInterface` like `new`, but with `mtu` instead of
+    /// assuming `DEFAULT_MTU`.
+    pub fn with_mtu(interface: Interface, channel: EthernetChannel, mtu: usize) -> StackInterface {
         let sender = channel.0;
         let receiver = channel.1;
 
         let arp_table = arp::ArpTable::new();
+        let igmp_table = Arc::new(Mutex::new(igmp::IgmpTable::new()));
         let ipv4_addresses = Arc::new(Mutex::new(HashSet::new()));
+        let icmp_error_replies = Arc::new(AtomicBool::new(true));
 
         let tx = Arc::new(Mutex::new(TxBarrier::new(sender)));
-        let thread_handle = StackInterfaceThread::spawn(arp_table.data(), ipv4_addresses.clone(), tx.clone());
+        let thread_handle = StackInterfaceThread::spawn(arp_table.data(),
+                                                          igmp_table.clone(),
+                                                          ipv4_addresses.clone(),
+                                                          tx.clone(),
+                                                          interface.mac,
+                                                          mtu,
+                                                          icmp_error_replies.clone());
 
         let arp_rx = arp_table.arp_rx(thread_handle.clone());
 
-        let ipv4_listeners = Arc::new(Mutex::new(HashMap::new()));
-        let ipv4_rx = ipv4::Ipv4Rx::new(ipv4_listeners.clone());
+        let ipv4_listeners = Arc::new(ArcSwap::new(Arc::new(HashMap::new())));
+        let ipv4_rx = ipv4::Ipv4Rx::new(ipv4_listeners.clone(), thread_handle.clone());
 
-        let ethernet_listeners = vec![arp_rx, ipv4_rx];
+        let ethernet_listeners = Arc::new(ArcSwap::new(Arc::new(HashMap::new())));
+        EthernetRx::add_listener(&ethernet_listeners, arp_rx);
+        EthernetRx::add_listener(&ethernet_listeners, ipv4_rx);
         let ethernet_rx = EthernetRx::new(ethernet_listeners);
+        let drop_counts = ethernet_rx.drop_counts();
         rx::spawn(receiver, ethernet_rx);
 
         StackInterface {
             interface: interface,
-            mtu: DEFAULT_MTU,
+            mtu: mtu,
             thread_handle: thread_handle,
             tx: tx,
             arp_table: arp_table,
+            igmp_table: igmp_table,
             ipv4_addresses: ipv4_addresses,
             ipv4_datas: HashMap::new(),
             ipv4_listeners: ipv4_listeners,
+            icmp_error_replies: icmp_error_replies,
+            drop_counts: drop_counts,
         }
     }
 
@@ -214,52 +454,179 @@ impl StackInterface {
         &mut self.arp_table
     }
 
+    /// Attaches `ip_net` to this interface. Before the address is installed,
+    /// probes the network for `ip_net.ip()` (RFC 5227 duplicate address
+    /// detection) and fails with `StackError::AddressConflict` if another
+    /// host answers; otherwise announces the new address with a gratuitous
+    /// Arp once it is installed, so neighbors with a stale cache entry for
+    /// it (e.g. from a previous owner) refresh it immediately instead of
+    /// waiting for it to expire.
     pub fn add_ipv4(&mut self, ip_net: Ipv4Network) -> StackResult<()> {
         let ip = ip_net.ip();
+        if self.ipv4_datas.contains_key(&ip) {
+            return Err(StackError::IllegalArgument);
+        }
+        self.probe_ipv4(ip)?;
         match self.ipv4_datas.entry(ip) {
             Entry::Occupied(_) => Err(StackError::IllegalArgument),
             Entry::Vacant(entry) => {
                 let mut proto_listeners = HashMap::new();
 
-                let udp_listeners = Arc::new(Mutex::new(HashMap::new()));
-                let udp_rx = udp::UdpRx::new(udp_listeners.clone());
+                let udp_listeners = Arc::new(ArcSwap::new(Arc::new(HashMap::new())));
+                let udp_rx = udp::UdpRx::new(udp_listeners.clone(), self.thread_handle.clone());
                 let udp_ipv4_listener = Box::new(udp_rx) as Box<ipv4::Ipv4Listener>;
-                proto_listeners.insert(IpNextHeaderProtocols::Udp, udp_ipv4_listener);
+                proto_listeners.insert(IpNextHeaderProtocols::Udp, Arc::new(Mutex::new(udp_ipv4_listener)));
 
-                let icmp_listeners = Arc::new(Mutex::new(HashMap::new()));
+                // Every address auto-answers Echo Requests with a matching
+                // Echo Reply, the same as a real host, so embedders do not
+                // each have to wire up their own ping responder.
+                let echo_responder = icmp::EchoResponder::new(self.thread_handle.clone());
+                let echo_responder = Box::new(echo_responder) as Box<icmp::IcmpListener>;
+                let mut initial_icmp_listeners = HashMap::new();
+                initial_icmp_listeners.insert(icmp::IcmpListenerKey::Type(IcmpTypes::EchoRequest),
+                                               vec![Arc::new(Mutex::new(echo_responder))]);
+                let icmp_listeners = Arc::new(ArcSwap::new(Arc::new(initial_icmp_listeners)));
                 let icmp_rx = icmp::IcmpRx::new(icmp_listeners.clone());
                 let icmp_listener = Box::new(icmp_rx) as Box<ipv4::Ipv4Listener>;
-                proto_listeners.insert(IpNextHeaderProtocols::Icmp, icmp_listener);
-                {
-                    let mut ipv4_listeners = self.ipv4_listeners.lock().unwrap();
-                    ipv4_listeners.insert(ip, proto_listeners);
-                }
+                proto_listeners.insert(IpNextHeaderProtocols::Icmp, Arc::new(Mutex::new(icmp_listener)));
+
+                let tcp_listeners = Arc::new(ArcSwap::new(Arc::new(HashMap::new())));
+                let tcp_rx = tcp::TcpRx::new(tcp_listeners.clone(), self.thread_handle.clone());
+                let tcp_listener = Box::new(tcp_rx) as Box<ipv4::Ipv4Listener>;
+                proto_listeners.insert(IpNextHeaderProtocols::Tcp, Arc::new(Mutex::new(tcp_listener)));
+
+                let igmp_rx = igmp::IgmpRx::new(self.thread_handle.clone());
+                let igmp_listener = Box::new(igmp_rx) as Box<ipv4::Ipv4Listener>;
+                let igmp_listener = Arc::new(Mutex::new(igmp_listener));
+                proto_listeners.insert(IpNextHeaderProtocols::Igmp, igmp_listener.clone());
+
+                // `RawRx` itself demultiplexes on protocol, so unlike the
+                // listeners above it is not inserted into `proto_listeners`
+                // here; `raw_listen` inserts it lazily under whatever
+                // protocol a `RawSocket` first binds to.
+                let raw_listeners = Arc::new(ArcSwap::new(Arc::new(HashMap::new())));
+                let raw_rx = raw::RawRx::new(raw_listeners.clone());
+                let raw_listener = Arc::new(Mutex::new(Box::new(raw_rx) as Box<ipv4::Ipv4Listener>));
+
+                self.ipv4_listeners.rcu(|current| {
+                    let mut new_listeners = current.clone();
+                    new_listeners.insert(ip, proto_listeners.clone());
+                    // Every member implicitly joins the all-hosts group, so
+                    // General Queries addressed to it reach our `IgmpRx`.
+                    let mut all_hosts_listeners = new_listeners.get(&igmp::all_hosts())
+                        .cloned()
+                        .unwrap_or_else(HashMap::new);
+                    all_hosts_listeners.insert(IpNextHeaderProtocols::Igmp, igmp_listener.clone());
+                    new_listeners.insert(igmp::all_hosts(), all_hosts_listeners);
+                    // Also alias every one of our listeners to the limited
+                    // broadcast address, so e.g. a Dhcp client's reply,
+                    // which a server sends to 255.255.255.255 since we have
+                    // no address of our own yet, still reaches it. When
+                    // more than one interface is configured they all share
+                    // this one global key, so the most recently added
+                    // interface's listener for a given protocol wins.
+                    let mut broadcast_listeners = new_listeners.get(&Ipv4Addr::new(255, 255, 255, 255))
+                        .cloned()
+                        .unwrap_or_else(HashMap::new);
+                    for (protocol, listener) in &proto_listeners {
+                        broadcast_listeners.insert(*protocol, listener.clone());
+                    }
+                    new_listeners.insert(Ipv4Addr::new(255, 255, 255, 255), broadcast_listeners);
+                    new_listeners
+                });
 
                 let data = Ipv4Data {
                     net: ip_net,
                     udp_listeners: udp_listeners,
                     icmp_listeners: icmp_listeners,
+                    tcp_listeners: tcp_listeners,
+                    raw_listeners: raw_listeners,
+                    raw_listener: raw_listener,
                 };
                 entry.insert(data);
                 self.ipv4_addresses.lock().unwrap().insert(ip);
+                self.announce_ipv4(ip);
                 Ok(())
             }
         }
     }
 
+    /// Sends an Arp probe for `ip` and waits up to `arp_probe_timeout` for a
+    /// reply, failing with `StackError::AddressConflict` if one arrives.
+    /// Mirrors `resolve`'s `arp::Miss` handling, but here receiving a reply
+    /// at all is the failure case rather than the success case: it means
+    /// some other host already claims `ip`, whoever it turns out to be.
+    fn probe_ipv4(&mut self, ip: Ipv4Addr) -> StackResult<()> {
+        let (rx, should_request) = match self.arp_table.get(ip) {
+            Ok(mac) => return Err(StackError::AddressConflict(ip, mac)),
+            Err(arp::Miss::Unresolved(rx)) => (rx, true),
+            Err(arp::Miss::Expired(rx)) => {
+                self.tx.lock().unwrap().inc();
+                (rx, true)
+            }
+            Err(arp::Miss::Pending(rx)) => (rx, false),
+        };
+        if should_request {
+            // Same retry-on-stale-Tx shape as the `tx_send!` macro used by
+            // `resolve` below, just spelled out since that macro is
+            // hardcoded to call `.send(...)` rather than `.send_probe(...)`.
+            let mut result = Err(TxError::InvalidTx);
+            while let Err(TxError::InvalidTx) = result {
+                result = self.arp_tx().send_probe(ip);
+            }
+            result?;
+        }
+        match rx.recv_timeout(arp_probe_timeout()) {
+            Ok(mac) => Err(StackError::AddressConflict(ip, mac)),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => Ok(()),
+        }
+    }
+
+    /// Sends a gratuitous Arp announcing `ip` as ours. Best effort: a
+    /// failure to send it is not reason enough to fail the address
+    /// assignment that already succeeded, the same way `send_icmp_unreachable`
+    /// and friends on `StackInterfaceThread` ignore `TxResult` failures.
+    fn announce_ipv4(&self, ip: Ipv4Addr) {
+        self.arp_tx().send_announcement(ip).unwrap_or(());
+    }
+
     pub fn ipv4_tx(&mut self,
                    dst: Ipv4Addr,
                    gw: Option<Ipv4Addr>)
                    -> StackResult<Ipv4TxImpl<EthernetTxImpl<TxImpl>>> {
         let local_dst = gw.unwrap_or(dst);
-        if let Some(src) = self.closest_local_ip(local_dst) {
-            let dst_mac = match self.arp_table.get(local_dst) {
-                Ok(mac) => mac,
-                Err(rx) => {
-                    tx_send!(|| self.arp_tx(); src, local_dst)?;
-                    rx.recv().unwrap()
+        if local_dst.is_multicast() || local_dst == Ipv4Addr::new(255, 255, 255, 255) {
+            // Multicast and limited-broadcast destinations never resolve
+            // through Arp; their Ethernet destination is derived straight
+            // from `local_dst` (the same way `igmp_tx_to` derives it for
+            // Membership Reports) rather than looked up, and any one of
+            // our addresses is a fine source since `local_dst` is never
+            // contained in any of our configured networks.
+            let dst_mac = if local_dst.is_multicast() {
+                igmp::multicast_mac(local_dst)
+            } else {
+                MacAddr::new(0xff, 0xff, 0xff, 0xff, 0xff, 0xff)
+            };
+            return match self.ipv4_datas.keys().next() {
+                Some(&src) => {
+                    let ethernet_tx = self.ethernet_tx(dst_mac);
+                    Ok(Ipv4TxImpl::new(ethernet_tx, src, dst, self.mtu))
                 }
+                None => Err(StackError::IllegalArgument),
             };
+        }
+        // `local_dst` should be on-link (it's either `dst` itself, or a
+        // gateway the caller already resolved a route to), so
+        // `closest_local_ip` will usually find it. If it doesn't and
+        // `local_dst` is a gateway - e.g. one outside every configured
+        // network - fall back to any one of our addresses rather than
+        // failing outright, the same way `ipv4_tx`'s multicast/broadcast
+        // case above picks any one of ours. Without a gateway, an off-link
+        // `dst` fails with `IllegalArgument`.
+        let src = self.closest_local_ip(local_dst)
+            .or_else(|| if gw.is_some() { self.ipv4_datas.keys().next().cloned() } else { None });
+        if let Some(src) = src {
+            let dst_mac = self.resolve(src, local_dst)?;
             let ethernet_tx = self.ethernet_tx(dst_mac);
             Ok(Ipv4TxImpl::new(ethernet_tx, src, dst, self.mtu))
         } else {
@@ -267,16 +634,100 @@ impl StackInterface {
         }
     }
 
+    /// Resolves `dst`'s MAC, sourced from `src`, blocking until an Arp
+    /// reply arrives or `ARP_RESOLVE_MAX_ATTEMPTS` sends of the request, the
+    /// first one included, have all gone unanswered, in which case
+    /// `StackError::NoRouteToHost` is returned instead of hanging forever.
+    /// Each retransmission waits longer than the last, doubling from
+    /// `arp_resolve_initial_timeout` up to `arp_resolve_max_timeout`.
+    fn resolve(&mut self, src: Ipv4Addr, dst: Ipv4Addr) -> StackResult<MacAddr> {
+        let (rx, mut should_request) = match self.arp_table.get(dst) {
+            Ok(mac) => return Ok(mac),
+            Err(arp::Miss::Unresolved(rx)) => (rx, true),
+            Err(arp::Miss::Expired(rx)) => {
+                // The old entry is gone, so any `Tx` built with its MAC
+                // baked in must be invalidated before we go resolve a
+                // (possibly different) one.
+                self.tx.lock().unwrap().inc();
+                (rx, true)
+            }
+            Err(arp::Miss::Pending(rx)) => (rx, false),
+        };
+        let mut timeout = arp_resolve_initial_timeout();
+        for _ in 0..ARP_RESOLVE_MAX_ATTEMPTS {
+            if should_request {
+                tx_send!(|| self.arp_tx(); src, dst)?;
+            }
+            match rx.recv_timeout(timeout) {
+                Ok(mac) => return Ok(mac),
+                Err(RecvTimeoutError::Timeout) => {
+                    should_request = true;
+                    timeout = cmp::min(timeout * 2, arp_resolve_max_timeout());
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        Err(StackError::NoRouteToHost)
+    }
+
     pub fn icmp_listen<L>(&mut self,
                           local_ip: Ipv4Addr,
-                          icmp_type: IcmpType,
+                          key: IcmpListenerKey,
                           listener: L)
                           -> io::Result<()>
         where L: icmp::IcmpListener + 'static
     {
         if let Some(ip_data) = self.ipv4_datas.get(&local_ip) {
-            let mut icmp_listeners = ip_data.icmp_listeners.lock().unwrap();
-            icmp_listeners.entry(icmp_type).or_insert_with(Vec::new).push(Box::new(listener));
+            let listener: Arc<Mutex<Box<icmp::IcmpListener>>> = Arc::new(Mutex::new(Box::new(listener)));
+            ip_data.icmp_listeners.rcu(|current| {
+                let mut new_listeners = current.clone();
+                new_listeners.entry(key).or_insert_with(Vec::new).push(listener.clone());
+                new_listeners
+            });
+            Ok(())
+        } else {
+            let msg = "Bind address does not exist on interface".to_owned();
+            Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+        }
+    }
+
+    /// Registers `listener` to receive every Ipv4 datagram carrying
+    /// `protocol` addressed to `local_ip`. The first time a given
+    /// `protocol` is bound on `local_ip`, this also inserts the `Ipv4Data`'s
+    /// shared `RawRx` into `ipv4_listeners` under that protocol, the same
+    /// way `join_multicast_group` inserts a group's listeners only once
+    /// it's actually joined.
+    pub fn raw_listen<L>(&mut self,
+                         local_ip: Ipv4Addr,
+                         protocol: IpNextHeaderProtocol,
+                         listener: L)
+                         -> io::Result<()>
+        where L: raw::RawListener + 'static
+    {
+        if let Some(ip_data) = self.ipv4_datas.get(&local_ip) {
+            let listener: Arc<Mutex<Box<raw::RawListener>>> = Arc::new(Mutex::new(Box::new(listener)));
+            let mut occupied = false;
+            ip_data.raw_listeners.rcu(|current| {
+                let mut new_listeners = current.clone();
+                if new_listeners.contains_key(&protocol) {
+                    occupied = true;
+                } else {
+                    new_listeners.insert(protocol, listener.clone());
+                }
+                new_listeners
+            });
+            if occupied {
+                let msg = format!("Protocol {:?} is already bound on {}", protocol, local_ip);
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, msg));
+            }
+            let raw_listener = ip_data.raw_listener.clone();
+            self.ipv4_listeners.rcu(|current| {
+                let mut new_listeners = current.clone();
+                let mut proto_listeners = new_listeners.get(&local_ip).cloned().unwrap_or_else(HashMap::new);
+                proto_listeners.insert(protocol, raw_listener.clone());
+                new_listeners.insert(local_ip, proto_listeners);
+                new_listeners
+            });
             Ok(())
         } else {
             let msg = "Bind address does not exist on interface".to_owned();
@@ -284,6 +735,102 @@ impl StackInterface {
         }
     }
 
+    pub fn has_ipv4(&self, ip: Ipv4Addr) -> bool {
+        self.ipv4_datas.contains_key(&ip)
+    }
+
+    /// Removes a previously added address, e.g. because a `dhcp::DhcpClient`
+    /// lost its lease. Drops `ip`'s listeners along with it; a socket bound
+    /// to `ip` will simply stop receiving anything rather than erroring.
+    pub fn remove_ipv4(&mut self, ip: Ipv4Addr) -> StackResult<()> {
+        match self.ipv4_datas.remove(&ip) {
+            Some(_) => {
+                self.ipv4_addresses.lock().unwrap().remove(&ip);
+                self.ipv4_listeners.rcu(|current| {
+                    let mut new_listeners = current.clone();
+                    new_listeners.remove(&ip);
+                    new_listeners
+                });
+                self.tx.lock().unwrap().inc();
+                Ok(())
+            }
+            None => Err(StackError::IllegalArgument),
+        }
+    }
+
+    /// Builds an `IgmpTx` sending to `dst`, with its underlying `Ipv4Tx`
+    /// from `local_ip`. Bypasses Arp entirely, unlike `ipv4_tx`, since
+    /// `dst` here is always a multicast address which never resolves
+    /// through it; the Ethernet destination is derived straight from `dst`
+    /// the same way `multicast_mac` is defined to. Per RFC 2236, the
+    /// underlying datagram carries TTL 1 and the Router Alert option so
+    /// routers on the link notice it despite it being addressed to a
+    /// multicast group rather than to them.
+    fn igmp_tx_to(&self, local_ip: Ipv4Addr, dst: Ipv4Addr) -> IgmpTx<Ipv4TxImpl<EthernetTxImpl<TxImpl>>> {
+        let ethernet_tx = self.ethernet_tx(igmp::multicast_mac(dst));
+        let ipv4_tx = Ipv4TxImpl::new(ethernet_tx, local_ip, dst, self.mtu)
+            .with_ttl(igmp::IGMP_TTL)
+            .with_options(igmp::ROUTER_ALERT_OPTION.to_vec());
+        igmp::IgmpTx::new(ipv4_tx)
+    }
+
+    /// Joins multicast `group`, sourced from `local_ip`. Adds `group` to
+    /// the Ipv4 listener table, aliased to whatever is already listening on
+    /// `local_ip`, sends an unsolicited Membership Report, and starts
+    /// tracking the group in case a Membership Query for it arrives later.
+    pub fn join_multicast_group(&mut self, local_ip: Ipv4Addr, group: Ipv4Addr) -> StackResult<()> {
+        let proto_listeners = match self.ipv4_listeners.load().get(&local_ip) {
+            Some(proto_listeners) => proto_listeners.clone(),
+            None => return Err(StackError::IllegalArgument),
+        };
+        self.ipv4_listeners.rcu(|current| {
+            let mut new_listeners = current.clone();
+            new_listeners.insert(group, proto_listeners.clone());
+            new_listeners
+        });
+        self.igmp_table.lock().unwrap().join(group);
+        self.igmp_tx_to(local_ip, group).send_membership_report(group)?;
+        Ok(())
+    }
+
+    /// Leaves multicast `group` previously joined through `local_ip`.
+    /// Returns `StackError::IllegalArgument` if we were not a member of it.
+    pub fn leave_multicast_group(&mut self, local_ip: Ipv4Addr, group: Ipv4Addr) -> StackResult<()> {
+        if !self.igmp_table.lock().unwrap().leave(group) {
+            return Err(StackError::IllegalArgument);
+        }
+        self.ipv4_listeners.rcu(|current| {
+            let mut new_listeners = current.clone();
+            new_listeners.remove(&group);
+            new_listeners
+        });
+        self.igmp_tx_to(local_ip, igmp::all_routers()).send_leave_group(group)?;
+        Ok(())
+    }
+
+    /// Every multicast group this interface is currently a member of.
+    pub fn multicast_groups(&self) -> Vec<Ipv4Addr> {
+        self.igmp_table.lock().unwrap().groups()
+    }
+
+    /// Sends a Membership Report for every group whose randomized response
+    /// delay has elapsed. Must be called periodically, the same way
+    /// `arp_table().flush_expired()` must, for queries to actually get
+    /// answered.
+    /// TODO: `igmp_table` tracks membership per interface, not per local
+    /// address, so a report is sent from whichever address happens to be
+    /// first in `ipv4_datas` rather than the one `join_multicast_group` was
+    /// actually called with. Harmless for the common single-address
+    /// interface, but worth revisiting once an interface can hold several.
+    pub fn igmp_flush_due_reports(&mut self) {
+        let due = self.igmp_table.lock().unwrap().due_reports();
+        if let Some(local_ip) = self.ipv4_datas.keys().next().cloned() {
+            for group in due {
+                self.igmp_tx_to(local_ip, group).send_membership_report(group).unwrap_or(());
+            }
+        }
+    }
+
     pub fn get_mtu(&self) -> usize {
         self.mtu
     }
@@ -293,15 +840,49 @@ impl StackInterface {
         self.tx.lock().unwrap().inc();
     }
 
+    /// Invalidates every `Ipv4Tx`/`EthernetTx` previously handed out for
+    /// this interface, the same way an expired Arp entry or an `Mtu` change
+    /// does. For use by anything that changes this interface's addressing
+    /// out from under already-cached senders, e.g. a Dhcp client acquiring
+    /// or losing a lease.
+    pub fn invalidate_tx(&self) {
+        self.tx.lock().unwrap().inc();
+    }
+
+    /// Returns whether this interface replies with Icmp Destination
+    /// Unreachable / Time Exceeded when it can't deliver an incoming
+    /// datagram. Enabled by default.
+    pub fn icmp_error_replies(&self) -> bool {
+        self.icmp_error_replies.load(Ordering::Relaxed)
+    }
+
+    /// Returns the shared `DropCounts` for this interface's `EthernetRx`,
+    /// tracking how many frames have been silently dropped so far because
+    /// nothing was listening for them, or because a listener rejected them
+    /// as malformed, and why.
+    pub fn drop_counts(&self) -> &DropCounts {
+        &self.drop_counts
+    }
+
+    /// Enables or disables the Icmp error replies described in
+    /// `icmp_error_replies`. Disable this for silent-drop, firewall-style
+    /// behavior.
+    pub fn set_icmp_error_replies(&mut self, enabled: bool) {
+        self.icmp_error_replies.store(enabled, Ordering::Relaxed);
+    }
+
     /// Finds which local IP is suitable as src ip for packets sent to `dst`.
-    /// TODO: Smarter algorithm
+    /// When more than one of our addresses' networks contain `dst` (e.g.
+    /// two addresses configured on the same interface with overlapping
+    /// subnets), prefers the one with the longest, i.e. most specific,
+    /// prefix - the same tie-breaking `RoutingTable::route` already applies
+    /// between candidate routes.
     fn closest_local_ip(&self, dst: Ipv4Addr) -> Option<Ipv4Addr> {
-        for (ip, ip_data) in &self.ipv4_datas {
-            if ip_data.net.contains(dst) {
-                return Some(*ip);
-            }
-        }
-        None
+        self.ipv4_datas
+            .iter()
+            .filter(|&(_ip, ip_data)| ip_data.net.contains(dst))
+            .max_by_key(|&(_ip, ip_data)| ip_data.net.prefix())
+            .map(|(ip, _ip_data)| *ip)
     }
 }
 
@@ -334,11 +915,22 @@ impl NetworkStack {
                          interface: Interface,
                          channel: EthernetChannel)
                          -> StackResult<()> {
+        self.add_interface_with_mtu(interface, channel, DEFAULT_MTU)
+    }
+
+    /// Adds a new interface like `add_interface`, but with `mtu` instead of
+    /// assuming `DEFAULT_MTU`, for backends like a TAP device that can
+    /// report their own real MTU.
+    pub fn add_interface_with_mtu(&mut self,
+                                  interface: Interface,
+                                  channel: EthernetChannel,
+                                  mtu: usize)
+                                  -> StackResult<()> {
         match self.interfaces.entry(interface) {
             Entry::Occupied(_) => Err(StackError::InvalidInterface),
             Entry::Vacant(entry) => {
                 let interface = entry.key().clone();
-                entry.insert(StackInterface::new(interface, channel));
+                entry.insert(StackInterface::with_mtu(interface, channel, mtu));
                 Ok(())
             }
         }
@@ -376,6 +968,15 @@ impl NetworkStack {
         Ok(())
     }
 
+    /// Removes a previously added address from `interface`.
+    /// Does not touch the `routing_table`; the route for a lapsed Dhcp
+    /// lease's network is harmless dead weight until the address is
+    /// reconfigured, the same way `add_ipv4`'s own `TODO` notes the routing
+    /// story here is still unrefined.
+    pub fn remove_ipv4(&mut self, interface: &Interface, ip: Ipv4Addr) -> StackResult<()> {
+        self.interface(interface)?.remove_ipv4(ip)
+    }
+
     pub fn ipv4_tx(&mut self, dst: Ipv4Addr) -> StackResult<Ipv4TxImpl<EthernetTxImpl<TxImpl>>> {
         if let Some((gw, interface)) = self.routing_table.route(dst) {
             if let Some(stack_interface) = self.interfaces.get_mut(&interface) {
@@ -388,6 +989,21 @@ impl NetworkStack {
         }
     }
 
+    /// Looks up the MTU of whichever interface `ipv4_tx` would route `dst`
+    /// through, e.g. so `tcp::TcpSocket` can size its segments to fit
+    /// without the underlying `Ipv4Tx` having to fragment them.
+    pub fn mtu_to(&mut self, dst: Ipv4Addr) -> StackResult<usize> {
+        if let Some((_gw, interface)) = self.routing_table.route(dst) {
+            if let Some(stack_interface) = self.interfaces.get(&interface) {
+                Ok(stack_interface.get_mtu())
+            } else {
+                Err(StackError::IllegalArgument)
+            }
+        } else {
+            Err(StackError::NoRouteToHost)
+        }
+    }
+
     pub fn icmp_tx(&mut self,
                    dst_ip: Ipv4Addr)
                    -> StackResult<IcmpTx<Ipv4TxImpl<EthernetTxImpl<TxImpl>>>> {
@@ -395,12 +1011,108 @@ impl NetworkStack {
         Ok(icmp::IcmpTx::new(ipv4_tx))
     }
 
+    /// Registers `listener` under `key` on `local_ip`, or, when `local_ip`
+    /// is `0.0.0.0`, on every address currently configured on every
+    /// interface, the way `bind("0.0.0.0")` does for a normal Icmp socket.
+    /// Addresses added to the stack after a wildcard bind do not
+    /// retroactively join it.
     pub fn icmp_listen<L>(&mut self,
                           local_ip: Ipv4Addr,
-                          icmp_type: IcmpType,
+                          key: IcmpListenerKey,
                           listener: L)
                           -> io::Result<()>
         where L: icmp::IcmpListener + 'static + Clone
+    {
+        let mut added_to_interface = false;
+        for stack_interface in self.interfaces.values_mut() {
+            if local_ip == Ipv4Addr::new(0, 0, 0, 0) {
+                for addr in stack_interface.ipv4_datas.keys().cloned().collect::<Vec<_>>() {
+                    let result = stack_interface.icmp_listen(addr, key, listener.clone());
+                    added_to_interface |= result.is_ok();
+                }
+            } else {
+                let result = stack_interface.icmp_listen(local_ip, key, listener.clone());
+                added_to_interface |= result.is_ok();
+            }
+        }
+        if added_to_interface {
+            Ok(())
+        } else {
+            let msg = "Bind address does not exist in stack".to_owned();
+            Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+        }
+    }
+
+    /// Joins multicast `group` on whichever interface `local_ip` is
+    /// configured on.
+    pub fn join_multicast_group(&mut self, local_ip: Ipv4Addr, group: Ipv4Addr) -> StackResult<()> {
+        for stack_interface in self.interfaces.values_mut() {
+            if stack_interface.has_ipv4(local_ip) {
+                return stack_interface.join_multicast_group(local_ip, group);
+            }
+        }
+        Err(StackError::IllegalArgument)
+    }
+
+    /// Leaves multicast `group`, previously joined through `local_ip`.
+    pub fn leave_multicast_group(&mut self, local_ip: Ipv4Addr, group: Ipv4Addr) -> StackResult<()> {
+        for stack_interface in self.interfaces.values_mut() {
+            if stack_interface.has_ipv4(local_ip) {
+                return stack_interface.leave_multicast_group(local_ip, group);
+            }
+        }
+        Err(StackError::IllegalArgument)
+    }
+
+    /// Every multicast group `local_ip` is currently a member of.
+    pub fn multicast_groups(&self, local_ip: Ipv4Addr) -> StackResult<Vec<Ipv4Addr>> {
+        for stack_interface in self.interfaces.values() {
+            if stack_interface.has_ipv4(local_ip) {
+                return Ok(stack_interface.multicast_groups());
+            }
+        }
+        Err(StackError::IllegalArgument)
+    }
+
+    /// Sends a Membership Report on every interface for every group whose
+    /// randomized response delay to a Membership Query has elapsed. Must be
+    /// called periodically for queries to actually get answered.
+    pub fn igmp_flush_due_reports(&mut self) {
+        for stack_interface in self.interfaces.values_mut() {
+            stack_interface.igmp_flush_due_reports();
+        }
+    }
+
+    pub fn udp_tx(&mut self,
+                  dst_ip: Ipv4Addr,
+                  src: u16,
+                  dst_port: u16)
+                  -> StackResult<UdpTx<Ipv4TxImpl<EthernetTxImpl<TxImpl>>>> {
+        let ipv4_tx = self.ipv4_tx(dst_ip)?;
+        Ok(udp::UdpTx::new(ipv4_tx, src, dst_port))
+    }
+
+    pub fn tcp_tx(&mut self, dst_ip: Ipv4Addr) -> StackResult<TcpTx<Ipv4TxImpl<EthernetTxImpl<TxImpl>>>> {
+        let ipv4_tx = self.ipv4_tx(dst_ip)?;
+        Ok(tcp::TcpTx::new(ipv4_tx))
+    }
+
+    pub fn raw_tx(&mut self,
+                  protocol: IpNextHeaderProtocol,
+                  dst_ip: Ipv4Addr)
+                  -> StackResult<RawTx<Ipv4TxImpl<EthernetTxImpl<TxImpl>>>> {
+        let ipv4_tx = self.ipv4_tx(dst_ip)?;
+        Ok(raw::RawTx::new(protocol, ipv4_tx))
+    }
+
+    /// Binds `listener` to receive every Ipv4 datagram carrying `protocol`
+    /// addressed to `local_ip`, on whichever interface owns that address.
+    pub fn raw_listen<L>(&mut self,
+                         local_ip: Ipv4Addr,
+                         protocol: IpNextHeaderProtocol,
+                         listener: L)
+                         -> io::Result<()>
+        where L: raw::RawListener + 'static + Clone
     {
         if local_ip == Ipv4Addr::new(0, 0, 0, 0) {
             let msg = "Rips does not support listening to all interfaces yet".to_owned();
@@ -408,7 +1120,7 @@ impl NetworkStack {
         } else {
             let mut added_to_interface = false;
             for stack_interface in self.interfaces.values_mut() {
-                let result = stack_interface.icmp_listen(local_ip, icmp_type, listener.clone());
+                let result = stack_interface.raw_listen(local_ip, protocol, listener.clone());
                 added_to_interface |= result.is_ok();
             }
             if added_to_interface {
@@ -420,30 +1132,138 @@ impl NetworkStack {
         }
     }
 
-    pub fn udp_tx(&mut self,
-                  dst_ip: Ipv4Addr,
-                  src: u16,
-                  dst_port: u16)
-                  -> StackResult<UdpTx<Ipv4TxImpl<EthernetTxImpl<TxImpl>>>> {
-        let ipv4_tx = self.ipv4_tx(dst_ip)?;
-        Ok(udp::UdpTx::new(ipv4_tx, src, dst_port))
-    }
-
     pub fn udp_listen<A, L>(&mut self, addr: A, listener: L) -> io::Result<SocketAddr>
         where A: ToSocketAddrs,
               L: udp::UdpListener + 'static + Clone
     {
         match util::first_socket_addr(addr)? {
             SocketAddr::V4(addr) => self.udp_listen_ipv4(addr, listener),
-            SocketAddr::V6(_) => {
-                let msg = "Rips does not support IPv6 yet".to_owned();
-                Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+            SocketAddr::V6(_) => Err(util::unsupported_ipv6()),
+        }
+    }
+
+    fn udp_listen_ipv4<L>(&mut self, addr: SocketAddrV4, listener: L) -> io::Result<SocketAddr>
+        where L: udp::UdpListener + 'static + Clone
+    {
+        let local_ip = addr.ip();
+        let mut local_port = addr.port();
+        if local_ip == &Ipv4Addr::new(0, 0, 0, 0) {
+            self.udp_listen_wildcard(local_port, listener)
+        } else {
+            for stack_interface in self.interfaces.values() {
+                if let Some(ip_data) = stack_interface.ipv4_datas.get(local_ip) {
+                    if local_port == 0 {
+                        local_port = self.get_random_port(&*ip_data.udp_listeners.load());
+                    }
+                    let listener: Arc<Mutex<Box<udp::UdpListener>>> =
+                        Arc::new(Mutex::new(Box::new(listener)));
+                    let mut occupied = false;
+                    ip_data.udp_listeners.rcu(|current| {
+                        let mut new_listeners = current.clone();
+                        if new_listeners.contains_key(&local_port) {
+                            occupied = true;
+                        } else {
+                            new_listeners.insert(local_port, listener.clone());
+                        }
+                        new_listeners
+                    });
+                    if occupied {
+                        let msg = format!("Port {} is already occupied on {}",
+                                          local_port,
+                                          local_ip);
+                        return Err(io::Error::new(io::ErrorKind::AddrInUse, msg));
+                    }
+                    return Ok(SocketAddr::V4(SocketAddrV4::new(*local_ip, local_port)));
+                }
             }
+            let msg = "Bind address does not exist in stack".to_owned();
+            Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
         }
     }
 
-    fn  udp_listen_ipv4<L>(&mut self, addr: SocketAddrV4, listener: L) -> io::Result<SocketAddr>
+    /// Binds `listener` to `local_port` on every address currently
+    /// configured on every interface, the way `bind("0.0.0.0:port")` does
+    /// for a normal Udp socket server. Port `0` picks one port that is free
+    /// on *all* of those addresses at once, rather than one that merely
+    /// happens to be free on whichever address `udp_listen_ipv4` would
+    /// otherwise have picked first. Addresses added to the stack after a
+    /// wildcard bind do not retroactively join it.
+    fn udp_listen_wildcard<L>(&mut self, mut local_port: u16, listener: L) -> io::Result<SocketAddr>
         where L: udp::UdpListener + 'static + Clone
+    {
+        let ip_datas: Vec<&Ipv4Data> = self.interfaces
+            .values()
+            .flat_map(|stack_interface| stack_interface.ipv4_datas.values())
+            .collect();
+        if ip_datas.is_empty() {
+            let msg = "Bind address does not exist in stack".to_owned();
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        if local_port == 0 {
+            local_port = self.get_random_port_wildcard(&ip_datas);
+        } else if ip_datas.iter().any(|ip_data| ip_data.udp_listeners.load().contains_key(&local_port)) {
+            let msg = format!("Port {} is already occupied on at least one interface address",
+                              local_port);
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, msg));
+        }
+        let listener: Arc<Mutex<Box<udp::UdpListener>>> = Arc::new(Mutex::new(Box::new(listener)));
+        for ip_data in &ip_datas {
+            ip_data.udp_listeners.rcu(|current| {
+                let mut new_listeners = current.clone();
+                new_listeners.insert(local_port, listener.clone());
+                new_listeners
+            });
+        }
+        Ok(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), local_port)))
+    }
+
+    fn get_random_port(&self, listeners: &udp::UdpListenerLookup) -> u16 {
+        let range = Range::new(LOCAL_PORT_RANGE_START, LOCAL_PORT_RANGE_END);
+        let mut rng = rand::thread_rng();
+        let mut port = 0;
+        while port == 0 {
+            let n = range.ind_sample(&mut rng);
+            if !listeners.contains_key(&n) {
+                port = n;
+                break;
+            }
+        }
+        port
+    }
+
+    /// Like `get_random_port`, but only returns a port free on every one of
+    /// `ip_datas`, for picking a port `udp_listen_wildcard` can bind on all
+    /// of them at once.
+    fn get_random_port_wildcard(&self, ip_datas: &[&Ipv4Data]) -> u16 {
+        let range = Range::new(LOCAL_PORT_RANGE_START, LOCAL_PORT_RANGE_END);
+        let mut rng = rand::thread_rng();
+        let mut port = 0;
+        while port == 0 {
+            let n = range.ind_sample(&mut rng);
+            if !ip_datas.iter().any(|ip_data| ip_data.udp_listeners.load().contains_key(&n)) {
+                port = n;
+                break;
+            }
+        }
+        port
+    }
+
+    /// Registers `listener` as the sole demultiplexer for every Tcp segment
+    /// addressed to `addr`'s port, on whichever interface owns `addr`'s ip.
+    /// Port `0` picks an unused one via `get_random_tcp_port`, the same way
+    /// `udp_listen` does for Udp.
+    pub fn tcp_listen<A, L>(&mut self, addr: A, listener: L) -> io::Result<SocketAddr>
+        where A: ToSocketAddrs,
+              L: tcp::TcpListener + 'static + Clone
+    {
+        match util::first_socket_addr(addr)? {
+            SocketAddr::V4(addr) => self.tcp_listen_ipv4(addr, listener),
+            SocketAddr::V6(_) => Err(util::unsupported_ipv6()),
+        }
+    }
+
+    fn tcp_listen_ipv4<L>(&mut self, addr: SocketAddrV4, listener: L) -> io::Result<SocketAddr>
+        where L: tcp::TcpListener + 'static + Clone
     {
         let local_ip = addr.ip();
         let mut local_port = addr.port();
@@ -453,19 +1273,28 @@ impl NetworkStack {
         } else {
             for stack_interface in self.interfaces.values() {
                 if let Some(ip_data) = stack_interface.ipv4_datas.get(local_ip) {
-                    let mut udp_listeners = ip_data.udp_listeners.lock().unwrap();
                     if local_port == 0 {
-                        local_port = self.get_random_port(&*udp_listeners);
+                        local_port = self.get_random_tcp_port(&*ip_data.tcp_listeners.load());
                     }
-                    if !udp_listeners.contains_key(&local_port) {
-                        udp_listeners.insert(local_port, Box::new(listener));
-                        return Ok(SocketAddr::V4(SocketAddrV4::new(*local_ip, local_port)));
-                    } else {
+                    let listener: Arc<Mutex<Box<tcp::TcpListener>>> =
+                        Arc::new(Mutex::new(Box::new(listener)));
+                    let mut occupied = false;
+                    ip_data.tcp_listeners.rcu(|current| {
+                        let mut new_listeners = current.clone();
+                        if new_listeners.contains_key(&local_port) {
+                            occupied = true;
+                        } else {
+                            new_listeners.insert(local_port, listener.clone());
+                        }
+                        new_listeners
+                    });
+                    if occupied {
                         let msg = format!("Port {} is already occupied on {}",
                                           local_port,
                                           local_ip);
                         return Err(io::Error::new(io::ErrorKind::AddrInUse, msg));
                     }
+                    return Ok(SocketAddr::V4(SocketAddrV4::new(*local_ip, local_port)));
                 }
             }
             let msg = "Bind address does not exist in stack".to_owned();
@@ -473,7 +1302,7 @@ impl NetworkStack {
         }
     }
 
-    fn get_random_port(&self, listeners: &udp::UdpListenerLookup) -> u16 {
+    fn get_random_tcp_port(&self, listeners: &tcp::TcpListenerLookup) -> u16 {
         let range = Range::new(LOCAL_PORT_RANGE_START, LOCAL_PORT_RANGE_END);
         let mut rng = rand::thread_rng();
         let mut port = 0;
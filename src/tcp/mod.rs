@@ -0,0 +1,821 @@
+mod tcp_rx;
+mod tcp_tx;
+
+pub use self::tcp_rx::{TcpListener, TcpListenerLookup, TcpRx};
+pub use self::tcp_tx::{TcpBuilder, TcpTx};
+
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, SocketAddrV4, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use pnet::packet::Packet;
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::tcp::{TcpFlags, TcpPacket};
+
+use rand;
+
+use {RxError, RxResult, TxResult};
+#[cfg(not(feature = "unit-tests"))]
+use {NetworkStack, StackError, StackResult};
+
+use ethernet::EthernetTxImpl;
+use ipv4::Ipv4TxImpl;
+use tx::TxImpl;
+
+/// Concrete `TcpTx` type handed out by `NetworkStack::tcp_tx`. Named here so
+/// `TcpSocket`/`TcpAcceptor` do not have to spell out the full
+/// `Ipv4Tx`/`EthernetTx` stack every time, the same way `udp::UdpSocket`
+/// caches a concrete `UdpTx`.
+#[cfg(not(feature = "unit-tests"))]
+type StackTcpTx = TcpTx<Ipv4TxImpl<EthernetTxImpl<TxImpl>>>;
+
+/// A raw, still Ipv4-wrapped segment handed from a `TcpDemux`/accept queue
+/// to whatever is consuming it, the same representation `udp::UdpSocketReader`
+/// uses for datagrams.
+#[cfg(not(feature = "unit-tests"))]
+type RawSegment = (SystemTime, Box<[u8]>);
+
+/// How often `ConnectionThread` wakes up with nothing to do, to check
+/// whether the oldest unacknowledged segment's `rto` has elapsed or
+/// `TIME_WAIT` has run out. Small enough that retransmissions aren't
+/// noticeably delayed past their deadline.
+#[cfg(not(feature = "unit-tests"))]
+fn tick_interval() -> Duration {
+    Duration::from_millis(100)
+}
+
+/// The first retransmission timeout given to a new connection, per RFC
+/// 6298's suggested default absent any round-trip measurement.
+#[cfg(not(feature = "unit-tests"))]
+fn initial_rto() -> Duration {
+    Duration::from_millis(500)
+}
+
+/// The cap `initial_rto` is doubled up to between retransmissions.
+#[cfg(not(feature = "unit-tests"))]
+fn max_rto() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// How long a connection lingers in `TimeWait` before `ConnectionThread`
+/// tears it down. RFC 793 specifies 2*MSL (commonly 2*120s); shortened
+/// here since this crate has no way to actually observe stray duplicate
+/// segments from a previous incarnation of the connection arriving that
+/// late anyway.
+#[cfg(not(feature = "unit-tests"))]
+fn time_wait_duration() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Returns whether sequence number `a` is at or before `b`, treating the
+/// 32 bit sequence space as circular the way RFC 793 §3.3 defines
+/// comparisons to work.
+#[cfg(not(feature = "unit-tests"))]
+fn seq_leq(a: u32, b: u32) -> bool {
+    b.wrapping_sub(a) < (1 << 31)
+}
+
+/// The RFC 793 connection states this crate actually distinguishes.
+/// `Closed` doubles as both "never opened" and "fully torn down"; nothing
+/// keeps a `Connection` around afterwards to tell the two apart.
+#[cfg(not(feature = "unit-tests"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TcpState {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    LastAck,
+    Closing,
+    TimeWait,
+    Closed,
+}
+
+/// A segment that has been sent but not yet acknowledged, kept around so
+/// `ConnectionThread` can retransmit it once `Connection::rto` elapses.
+#[cfg(not(feature = "unit-tests"))]
+struct Unacked {
+    seq: u32,
+    flags: u8,
+    data: Vec<u8>,
+    sent_at: Instant,
+}
+
+#[cfg(not(feature = "unit-tests"))]
+impl Unacked {
+    /// How many bytes of sequence space this segment consumes: its
+    /// payload, plus one each for `Syn`/`Fin` since those occupy a
+    /// sequence number of their own.
+    fn seq_len(&self) -> u32 {
+        let control = if self.flags & (TcpFlags::SYN | TcpFlags::FIN) != 0 {
+            1
+        } else {
+            0
+        };
+        self.data.len() as u32 + control
+    }
+}
+
+/// The sequence/ack tracking, retransmission queue and send/receive
+/// windows for one Tcp connection. Shared between the thread that called
+/// `TcpSocket::connect`/`TcpAcceptor::accept` (for `write`/`close`) and its
+/// `ConnectionThread` (for everything driven by incoming segments or
+/// timers), the same way `arp::ArpTable`'s `TableData` is shared between
+/// callers and `StackInterfaceThread`.
+///
+/// TODO: segments that arrive out of order are dropped rather than
+/// reassembled; only the in-order, non-overlapping case is handled.
+#[cfg(not(feature = "unit-tests"))]
+struct Connection {
+    state: TcpState,
+    tcp_tx: StackTcpTx,
+    local_port: u16,
+    remote: SocketAddrV4,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u16,
+    rcv_nxt: u32,
+    rcv_wnd: u16,
+    mss: usize,
+    unacked: VecDeque<Unacked>,
+    rto: Duration,
+    time_wait_until: Option<Instant>,
+}
+
+#[cfg(not(feature = "unit-tests"))]
+impl Connection {
+    fn in_flight(&self) -> u32 {
+        self.snd_nxt.wrapping_sub(self.snd_una)
+    }
+
+    /// Sends `data` as the next in-sequence segment and queues it for
+    /// retransmission until acknowledged.
+    fn send_data(&mut self, data: &[u8]) -> TxResult {
+        self.send_segment(TcpFlags::ACK, data)
+    }
+
+    /// Sends a bare control segment (e.g. `Syn`, `Fin`) carrying no
+    /// payload, and queues it for retransmission until acknowledged.
+    fn send_control(&mut self, flags: u8) -> TxResult {
+        self.send_segment(flags, &[])
+    }
+
+    /// Acknowledges already-received data or a `Fin`. Unlike `send_data`/
+    /// `send_control`, a bare `Ack` carries nothing of its own for the peer
+    /// to ever fail to receive, so there is nothing to retransmit even if
+    /// this one is lost: the peer's own next send, if any, will carry a
+    /// fresh cumulative ack anyway. Left out of the retransmission queue
+    /// so it can't sit in front of, and so starve, genuinely unacknowledged
+    /// data there.
+    fn send_ack(&mut self) {
+        self.tcp_tx
+            .send_segment(self.local_port, self.remote.port(), self.snd_nxt, self.rcv_nxt, TcpFlags::ACK, self.rcv_wnd, &[])
+            .unwrap_or(());
+    }
+
+    fn send_segment(&mut self, flags: u8, data: &[u8]) -> TxResult {
+        let seq = self.snd_nxt;
+        self.tcp_tx.send_segment(self.local_port,
+                                 self.remote.port(),
+                                 seq,
+                                 self.rcv_nxt,
+                                 flags,
+                                 self.rcv_wnd,
+                                 data)?;
+        let unacked = Unacked {
+            seq: seq,
+            flags: flags,
+            data: data.to_vec(),
+            sent_at: Instant::now(),
+        };
+        self.snd_nxt = self.snd_nxt.wrapping_add(unacked.seq_len());
+        self.unacked.push_back(unacked);
+        Ok(())
+    }
+
+    /// Re-sends the oldest unacknowledged segment, unchanged, and doubles
+    /// `rto` up to `max_rto`. Does nothing if everything sent so far has
+    /// already been acknowledged.
+    fn retransmit_oldest(&mut self) {
+        let resend = self.unacked.front().map(|seg| (seg.seq, seg.flags, seg.data.clone()));
+        if let Some((seq, flags, data)) = resend {
+            self.tcp_tx
+                .send_segment(self.local_port, self.remote.port(), seq, self.rcv_nxt, flags, self.rcv_wnd, &data)
+                .unwrap_or(());
+            self.rto = cmp::min(self.rto * 2, max_rto());
+            if let Some(front) = self.unacked.front_mut() {
+                front.sent_at = Instant::now();
+            }
+        }
+    }
+
+    /// Drops every unacknowledged segment `ack` now covers and advances
+    /// `snd_una`/the peer's advertised `snd_wnd` to match.
+    fn ack_received(&mut self, ack: u32, window: u16) {
+        while let Some(seq_end) = self.unacked.front().map(|seg| seg.seq.wrapping_add(seg.seq_len())) {
+            if seq_leq(seq_end, ack) {
+                self.unacked.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.snd_una = ack;
+        self.snd_wnd = window;
+    }
+}
+
+/// Demultiplexes incoming Tcp segments bound for one local port across
+/// however many remote peers are talking to it, the way a real kernel's
+/// per-port socket table would. `NetworkStack::tcp_listen` registers a
+/// single `TcpDemuxListener` per port in `tcp_listeners`; `TcpSocket` and
+/// `TcpAcceptor` each hold a clone of the `Arc<Mutex<..>>` behind it so
+/// they can add or remove their own per-connection queue as connections
+/// come and go.
+#[cfg(not(feature = "unit-tests"))]
+struct TcpDemux {
+    connections: HashMap<SocketAddrV4, Sender<RawSegment>>,
+    accept_queue: Option<Sender<RawSegment>>,
+}
+
+#[cfg(not(feature = "unit-tests"))]
+impl TcpDemux {
+    fn new() -> TcpDemux {
+        TcpDemux {
+            connections: HashMap::new(),
+            accept_queue: None,
+        }
+    }
+}
+
+#[cfg(not(feature = "unit-tests"))]
+#[derive(Clone)]
+struct TcpDemuxListener(Arc<Mutex<TcpDemux>>);
+
+#[cfg(not(feature = "unit-tests"))]
+impl TcpListener for TcpDemuxListener {
+    fn recv(&mut self, time: SystemTime, packet: &Ipv4Packet) -> (RxResult, bool) {
+        let remote = match TcpPacket::new(packet.payload()) {
+            Some(tcp_pkg) => SocketAddrV4::new(packet.get_source(), tcp_pkg.get_source()),
+            None => return (Err(RxError::InvalidContent), true),
+        };
+        let data = packet.packet().to_vec().into_boxed_slice();
+        let demux = self.0.lock().unwrap();
+        if let Some(chan) = demux.connections.get(&remote) {
+            (Ok(()), chan.send((time, data)).is_ok())
+        } else if let Some(ref queue) = demux.accept_queue {
+            (Ok(()), queue.send((time, data)).is_ok())
+        } else {
+            let msg = format!("Tcp, no connection accepting segments from {}", remote);
+            (Err(RxError::NoListener(msg)), true)
+        }
+    }
+}
+
+/// Drives one established connection for as long as it lives: applies
+/// incoming segments to the shared `Connection`, hands received data off
+/// to whoever is blocked in `TcpSocket::read`, and periodically checks
+/// whether the oldest unacknowledged segment's `rto` or a `TimeWait`
+/// linger has expired. Modeled on `StackInterfaceThread`, but one instance
+/// per connection rather than one per interface.
+#[cfg(not(feature = "unit-tests"))]
+struct ConnectionThread {
+    conn: Arc<Mutex<Connection>>,
+    rx: Receiver<RawSegment>,
+    data_tx: Option<Sender<Box<[u8]>>>,
+    demux: Arc<Mutex<TcpDemux>>,
+    remote: SocketAddrV4,
+}
+
+#[cfg(not(feature = "unit-tests"))]
+impl ConnectionThread {
+    fn spawn(conn: Arc<Mutex<Connection>>,
+             rx: Receiver<RawSegment>,
+             data_tx: Sender<Box<[u8]>>,
+             demux: Arc<Mutex<TcpDemux>>,
+             remote: SocketAddrV4) {
+        let thread = ConnectionThread {
+            conn: conn,
+            rx: rx,
+            data_tx: Some(data_tx),
+            demux: demux,
+            remote: remote,
+        };
+        thread::spawn(move || thread.run());
+    }
+
+    fn run(mut self) {
+        loop {
+            match self.rx.recv_timeout(tick_interval()) {
+                Ok((_time, data)) => {
+                    if !self.process_segment(&data) {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if !self.tick() {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        self.demux.lock().unwrap().connections.remove(&self.remote);
+        debug!("ConnectionThread for {} is quitting", self.remote);
+    }
+
+    /// Applies one incoming, already Ipv4-wrapped segment to `self.conn`.
+    /// Returns `false` once the connection has fully closed (by `Rst` or
+    /// by completing the close handshake), telling `run` to stop.
+    fn process_segment(&mut self, data: &[u8]) -> bool {
+        let ip_pkg = match Ipv4Packet::new(data) {
+            Some(pkg) => pkg,
+            None => return true,
+        };
+        let tcp_pkg = match TcpPacket::new(ip_pkg.payload()) {
+            Some(pkg) => pkg,
+            None => return true,
+        };
+        let flags = tcp_pkg.get_flags();
+        let mut conn = self.conn.lock().unwrap();
+        if flags & TcpFlags::RST != 0 {
+            conn.state = TcpState::Closed;
+            return false;
+        }
+        if flags & TcpFlags::ACK != 0 {
+            conn.ack_received(tcp_pkg.get_acknowledgement(), tcp_pkg.get_window());
+            match conn.state {
+                TcpState::SynReceived => conn.state = TcpState::Established,
+                TcpState::FinWait1 => conn.state = TcpState::FinWait2,
+                TcpState::Closing => {
+                    conn.state = TcpState::TimeWait;
+                    conn.time_wait_until = Some(Instant::now() + time_wait_duration());
+                }
+                TcpState::LastAck => {
+                    conn.state = TcpState::Closed;
+                    return false;
+                }
+                _ => {}
+            }
+        }
+        if flags & TcpFlags::SYN != 0 && flags & TcpFlags::ACK != 0 {
+            // The peer resent its Syn+Ack, so our final handshake Ack was lost.
+            conn.send_ack();
+        }
+        let payload = tcp_pkg.payload();
+        if !payload.is_empty() && tcp_pkg.get_sequence() == conn.rcv_nxt {
+            conn.rcv_nxt = conn.rcv_nxt.wrapping_add(payload.len() as u32);
+            conn.send_ack();
+            if let Some(ref data_tx) = self.data_tx {
+                data_tx.send(payload.to_vec().into_boxed_slice()).unwrap_or(());
+            }
+        }
+        if flags & TcpFlags::FIN != 0 && tcp_pkg.get_sequence() == conn.rcv_nxt {
+            conn.rcv_nxt = conn.rcv_nxt.wrapping_add(1);
+            conn.send_ack();
+            match conn.state {
+                TcpState::Established => {
+                    conn.state = TcpState::CloseWait;
+                    // No more data will ever arrive; dropping the sender
+                    // makes the blocked `TcpSocket::read` see end-of-file.
+                    self.data_tx = None;
+                }
+                TcpState::FinWait1 => conn.state = TcpState::Closing,
+                TcpState::FinWait2 => {
+                    conn.state = TcpState::TimeWait;
+                    conn.time_wait_until = Some(Instant::now() + time_wait_duration());
+                }
+                _ => {}
+            }
+        }
+        true
+    }
+
+    /// Called whenever `tick_interval` passes with nothing received.
+    /// Returns `false` once `TimeWait` has run out, telling `run` to stop.
+    fn tick(&mut self) -> bool {
+        let mut conn = self.conn.lock().unwrap();
+        match conn.state {
+            TcpState::TimeWait => {
+                let expired = conn.time_wait_until.map(|until| Instant::now() >= until).unwrap_or(false);
+                return !expired;
+            }
+            TcpState::Closed => return false,
+            _ => {}
+        }
+        let due = conn.unacked.front().map(|seg| seg.sent_at.elapsed() >= conn.rto).unwrap_or(false);
+        if due {
+            conn.retransmit_oldest();
+        }
+        true
+    }
+}
+
+/// A Tcp connection: `connect` to actively open one, or get one back from
+/// `TcpAcceptor::accept`. Implements `Read`/`Write` the same way a
+/// `std::net::TcpStream` does.
+#[cfg(not(feature = "unit-tests"))]
+pub struct TcpSocket {
+    local_addr: SocketAddrV4,
+    remote_addr: SocketAddrV4,
+    conn: Arc<Mutex<Connection>>,
+    data_rx: Receiver<Box<[u8]>>,
+}
+
+#[cfg(not(feature = "unit-tests"))]
+impl TcpSocket {
+    /// Actively opens a connection to `remote_addr`, sourced from
+    /// `local_addr` (port `0` picks one via `NetworkStack::get_random_tcp_port`
+    /// the same way `UdpSocket::bind` does). Blocks through the `Syn`/
+    /// `Syn+Ack`/`Ack` handshake, retransmitting the `Syn` the same way
+    /// `StackInterface::resolve` retransmits an Arp request.
+    pub fn connect(stack: Arc<Mutex<NetworkStack>>,
+                   local_addr: SocketAddrV4,
+                   remote_addr: SocketAddrV4)
+                   -> io::Result<TcpSocket> {
+        let demux = Arc::new(Mutex::new(TcpDemux::new()));
+        let (conn_tx, conn_rx) = mpsc::channel();
+        demux.lock().unwrap().connections.insert(remote_addr, conn_tx);
+
+        let (local_addr, tcp_tx, mss) = {
+            let mut stack = stack.lock().unwrap();
+            let local_addr = match stack.tcp_listen(local_addr, TcpDemuxListener(demux.clone()))? {
+                SocketAddr::V4(addr) => addr,
+                SocketAddr::V6(_) => unreachable!("tcp_listen rejects IPv6 addresses"),
+            };
+            let tcp_tx = stack.tcp_tx(*remote_addr.ip()).map_err(io::Error::from)?;
+            let mss = stack.mtu_to(*remote_addr.ip()).map_err(io::Error::from)? - 40;
+            (local_addr, tcp_tx, mss)
+        };
+
+        let conn = Connection {
+            state: TcpState::SynSent,
+            tcp_tx: tcp_tx,
+            local_port: local_addr.port(),
+            remote: remote_addr,
+            snd_una: 0,
+            snd_nxt: rand::random(),
+            snd_wnd: 0,
+            rcv_nxt: 0,
+            rcv_wnd: ::std::u16::MAX,
+            mss: mss,
+            unacked: VecDeque::new(),
+            rto: initial_rto(),
+            time_wait_until: None,
+        };
+        let conn = Arc::new(Mutex::new(conn));
+        conn.lock().unwrap().send_control(TcpFlags::SYN).map_err(StackError::TxError)?;
+
+        let mut timeout = initial_rto();
+        let mut established = false;
+        for _ in 0..7 {
+            match conn_rx.recv_timeout(timeout) {
+                Ok((_time, data)) => {
+                    if Self::handle_handshake_reply(&conn, &data) {
+                        established = true;
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    conn.lock().unwrap().retransmit_oldest();
+                    timeout = cmp::min(timeout * 2, max_rto());
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        if !established {
+            demux.lock().unwrap().connections.remove(&remote_addr);
+            let msg = "Tcp handshake timed out".to_owned();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, msg));
+        }
+
+        let (data_tx, data_rx) = mpsc::channel();
+        ConnectionThread::spawn(conn.clone(), conn_rx, data_tx, demux, remote_addr);
+        Ok(TcpSocket {
+            local_addr: local_addr,
+            remote_addr: remote_addr,
+            conn: conn,
+            data_rx: data_rx,
+        })
+    }
+
+    /// Applies one segment received during the handshake loop in `connect`.
+    /// Returns `true` once it completed the three-way handshake by
+    /// replying to a `Syn+Ack` with the final `Ack`.
+    fn handle_handshake_reply(conn: &Arc<Mutex<Connection>>, data: &[u8]) -> bool {
+        let ip_pkg = match Ipv4Packet::new(data) {
+            Some(pkg) => pkg,
+            None => return false,
+        };
+        let tcp_pkg = match TcpPacket::new(ip_pkg.payload()) {
+            Some(pkg) => pkg,
+            None => return false,
+        };
+        let flags = tcp_pkg.get_flags();
+        if flags & TcpFlags::SYN == 0 || flags & TcpFlags::ACK == 0 {
+            return false;
+        }
+        let mut conn = conn.lock().unwrap();
+        conn.rcv_nxt = tcp_pkg.get_sequence().wrapping_add(1);
+        conn.ack_received(tcp_pkg.get_acknowledgement(), tcp_pkg.get_window());
+        conn.state = TcpState::Established;
+        // The final Ack of the handshake needs no retransmission tracking
+        // of its own; if it is lost the peer will resend its Syn+Ack,
+        // which `ConnectionThread::process_segment` re-acks.
+        conn.send_ack();
+        true
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(SocketAddr::V4(self.local_addr))
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(SocketAddr::V4(self.remote_addr))
+    }
+
+    /// Sends a `Fin`, moving the connection into `FinWait1` (or `LastAck`
+    /// if the peer had already closed its half). Does not block for the
+    /// close handshake to finish; `ConnectionThread` finishes tearing the
+    /// connection down in the background.
+    pub fn close(self) -> io::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        match conn.state {
+            TcpState::Established => {
+                conn.send_control(TcpFlags::FIN | TcpFlags::ACK).map_err(StackError::TxError)?;
+                conn.state = TcpState::FinWait1;
+            }
+            TcpState::CloseWait => {
+                conn.send_control(TcpFlags::FIN | TcpFlags::ACK).map_err(StackError::TxError)?;
+                conn.state = TcpState::LastAck;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "unit-tests"))]
+impl Read for TcpSocket {
+    /// Blocks for the next in-order chunk of data the peer sent, copying
+    /// as much of it as fits in `buf`. Returns `Ok(0)` once the peer has
+    /// sent a `Fin` and everything before it has already been read.
+    ///
+    /// TODO: a chunk larger than `buf` has its remainder silently dropped
+    /// rather than kept for the next call, since `ConnectionThread` only
+    /// ever forwards whole segments.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.data_rx.recv() {
+            Ok(data) => {
+                let n = cmp::min(buf.len(), data.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                Ok(n)
+            }
+            Err(_) => Ok(0),
+        }
+    }
+}
+
+#[cfg(not(feature = "unit-tests"))]
+impl Write for TcpSocket {
+    /// Segments `buf` to `Connection::mss` and sends as much of it as the
+    /// peer's advertised window currently allows, blocking in small steps
+    /// until the rest of the window frees up via incoming `Ack`s.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        if conn.state != TcpState::Established && conn.state != TcpState::CloseWait {
+            let msg = "Connection is not established".to_owned();
+            return Err(io::Error::new(io::ErrorKind::NotConnected, msg));
+        }
+        let mut sent = 0;
+        while sent < buf.len() {
+            let available = (conn.snd_wnd as u32).saturating_sub(conn.in_flight()) as usize;
+            if available == 0 {
+                drop(conn);
+                thread::sleep(tick_interval());
+                conn = self.conn.lock().unwrap();
+                continue;
+            }
+            let chunk_len = cmp::min(cmp::min(conn.mss, available), buf.len() - sent);
+            conn.send_data(&buf[sent..sent + chunk_len]).map_err(StackError::TxError)?;
+            sent += chunk_len;
+        }
+        Ok(sent)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Accepts incoming Tcp connections on a bound local port. Named
+/// `TcpAcceptor` rather than `TcpListener` since that name is already
+/// taken by the low-level per-port demultiplexing trait in `tcp_rx`.
+#[cfg(not(feature = "unit-tests"))]
+pub struct TcpAcceptor {
+    local_ip: ::std::net::Ipv4Addr,
+    local_port: u16,
+    stack: Arc<Mutex<NetworkStack>>,
+    demux: Arc<Mutex<TcpDemux>>,
+    accept_rx: Receiver<RawSegment>,
+}
+
+#[cfg(not(feature = "unit-tests"))]
+impl TcpAcceptor {
+    pub fn bind<A: ToSocketAddrs>(stack: Arc<Mutex<NetworkStack>>, addr: A) -> io::Result<TcpAcceptor> {
+        let addr = match ::util::first_socket_addr(addr)? {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => return Err(::util::unsupported_ipv6()),
+        };
+        let demux = Arc::new(Mutex::new(TcpDemux::new()));
+        let (accept_tx, accept_rx) = mpsc::channel();
+        demux.lock().unwrap().accept_queue = Some(accept_tx);
+
+        let bound_addr = {
+            let mut stack = stack.lock().unwrap();
+            stack.tcp_listen(addr, TcpDemuxListener(demux.clone()))?
+        };
+        let bound_addr = match bound_addr {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => unreachable!("tcp_listen rejects IPv6 addresses"),
+        };
+        Ok(TcpAcceptor {
+            local_ip: *bound_addr.ip(),
+            local_port: bound_addr.port(),
+            stack: stack,
+            demux: demux,
+            accept_rx: accept_rx,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(SocketAddr::V4(SocketAddrV4::new(self.local_ip, self.local_port)))
+    }
+
+    /// Blocks for the next incoming connection attempt, replies to its
+    /// `Syn` with a `Syn+Ack` and waits for the final `Ack`, retransmitting
+    /// the `Syn+Ack` the same way `connect` retransmits its `Syn`.
+    /// Segments from remote peers that never complete the handshake are
+    /// simply ignored; `accept` keeps waiting for the next attempt.
+    pub fn accept(&mut self) -> io::Result<(TcpSocket, SocketAddr)> {
+        loop {
+            let (_time, data) = self.accept_rx
+                .recv()
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "Acceptor is closed".to_owned()))?;
+            if let Some(result) = self.try_accept(&data) {
+                return result;
+            }
+        }
+    }
+
+    /// Returns `None` when `data` was not a `Syn` opening a new connection,
+    /// so `accept` should keep waiting for the next one.
+    fn try_accept(&mut self, data: &[u8]) -> Option<io::Result<(TcpSocket, SocketAddr)>> {
+        let ip_pkg = Ipv4Packet::new(data)?;
+        let tcp_pkg = TcpPacket::new(ip_pkg.payload())?;
+        let flags = tcp_pkg.get_flags();
+        if flags & TcpFlags::SYN == 0 || flags & TcpFlags::ACK != 0 {
+            return None;
+        }
+        let remote = SocketAddrV4::new(ip_pkg.get_source(), tcp_pkg.get_source());
+        Some(self.complete_handshake(remote, tcp_pkg.get_sequence()))
+    }
+
+    fn complete_handshake(&mut self, remote: SocketAddrV4, peer_isn: u32) -> io::Result<(TcpSocket, SocketAddr)> {
+        let (conn_tx, conn_rx) = mpsc::channel();
+        self.demux.lock().unwrap().connections.insert(remote, conn_tx);
+
+        let (tcp_tx, mss) = {
+            let mut stack = self.stack.lock().unwrap();
+            let tcp_tx = stack.tcp_tx(*remote.ip()).map_err(io::Error::from)?;
+            let mss = stack.mtu_to(*remote.ip()).map_err(io::Error::from)? - 40;
+            (tcp_tx, mss)
+        };
+        let conn = Connection {
+            state: TcpState::SynReceived,
+            tcp_tx: tcp_tx,
+            local_port: self.local_port,
+            remote: remote,
+            snd_una: 0,
+            snd_nxt: rand::random(),
+            snd_wnd: 0,
+            rcv_nxt: peer_isn.wrapping_add(1),
+            rcv_wnd: ::std::u16::MAX,
+            mss: mss,
+            unacked: VecDeque::new(),
+            rto: initial_rto(),
+            time_wait_until: None,
+        };
+        let conn = Arc::new(Mutex::new(conn));
+        conn.lock().unwrap().send_control(TcpFlags::SYN | TcpFlags::ACK).map_err(StackError::TxError)?;
+
+        let mut timeout = initial_rto();
+        let mut established = false;
+        for _ in 0..7 {
+            match conn_rx.recv_timeout(timeout) {
+                Ok((_time, data)) => {
+                    if Self::final_ack_received(&conn, &data) {
+                        established = true;
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    conn.lock().unwrap().retransmit_oldest();
+                    timeout = cmp::min(timeout * 2, max_rto());
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        if !established {
+            self.demux.lock().unwrap().connections.remove(&remote);
+            let msg = "Tcp handshake timed out".to_owned();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, msg));
+        }
+
+        let (data_tx, data_rx) = mpsc::channel();
+        ConnectionThread::spawn(conn.clone(), conn_rx, data_tx, self.demux.clone(), remote);
+        let socket = TcpSocket {
+            local_addr: SocketAddrV4::new(self.local_ip, self.local_port),
+            remote_addr: remote,
+            conn: conn,
+            data_rx: data_rx,
+        };
+        Ok((socket, SocketAddr::V4(remote)))
+    }
+
+    /// Returns `true` once `data` was the final `Ack` of the handshake
+    /// `complete_handshake` started.
+    fn final_ack_received(conn: &Arc<Mutex<Connection>>, data: &[u8]) -> bool {
+        let ip_pkg = match Ipv4Packet::new(data) {
+            Some(pkg) => pkg,
+            None => return false,
+        };
+        let tcp_pkg = match TcpPacket::new(ip_pkg.payload()) {
+            Some(pkg) => pkg,
+            None => return false,
+        };
+        if tcp_pkg.get_flags() & TcpFlags::ACK == 0 {
+            return false;
+        }
+        let mut conn = conn.lock().unwrap();
+        conn.ack_received(tcp_pkg.get_acknowledgement(), tcp_pkg.get_window());
+        conn.state = TcpState::Established;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seq_leq_without_wraparound() {
+        assert!(seq_leq(10, 20));
+        assert!(seq_leq(10, 10));
+        assert!(!seq_leq(20, 10));
+    }
+
+    #[test]
+    fn seq_leq_across_the_wraparound_point() {
+        assert!(seq_leq(::std::u32::MAX, 0));
+        assert!(seq_leq(::std::u32::MAX - 1, ::std::u32::MAX));
+        assert!(!seq_leq(0, ::std::u32::MAX));
+    }
+
+    #[test]
+    fn unacked_seq_len_counts_data_only_for_a_pure_data_segment() {
+        let seg = Unacked {
+            seq: 0,
+            flags: 0,
+            data: vec![1, 2, 3],
+            sent_at: Instant::now(),
+        };
+        assert_eq!(3, seg.seq_len());
+    }
+
+    #[test]
+    fn unacked_seq_len_counts_one_extra_for_syn_or_fin() {
+        let syn = Unacked {
+            seq: 0,
+            flags: TcpFlags::SYN,
+            data: Vec::new(),
+            sent_at: Instant::now(),
+        };
+        assert_eq!(1, syn.seq_len());
+
+        let fin_with_data = Unacked {
+            seq: 0,
+            flags: TcpFlags::FIN,
+            data: vec![1, 2],
+            sent_at: Instant::now(),
+        };
+        assert_eq!(3, fin_with_data.seq_len());
+    }
+}
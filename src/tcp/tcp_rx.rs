@@ -0,0 +1,185 @@
+use arc_swap::ArcSwap;
+
+use {RxError, RxResult};
+use checksum::ChecksumCapabilities;
+use ipv4::Ipv4Listener;
+use stack::StackInterfaceMsg;
+
+use pnet::packet::Packet;
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::tcp::{TcpFlags, TcpPacket, ipv4_checksum};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+use std::time::SystemTime;
+
+pub trait TcpListener: Send {
+    fn recv(&mut self, time: SystemTime, packet: &Ipv4Packet) -> (RxResult, bool);
+}
+
+/// Each listener is individually wrapped in its own `Mutex` so that
+/// `TcpRx::recv` can dispatch via a lock-free `ArcSwap::load` and only ever
+/// has to take a fine grained, almost never contended, per-listener lock.
+pub type TcpListenerLookup = HashMap<u16, Arc<Mutex<Box<TcpListener>>>>;
+
+/// Listener and parser of Tcp segments. Any segment addressed to a port
+/// with no registered `TcpSocket`/`TcpAcceptor` listening on it is reported
+/// to the owning `StackInterfaceThread` so it can reply with a RST, the
+/// same way `Ipv4Rx` asks for an Icmp Destination Unreachable when nothing
+/// claims a datagram. This mirrors smoltcp's approach of mapping every
+/// incoming segment to a single response type, closed ports included.
+pub struct TcpRx {
+    listeners: Arc<ArcSwap<TcpListenerLookup>>,
+    stack_tx: Sender<StackInterfaceMsg>,
+    checksums: ChecksumCapabilities,
+}
+
+impl TcpRx {
+    pub fn new(listeners: Arc<ArcSwap<TcpListenerLookup>>,
+               stack_tx: Sender<StackInterfaceMsg>)
+               -> TcpRx {
+        Self::with_checksums(listeners, stack_tx, ChecksumCapabilities::default())
+    }
+
+    /// Creates a new `TcpRx`, skipping checksum verification according to
+    /// `checksums` when the underlying NIC already did it in hardware.
+    pub fn with_checksums(listeners: Arc<ArcSwap<TcpListenerLookup>>,
+                           stack_tx: Sender<StackInterfaceMsg>,
+                           checksums: ChecksumCapabilities)
+                           -> TcpRx {
+        TcpRx {
+            listeners: listeners,
+            stack_tx: stack_tx,
+            checksums: checksums,
+        }
+    }
+
+    fn get_port(pkg: &Ipv4Packet) -> Result<u16, RxError> {
+        let payload = pkg.payload();
+        if payload.len() < TcpPacket::minimum_packet_size() {
+            return Err(RxError::InvalidContent);
+        }
+        let tcp_pkg = TcpPacket::new(payload).unwrap();
+        Ok(tcp_pkg.get_destination())
+    }
+
+    /// Verifies the Tcp checksum of `pkg`, unless `self.checksums.tcp` says
+    /// the NIC already did so in hardware. Assumes `get_port` has already
+    /// validated the payload is at least as long as a Tcp header.
+    fn verify_checksum(&self, pkg: &Ipv4Packet) -> RxResult {
+        if self.checksums.tcp.rx() {
+            let tcp_pkg = TcpPacket::new(pkg.payload()).ok_or(RxError::InvalidContent)?;
+            let expected = ipv4_checksum(&tcp_pkg, pkg.get_source(), pkg.get_destination());
+            if tcp_pkg.get_checksum() != expected {
+                return Err(RxError::InvalidContent);
+            }
+        }
+        Ok(())
+    }
+
+    /// RFC 793 ("Reset Generation" / "Reset Processing"): a segment that
+    /// already carries RST must never itself be answered with a RST, or two
+    /// compliant stacks that both consider the other's port closed would
+    /// keep echoing resets back and forth forever. Assumes `get_port` has
+    /// already validated the payload is at least as long as a Tcp header.
+    fn is_rst(ip_pkg: &Ipv4Packet) -> bool {
+        let tcp_pkg = TcpPacket::new(ip_pkg.payload()).unwrap();
+        tcp_pkg.get_flags() & TcpFlags::RST != 0
+    }
+
+    /// Tells the owning `StackInterfaceThread` that `ip_pkg` carried a Tcp
+    /// segment for a port nothing is listening on, so it can reply with a
+    /// RST if this is a segment that warrants one.
+    fn report_closed_port(&self, ip_pkg: &Ipv4Packet) {
+        let msg = StackInterfaceMsg::TcpRst(ip_pkg.packet().to_vec());
+        self.stack_tx.send(msg).unwrap_or(());
+    }
+}
+
+impl Ipv4Listener for TcpRx {
+    fn recv(&mut self, time: SystemTime, ip_pkg: Ipv4Packet) -> RxResult {
+        let port = try!(Self::get_port(&ip_pkg));
+        try!(self.verify_checksum(&ip_pkg));
+        let listeners = self.listeners.load();
+        if let Some(listener) = listeners.get(&port) {
+            let (result, _resume) = listener.lock().unwrap().recv(time, &ip_pkg);
+            result
+            // TODO: When resume turns false, remove this socket.
+        } else {
+            if !Self::is_rst(&ip_pkg) {
+                self.report_closed_port(&ip_pkg);
+            }
+            Err(RxError::NoListener(format!("Tcp, no listener for port {:?}", port)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::Checksum;
+
+    use pnet::packet::MutablePacket;
+    use pnet::packet::ipv4::MutableIpv4Packet;
+    use pnet::packet::tcp::MutableTcpPacket;
+
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+    use std::sync::mpsc;
+
+    fn closed_port_segment(flags: u8) -> Ipv4Packet<'static> {
+        let src_ip = Ipv4Addr::new(10, 0, 0, 1);
+        let dst_ip = Ipv4Addr::new(10, 0, 0, 2);
+
+        let size = Ipv4Packet::minimum_packet_size() + TcpPacket::minimum_packet_size();
+        let mut buffer = vec![0u8; size];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(size as u16);
+            ip_pkg.set_source(src_ip);
+            ip_pkg.set_destination(dst_ip);
+            let mut tcp_pkg = MutableTcpPacket::new(ip_pkg.payload_mut()).unwrap();
+            tcp_pkg.set_destination(12345);
+            tcp_pkg.set_data_offset(5);
+            tcp_pkg.set_flags(flags);
+        }
+        Ipv4Packet::owned(buffer).unwrap()
+    }
+
+    #[test]
+    fn recv_reports_closed_port_when_segment_is_not_a_rst() {
+        let ip_pkg = closed_port_segment(TcpFlags::SYN);
+
+        let (stack_tx, stack_rx) = mpsc::channel();
+        let mut checksums = ChecksumCapabilities::default();
+        checksums.tcp = Checksum::Tx;
+        let mut tcp_rx = TcpRx::with_checksums(Arc::new(ArcSwap::new(Arc::new(HashMap::new()))),
+                                                stack_tx,
+                                                checksums);
+
+        assert!(tcp_rx.recv(SystemTime::now(), ip_pkg).is_err());
+
+        match stack_rx.try_recv() {
+            Ok(StackInterfaceMsg::TcpRst(_)) => {}
+            other => panic!("Expected a TcpRst message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn recv_does_not_reply_to_a_rst_segment() {
+        let ip_pkg = closed_port_segment(TcpFlags::RST);
+
+        let (stack_tx, stack_rx) = mpsc::channel();
+        let mut checksums = ChecksumCapabilities::default();
+        checksums.tcp = Checksum::Tx;
+        let mut tcp_rx = TcpRx::with_checksums(Arc::new(ArcSwap::new(Arc::new(HashMap::new()))),
+                                                stack_tx,
+                                                checksums);
+
+        assert!(tcp_rx.recv(SystemTime::now(), ip_pkg).is_err());
+
+        assert!(stack_rx.try_recv().is_err());
+    }
+}
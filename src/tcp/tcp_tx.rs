@@ -0,0 +1,233 @@
+use std::net::Ipv4Addr;
+
+use pnet::packet::MutablePacket;
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::tcp::{MutableTcpPacket, TcpPacket, ipv4_checksum};
+
+use {Payload, TxResult};
+use checksum::Checksum;
+use ipv4::{Ipv4Payload, Ipv4Tx};
+
+/// Tcp packet sender struct. Used both to synthesize bare segments (RST
+/// replies to segments addressed to a closed port) and, via `send_segment`,
+/// to carry a `tcp::TcpSocket` connection's data and window advertisement.
+pub struct TcpTx<T: Ipv4Tx> {
+    checksum: Checksum,
+    ipv4: T,
+}
+
+impl<T: Ipv4Tx> TcpTx<T> {
+    /// Creates a new `TcpTx` based on `ipv4`. Inherits its checksum offload
+    /// capability from `ipv4`, the same way `udp::UdpTx::new` does.
+    pub fn new(ipv4: T) -> Self {
+        let checksum = ipv4.checksums().tcp;
+        TcpTx {
+            checksum: checksum,
+            ipv4: ipv4,
+        }
+    }
+
+    /// Sends a bare Tcp segment carrying no payload, no options and a zero
+    /// window, with the given ports, sequence number, acknowledgement
+    /// number and flags. The source/destination IP addresses are taken
+    /// from the underlying `Ipv4Tx`.
+    pub fn send(&mut self,
+                src_port: u16,
+                dst_port: u16,
+                seq: u32,
+                ack: u32,
+                flags: u8)
+                -> TxResult {
+        self.send_segment(src_port, dst_port, seq, ack, flags, 0, &[])
+    }
+
+    /// Like `send`, but also advertises `window` bytes of receive window
+    /// and carries `payload` as the segment's data.
+    pub fn send_segment(&mut self,
+                         src_port: u16,
+                         dst_port: u16,
+                         seq: u32,
+                         ack: u32,
+                         flags: u8,
+                         window: u16,
+                         payload: &[u8])
+                         -> TxResult {
+        let builder = TcpBuilder::new(self.ipv4.src(),
+                                       self.ipv4.dst(),
+                                       src_port,
+                                       dst_port,
+                                       seq,
+                                       ack,
+                                       flags,
+                                       self.checksum)
+            .window(window)
+            .payload(payload);
+        self.ipv4.send(builder)
+    }
+}
+
+pub struct TcpBuilder {
+    src_ip: Ipv4Addr,
+    dst_ip: Ipv4Addr,
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    checksum: Checksum,
+    window: u16,
+    payload: Vec<u8>,
+}
+
+impl TcpBuilder {
+    pub fn new(src_ip: Ipv4Addr,
+               dst_ip: Ipv4Addr,
+               src_port: u16,
+               dst_port: u16,
+               seq: u32,
+               ack: u32,
+               flags: u8,
+               checksum: Checksum)
+               -> Self {
+        TcpBuilder {
+            src_ip: src_ip,
+            dst_ip: dst_ip,
+            src_port: src_port,
+            dst_port: dst_port,
+            seq: seq,
+            ack: ack,
+            flags: flags,
+            checksum: checksum,
+            window: 0,
+            payload: Vec::new(),
+        }
+    }
+
+    /// Sets the advertised receive window. Defaults to `0`.
+    pub fn window(mut self, window: u16) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Sets the segment's data. Defaults to empty, for a bare control
+    /// segment.
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+}
+
+impl Ipv4Payload for TcpBuilder {
+    fn next_level_protocol(&self) -> IpNextHeaderProtocol {
+        IpNextHeaderProtocols::Tcp
+    }
+}
+
+impl Payload for TcpBuilder {
+    fn len(&self) -> usize {
+        TcpPacket::minimum_packet_size() + self.payload.len()
+    }
+
+    fn build(&mut self, buffer: &mut [u8]) {
+        let mut pkg = MutableTcpPacket::new(buffer).unwrap();
+        pkg.set_source(self.src_port);
+        pkg.set_destination(self.dst_port);
+        pkg.set_sequence(self.seq);
+        pkg.set_acknowledgement(self.ack);
+        pkg.set_data_offset(5); // No options, 5 is for a 20 byte header
+        pkg.set_flags(self.flags);
+        pkg.set_window(self.window);
+        pkg.set_urgent_ptr(0);
+        pkg.set_payload(&self.payload);
+        if self.checksum.tx() {
+            let csum = ipv4_checksum(&pkg.to_immutable(), self.src_ip, self.dst_ip);
+            pkg.set_checksum(csum);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use TxResult;
+    use checksum::ChecksumCapabilities;
+    use ipv4::Ipv4Payload;
+
+    use pnet::packet::tcp::TcpFlags;
+
+    use std::net::Ipv4Addr;
+    use std::sync::mpsc;
+
+    use super::*;
+
+    struct MockIpv4Tx {
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        chan: mpsc::Sender<Box<[u8]>>,
+    }
+
+    impl MockIpv4Tx {
+        fn new() -> (Self, mpsc::Receiver<Box<[u8]>>) {
+            let (tx, rx) = mpsc::channel();
+            let mock = MockIpv4Tx {
+                src: Ipv4Addr::new(10, 0, 0, 1),
+                dst: Ipv4Addr::new(10, 0, 0, 2),
+                chan: tx,
+            };
+            (mock, rx)
+        }
+    }
+
+    impl Ipv4Tx for MockIpv4Tx {
+        fn src(&self) -> Ipv4Addr {
+            self.src
+        }
+
+        fn dst(&self) -> Ipv4Addr {
+            self.dst
+        }
+
+        fn checksums(&self) -> ChecksumCapabilities {
+            ChecksumCapabilities::default()
+        }
+
+        fn send<P: Ipv4Payload>(&mut self, mut payload: P) -> TxResult {
+            let mut buffer = vec![0; payload.len()];
+            payload.build(&mut buffer);
+            self.chan.send(buffer.into_boxed_slice()).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn send_builds_a_bare_control_segment() {
+        let (ipv4_tx, rx) = MockIpv4Tx::new();
+        let mut testee = TcpTx::new(ipv4_tx);
+        testee.send(12345, 80, 42, 43, TcpFlags::RST).unwrap();
+
+        let buffer = rx.try_recv().unwrap();
+        assert!(rx.try_recv().is_err());
+
+        let pkg = TcpPacket::new(&buffer).unwrap();
+        assert_eq!(12345, pkg.get_source());
+        assert_eq!(80, pkg.get_destination());
+        assert_eq!(42, pkg.get_sequence());
+        assert_eq!(43, pkg.get_acknowledgement());
+        assert_eq!(TcpFlags::RST, pkg.get_flags());
+        assert_eq!(0, pkg.get_window());
+        assert!(pkg.payload().is_empty());
+    }
+
+    #[test]
+    fn send_segment_carries_window_and_payload() {
+        let (ipv4_tx, rx) = MockIpv4Tx::new();
+        let mut testee = TcpTx::new(ipv4_tx);
+        let data = [1, 2, 3, 4];
+        testee.send_segment(1, 2, 100, 200, TcpFlags::ACK, 4096, &data).unwrap();
+
+        let buffer = rx.try_recv().unwrap();
+        let pkg = TcpPacket::new(&buffer).unwrap();
+        assert_eq!(TcpFlags::ACK, pkg.get_flags());
+        assert_eq!(4096, pkg.get_window());
+        assert_eq!(&data[..], pkg.payload());
+    }
+}
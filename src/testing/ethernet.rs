@@ -1,9 +1,11 @@
 use TxResult;
-use ethernet::{EthernetProtocol, EthernetTx};
+use ethernet::{EthernetDevice, EthernetPayload, EthernetTx, RxToken, TxToken};
 
 use pnet::util::MacAddr;
 
-use std::sync::mpsc;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::SystemTime;
 
 #[derive(Debug)]
 pub struct MockEthernetTx {
@@ -27,7 +29,7 @@ impl EthernetTx for MockEthernetTx {
     }
 
     fn send<P>(&mut self, packets: usize, packet_size: usize, mut payload: P) -> TxResult
-        where P: EthernetProtocol
+        where P: EthernetPayload
     {
         for _ in 0..packets {
             let mut buffer = vec![0; packet_size];
@@ -37,3 +39,85 @@ impl EthernetTx for MockEthernetTx {
         Ok(())
     }
 }
+
+/// `RxToken` handed out by `MockEthernetDevice`, wrapping a single
+/// already-received frame.
+pub struct MockRxToken {
+    time: SystemTime,
+    data: Box<[u8]>,
+}
+
+impl RxToken for MockRxToken {
+    fn consume<R, F>(self, f: F) -> io::Result<R>
+        where F: FnOnce(SystemTime, &[u8]) -> io::Result<R>
+    {
+        f(self.time, &self.data)
+    }
+}
+
+/// `TxToken` handed out by `MockEthernetDevice`. Borrows the device's
+/// reused scratch buffer instead of allocating a fresh one for every frame.
+pub struct MockTxToken<'a> {
+    scratch: &'a mut [u8],
+    out_chan: &'a Sender<Box<[u8]>>,
+}
+
+impl<'a> TxToken for MockTxToken<'a> {
+    fn consume<R, F>(self, f: F) -> io::Result<R>
+        where F: FnOnce(&mut [u8]) -> io::Result<R>
+    {
+        let result = f(self.scratch);
+        self.out_chan.send(self.scratch.to_vec().into_boxed_slice()).unwrap();
+        result
+    }
+}
+
+/// Test double for `EthernetDevice`. Received frames are read from an mpsc
+/// channel and transmitted frames are pushed onto another, the same
+/// arrangement `MockEthernetTx` uses, but frames are built straight into a
+/// scratch buffer that is reused across calls to `transmit` instead of
+/// allocating a fresh `Vec` for every packet.
+pub struct MockEthernetDevice {
+    in_packets: Receiver<(SystemTime, Box<[u8]>)>,
+    out_chan: Sender<Box<[u8]>>,
+    scratch: Vec<u8>,
+}
+
+impl MockEthernetDevice {
+    /// Creates a new `MockEthernetDevice` together with the two ends that
+    /// drive it: inject frames on the returned `Sender` to have them show up
+    /// from `receive`, and read frames handed to `transmit` off the returned
+    /// `Receiver`.
+    pub fn new() -> (MockEthernetDevice, Sender<(SystemTime, Box<[u8]>)>, Receiver<Box<[u8]>>) {
+        let (in_tx, in_packets) = mpsc::channel();
+        let (out_chan, out_rx) = mpsc::channel();
+        let device = MockEthernetDevice {
+            in_packets: in_packets,
+            out_chan: out_chan,
+            scratch: Vec::new(),
+        };
+        (device, in_tx, out_rx)
+    }
+}
+
+impl<'a> EthernetDevice<'a> for MockEthernetDevice {
+    type RxToken = MockRxToken;
+    type TxToken = MockTxToken<'a>;
+
+    fn receive(&'a mut self) -> Option<Self::RxToken> {
+        match self.in_packets.try_recv() {
+            Ok((time, data)) => Some(MockRxToken { time: time, data: data }),
+            Err(_) => None,
+        }
+    }
+
+    fn transmit(&'a mut self, len: usize) -> Option<Self::TxToken> {
+        if self.scratch.len() < len {
+            self.scratch.resize(len, 0);
+        }
+        Some(MockTxToken {
+            scratch: &mut self.scratch[..len],
+            out_chan: &self.out_chan,
+        })
+    }
+}
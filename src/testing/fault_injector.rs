@@ -0,0 +1,401 @@
+//! Configurable fault injection directly on the `pnet::datalink`
+//! `EthernetDataLinkSender`/`EthernetDataLinkReceiver` pair, for
+//! integration tests that need a lossy, reordering, rate-limited link one
+//! layer below `Tx`/`EthernetListener` without swapping either of those
+//! out. Complements `testing::faulty`, which does the same thing one
+//! layer higher.
+//!
+//! Delay and reordering are driven by an explicit `tick()` rather than a
+//! real timer, the same way `testing::faulty` and `ArpTable::flush_expired`
+//! are: call `tick()` once per unit of simulated time to age the token
+//! bucket and release any frames whose delay has elapsed. Since a
+//! `FaultInjectorSender`/`FaultInjectorReceiver` pair is typically driven
+//! by the stack's own background tx/rx threads while a test thread calls
+//! `tick()`, the shared state is behind a `Mutex` rather than assuming
+//! single-threaded access like `testing::faulty` can.
+
+use pnet::datalink::{EthernetDataLinkChannelIterator, EthernetDataLinkReceiver,
+                     EthernetDataLinkSender};
+use pnet::packet::{MutablePacket, Packet};
+use pnet::packet::ethernet::{EthernetPacket, MutableEthernetPacket};
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+use rand::distributions::{IndependentSample, Range};
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Builds a linked `FaultInjectorSender`/`FaultInjectorReceiver` pair
+/// sharing one RNG and delay queue, so e.g. a stack with 30% loss and a
+/// shaped rate of 1000 frames per tick can be assembled with a readable
+/// chained call instead of a struct literal.
+pub struct FaultInjectorBuilder {
+    drop_probability: u8,
+    corrupt_probability: u8,
+    max_delay_ticks: u32,
+    packets_per_tick: usize,
+    seed: [u32; 4],
+}
+
+impl FaultInjectorBuilder {
+    /// A builder that does not degrade traffic at all, useful as a base to
+    /// tweak individual fields from. `seed` drives the RNG behind drop,
+    /// corruption and delay decisions, so the same seed always reproduces
+    /// the same sequence of faults.
+    pub fn new(seed: [u32; 4]) -> Self {
+        FaultInjectorBuilder {
+            drop_probability: 0,
+            corrupt_probability: 0,
+            max_delay_ticks: 0,
+            packets_per_tick: ::std::usize::MAX,
+            seed: seed,
+        }
+    }
+
+    /// Sets how likely each individual frame is to be silently dropped,
+    /// out of 255. `0` never drops, `255` always drops.
+    pub fn drop_probability(mut self, probability: u8) -> Self {
+        self.drop_probability = probability;
+        self
+    }
+
+    /// Sets how likely each individual frame is to have one random byte
+    /// flipped, out of 255.
+    pub fn corrupt_probability(mut self, probability: u8) -> Self {
+        self.corrupt_probability = probability;
+        self
+    }
+
+    /// Bounds how many ticks a frame may be held before release, chosen
+    /// per frame. `0`, the default, disables delay/reordering.
+    pub fn max_delay_ticks(mut self, ticks: u32) -> Self {
+        self.max_delay_ticks = ticks;
+        self
+    }
+
+    /// Caps how many frames may pass through per `tick()`, shaping the
+    /// link's rate. Defaults to unlimited.
+    pub fn packets_per_tick(mut self, packets: usize) -> Self {
+        self.packets_per_tick = packets;
+        self
+    }
+
+    /// Wraps `sender`/`receiver`, returning a pair that shares one RNG and
+    /// delay queue so both directions of the link degrade consistently.
+    pub fn build(self,
+                 sender: Box<EthernetDataLinkSender>,
+                 receiver: Box<EthernetDataLinkReceiver>)
+                 -> (FaultInjectorSender, FaultInjectorReceiver) {
+        let state = Arc::new(Mutex::new(FaultState::new(self)));
+        (FaultInjectorSender {
+             inner: sender,
+             state: state.clone(),
+         },
+         FaultInjectorReceiver {
+             inner: receiver,
+             state: state,
+         })
+    }
+}
+
+/// Holds the RNG, token bucket and delay queue shared by a
+/// `FaultInjectorSender`/`FaultInjectorReceiver` pair.
+struct FaultState {
+    drop_probability: u8,
+    corrupt_probability: u8,
+    max_delay_ticks: u32,
+    packets_per_tick: usize,
+    tokens: usize,
+    rng: XorShiftRng,
+    queue: VecDeque<(u32, Vec<u8>)>,
+    due: VecDeque<Vec<u8>>,
+}
+
+impl FaultState {
+    fn new(config: FaultInjectorBuilder) -> Self {
+        FaultState {
+            drop_probability: config.drop_probability,
+            corrupt_probability: config.corrupt_probability,
+            max_delay_ticks: config.max_delay_ticks,
+            packets_per_tick: config.packets_per_tick,
+            tokens: config.packets_per_tick,
+            rng: XorShiftRng::from_seed(config.seed),
+            queue: VecDeque::new(),
+            due: VecDeque::new(),
+        }
+    }
+
+    fn roll(&mut self, threshold: u8) -> bool {
+        threshold > 0 && self.rng.gen::<u8>() <= threshold
+    }
+
+    fn corrupt(&mut self, buffer: &mut [u8]) {
+        if !buffer.is_empty() {
+            let range = Range::new(0, buffer.len());
+            let index = range.ind_sample(&mut self.rng);
+            buffer[index] ^= self.rng.gen::<u8>() | 1;
+        }
+    }
+
+    fn delay_ticks(&mut self) -> u32 {
+        if self.max_delay_ticks == 0 {
+            0
+        } else {
+            let range = Range::new(0, self.max_delay_ticks + 1);
+            range.ind_sample(&mut self.rng)
+        }
+    }
+
+    /// Runs a just-built frame through drop/corrupt/rate-limit/delay.
+    /// Returns `Some(bytes)` if it should be forwarded right now, `None`
+    /// if it was dropped or queued for later release through `tick()`.
+    fn process(&mut self, mut buffer: Vec<u8>) -> Option<Vec<u8>> {
+        if self.roll(self.drop_probability) {
+            return None;
+        }
+        if self.roll(self.corrupt_probability) {
+            self.corrupt(&mut buffer);
+        }
+        if self.tokens == 0 {
+            // Not enough budget left this tick, queue instead of
+            // forwarding right away.
+            self.queue.push_back((1, buffer));
+            return None;
+        }
+        self.tokens -= 1;
+        let delay = self.delay_ticks();
+        if delay == 0 {
+            Some(buffer)
+        } else {
+            self.queue.push_back((delay, buffer));
+            None
+        }
+    }
+
+    /// Ages the token bucket and delay queue by one tick, moving any
+    /// frames whose delay has now elapsed into `due`. The order frames
+    /// land in `due` need not match the order they were queued in, that
+    /// is the whole point of the delay queue: it lets frames be
+    /// reordered.
+    fn tick(&mut self) {
+        self.tokens = self.packets_per_tick;
+        let mut still_waiting = VecDeque::new();
+        for (ticks_left, buffer) in self.queue.drain(..) {
+            if ticks_left <= 1 {
+                self.due.push_back(buffer);
+            } else {
+                still_waiting.push_back((ticks_left - 1, buffer));
+            }
+        }
+        self.queue = still_waiting;
+    }
+}
+
+fn replay(inner: &mut Box<EthernetDataLinkSender>, buffer: &[u8]) -> io::Result<()> {
+    let result = inner.build_and_send(1, buffer.len(), &mut |mut packet: MutableEthernetPacket| {
+        packet.packet_mut().copy_from_slice(buffer);
+    });
+    match result {
+        Some(r) => r,
+        None => Err(io::Error::new(io::ErrorKind::Other, "Insufficient buffer space")),
+    }
+}
+
+/// `EthernetDataLinkSender` wrapping another sender, degrading the frames
+/// passed through it according to the shared `FaultState`.
+pub struct FaultInjectorSender {
+    inner: Box<EthernetDataLinkSender>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultInjectorSender {
+    /// Ages this link's token bucket and delay queue by one tick, sending
+    /// out any frames whose delay has elapsed through the inner sender.
+    pub fn tick(&mut self) -> io::Result<()> {
+        let due = {
+            let mut state = self.state.lock().expect("Unable to lock FaultInjector state");
+            state.tick();
+            state.due.drain(..).collect::<Vec<_>>()
+        };
+        for buffer in due {
+            replay(&mut self.inner, &buffer)?;
+        }
+        Ok(())
+    }
+}
+
+impl EthernetDataLinkSender for FaultInjectorSender {
+    fn build_and_send(&mut self,
+                       num_packets: usize,
+                       packet_size: usize,
+                       func: &mut FnMut(MutableEthernetPacket))
+                       -> Option<io::Result<()>> {
+        for _ in 0..num_packets {
+            let mut buffer = vec![0; packet_size];
+            {
+                let packet = MutableEthernetPacket::new(&mut buffer).unwrap();
+                func(packet);
+            }
+            let processed = {
+                let mut state = self.state.lock().expect("Unable to lock FaultInjector state");
+                state.process(buffer)
+            };
+            if let Some(buffer) = processed {
+                // The wire silently lost or queued anything that did not
+                // make it this far, so a failure to actually put a
+                // surviving frame on it is the only thing worth
+                // surfacing.
+                if let Err(e) = replay(&mut self.inner, &buffer) {
+                    return Some(Err(e));
+                }
+            }
+        }
+        Some(Ok(()))
+    }
+}
+
+/// `EthernetDataLinkReceiver` wrapping another receiver, degrading the
+/// frames read through its iterator according to the shared `FaultState`.
+pub struct FaultInjectorReceiver {
+    inner: Box<EthernetDataLinkReceiver>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultInjectorReceiver {
+    /// Ages this link's token bucket and delay queue by one tick. Frames
+    /// released this way are picked up by the next `next()` call on the
+    /// iterator this receiver hands out.
+    pub fn tick(&mut self) {
+        self.state.lock().expect("Unable to lock FaultInjector state").tick();
+    }
+}
+
+impl EthernetDataLinkReceiver for FaultInjectorReceiver {
+    fn iter<'a>(&'a mut self) -> Box<EthernetDataLinkChannelIterator<'a> + 'a> {
+        Box::new(FaultInjectorIter {
+            inner: self.inner.iter(),
+            state: self.state.clone(),
+        })
+    }
+}
+
+struct FaultInjectorIter<'a> {
+    inner: Box<EthernetDataLinkChannelIterator<'a> + 'a>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl<'a> EthernetDataLinkChannelIterator<'a> for FaultInjectorIter<'a> {
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        loop {
+            let due = {
+                let mut state = self.state.lock().expect("Unable to lock FaultInjector state");
+                state.due.pop_front()
+            };
+            if let Some(buffer) = due {
+                return Ok(EthernetPacket::owned(buffer).unwrap());
+            }
+            let packet = self.inner.next()?;
+            let buffer = packet.packet().to_vec();
+            let processed = {
+                let mut state = self.state.lock().expect("Unable to lock FaultInjector state");
+                state.process(buffer)
+            };
+            if let Some(buffer) = processed {
+                return Ok(EthernetPacket::owned(buffer).unwrap());
+            }
+            // Dropped, or queued for release through a later `tick()`.
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct RecordingSender {
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl EthernetDataLinkSender for RecordingSender {
+        fn build_and_send(&mut self,
+                           num_packets: usize,
+                           packet_size: usize,
+                           func: &mut FnMut(MutableEthernetPacket))
+                           -> Option<io::Result<()>> {
+            for _ in 0..num_packets {
+                let mut buffer = vec![0; packet_size];
+                {
+                    let packet = MutableEthernetPacket::new(&mut buffer).unwrap();
+                    func(packet);
+                }
+                self.sent.lock().unwrap().push(buffer);
+            }
+            Some(Ok(()))
+        }
+    }
+
+    fn no_fault_sender(sent: Arc<Mutex<Vec<Vec<u8>>>>) -> FaultInjectorSender {
+        let inner = Box::new(RecordingSender { sent: sent });
+        let state = Arc::new(Mutex::new(FaultState::new(FaultInjectorBuilder::new([1, 2, 3, 4]))));
+        FaultInjectorSender {
+            inner: inner,
+            state: state,
+        }
+    }
+
+    #[test]
+    fn drop_probability_255_drops_every_frame() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut sender = no_fault_sender(sent.clone());
+        sender.state.lock().unwrap().drop_probability = 255;
+
+        let result = sender.build_and_send(3, 14, &mut |_packet: MutableEthernetPacket| {});
+
+        assert_eq!(Some(Ok(())), result.map(|r| r.map_err(|_| ())));
+        assert!(sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn drop_probability_0_forwards_every_frame() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut sender = no_fault_sender(sent.clone());
+
+        sender.build_and_send(3, 14, &mut |_packet: MutableEthernetPacket| {});
+
+        assert_eq!(3, sent.lock().unwrap().len());
+    }
+
+    #[test]
+    fn rate_limit_queues_frames_beyond_the_per_tick_budget() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut sender = no_fault_sender(sent.clone());
+        {
+            let mut state = sender.state.lock().unwrap();
+            state.packets_per_tick = 1;
+            state.tokens = 1;
+        }
+
+        sender.build_and_send(2, 14, &mut |_packet: MutableEthernetPacket| {});
+        assert_eq!(1, sent.lock().unwrap().len());
+
+        sender.tick().unwrap();
+        assert_eq!(2, sent.lock().unwrap().len());
+    }
+
+    #[test]
+    fn delayed_frames_are_released_once_their_ticks_elapse() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut sender = no_fault_sender(sent.clone());
+        {
+            let mut state = sender.state.lock().unwrap();
+            state.queue.push_back((2, vec![0u8; 14]));
+        }
+
+        assert!(sent.lock().unwrap().is_empty());
+        sender.tick().unwrap();
+        assert!(sent.lock().unwrap().is_empty());
+        sender.tick().unwrap();
+        assert_eq!(1, sent.lock().unwrap().len());
+    }
+}
@@ -0,0 +1,272 @@
+//! Fault-injection and rate-limiting middleware for `EthernetTx`/
+//! `EthernetListener`. Turns `MockPnet` into a network emulator so ARP
+//! retransmit logic and the UDP benchmarks can be exercised under loss and
+//! congestion without real hardware.
+//!
+//! Delay and reordering are driven by an explicit `tick()` call rather than
+//! a real timer, the same way `ArpTable::flush_expired` must be called
+//! periodically rather than firing on its own: call `tick()` once per unit
+//! of simulated time (e.g. once per test loop iteration) to age the token
+//! bucket and release any frames whose delay has elapsed.
+
+use {Payload, RxResult, TxResult};
+use checksum::ChecksumCapabilities;
+use ethernet::{EthernetListener, EthernetPayload, EthernetTx};
+
+use pnet::packet::MutablePacket;
+use pnet::packet::ethernet::{EtherType, EthernetPacket, MutableEthernetPacket};
+use pnet::util::MacAddr;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+use rand::distributions::{IndependentSample, Range};
+
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+/// Tunables for `FaultyTx`/`FaultyListener`. All probabilities are in
+/// `[0.0, 1.0]`.
+pub struct FaultConfig {
+    /// Chance that an individual frame is silently lost.
+    pub drop_probability: f64,
+    /// Chance that an individual frame has one random payload byte flipped.
+    pub corrupt_probability: f64,
+    /// Highest number of `tick()`s a frame may be held before release,
+    /// chosen per frame. `0` disables delay/reordering.
+    pub max_delay_ticks: u32,
+    /// Token bucket capacity, in bytes refilled every `tick()`.
+    pub bytes_per_tick: usize,
+    /// Seed for the injected RNG, so failing tests are reproducible.
+    pub seed: [u32; 4],
+}
+
+impl FaultConfig {
+    /// A config that does not degrade traffic at all, useful as a base to
+    /// tweak individual fields from.
+    pub fn none(seed: [u32; 4]) -> Self {
+        FaultConfig {
+            drop_probability: 0.0,
+            corrupt_probability: 0.0,
+            max_delay_ticks: 0,
+            bytes_per_tick: ::std::usize::MAX,
+            seed: seed,
+        }
+    }
+}
+
+/// Holds the RNG, token bucket and delay queue shared by the `FaultyTx`/
+/// `FaultyListener` wrapping one link.
+struct FaultState {
+    config: FaultConfig,
+    rng: XorShiftRng,
+    tokens: usize,
+    queue: VecDeque<(u32, EtherType, Vec<u8>)>,
+}
+
+impl FaultState {
+    fn new(config: FaultConfig) -> Self {
+        let rng = XorShiftRng::from_seed(config.seed);
+        let tokens = config.bytes_per_tick;
+        FaultState {
+            config: config,
+            rng: rng,
+            tokens: tokens,
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn roll(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.rng.gen::<f64>() < probability
+    }
+
+    fn corrupt(&mut self, buffer: &mut [u8]) {
+        if !buffer.is_empty() {
+            let range = Range::new(0, buffer.len());
+            let index = range.ind_sample(&mut self.rng);
+            buffer[index] ^= self.rng.gen::<u8>() | 1;
+        }
+    }
+
+    fn delay_ticks(&mut self) -> u32 {
+        if self.config.max_delay_ticks == 0 {
+            0
+        } else {
+            let range = Range::new(0, self.config.max_delay_ticks + 1);
+            range.ind_sample(&mut self.rng)
+        }
+    }
+
+    /// Runs a just-built frame through drop/corrupt/rate-limit/delay.
+    /// Returns `Some(bytes)` if it should be delivered right now, `None` if
+    /// it was dropped or queued for later.
+    fn process(&mut self, ether_type: EtherType, mut buffer: Vec<u8>) -> Option<Vec<u8>> {
+        if self.roll(self.config.drop_probability) {
+            return None;
+        }
+        if self.roll(self.config.corrupt_probability) {
+            self.corrupt(&mut buffer);
+        }
+        if buffer.len() > self.tokens {
+            // Not enough budget left this tick, queue instead of sending
+            // right away.
+            self.queue.push_back((1, ether_type, buffer));
+            return None;
+        }
+        self.tokens -= buffer.len();
+        let delay = self.delay_ticks();
+        if delay == 0 {
+            Some(buffer)
+        } else {
+            self.queue.push_back((delay, ether_type, buffer));
+            None
+        }
+    }
+
+    /// Ages the token bucket and the delay queue by one tick, returning any
+    /// frames whose delay has now elapsed. The returned order need not
+    /// match the order frames were queued in, that is the whole point of
+    /// the delay queue: it lets frames be reordered.
+    fn tick(&mut self) -> Vec<(EtherType, Vec<u8>)> {
+        self.tokens = self.config.bytes_per_tick;
+        let mut due = Vec::new();
+        let mut still_waiting = VecDeque::new();
+        for (ticks_left, ether_type, buffer) in self.queue.drain(..) {
+            if ticks_left <= 1 {
+                due.push((ether_type, buffer));
+            } else {
+                still_waiting.push_back((ticks_left - 1, ether_type, buffer));
+            }
+        }
+        self.queue = still_waiting;
+        due
+    }
+}
+
+/// Payload that simply replays a buffer built earlier, used to hand a
+/// previously materialized (and possibly delayed) frame back to an
+/// `EthernetTx` for sending.
+struct RawPayload {
+    ether_type: EtherType,
+    data: Vec<u8>,
+}
+
+impl EthernetPayload for RawPayload {
+    fn ether_type(&self) -> EtherType {
+        self.ether_type
+    }
+}
+
+impl Payload for RawPayload {
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn build(&mut self, buffer: &mut [u8]) {
+        buffer.copy_from_slice(&self.data);
+    }
+}
+
+/// `EthernetTx` wrapping another `EthernetTx`, degrading the frames passed
+/// through it according to a `FaultConfig`.
+pub struct FaultyTx<T: EthernetTx> {
+    inner: T,
+    state: FaultState,
+}
+
+impl<T: EthernetTx> FaultyTx<T> {
+    pub fn new(inner: T, config: FaultConfig) -> Self {
+        FaultyTx {
+            inner: inner,
+            state: FaultState::new(config),
+        }
+    }
+
+    /// Ages this link's token bucket and delay queue by one tick, sending
+    /// out any frames whose delay has elapsed.
+    pub fn tick(&mut self) -> TxResult {
+        for (ether_type, data) in self.state.tick() {
+            let payload = RawPayload {
+                ether_type: ether_type,
+                data: data,
+            };
+            let size = payload.data.len();
+            self.inner.send(1, size, payload)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: EthernetTx> EthernetTx for FaultyTx<T> {
+    fn src(&self) -> MacAddr {
+        self.inner.src()
+    }
+
+    fn dst(&self) -> MacAddr {
+        self.inner.dst()
+    }
+
+    fn checksums(&self) -> ChecksumCapabilities {
+        self.inner.checksums()
+    }
+
+    fn send<P>(&mut self, packets: usize, size: usize, mut payload: P) -> TxResult
+        where P: EthernetPayload
+    {
+        for _ in 0..packets {
+            let mut buffer = vec![0; size];
+            payload.build(&mut buffer);
+            if let Some(buffer) = self.state.process(payload.ether_type(), buffer) {
+                let raw = RawPayload {
+                    ether_type: payload.ether_type(),
+                    data: buffer,
+                };
+                self.inner.send(1, size, raw)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `EthernetListener` wrapping another listener, degrading the frames
+/// delivered to it according to a `FaultConfig`.
+pub struct FaultyListener<L: EthernetListener> {
+    inner: L,
+    state: FaultState,
+}
+
+impl<L: EthernetListener> FaultyListener<L> {
+    pub fn new(inner: L, config: FaultConfig) -> Self {
+        FaultyListener {
+            inner: inner,
+            state: FaultState::new(config),
+        }
+    }
+
+    /// Ages this link's token bucket and delay queue by one tick,
+    /// delivering any frames whose delay has elapsed to the inner
+    /// listener.
+    pub fn tick(&mut self) -> RxResult {
+        for (_, data) in self.state.tick() {
+            let packet = EthernetPacket::owned(data).unwrap();
+            self.inner.recv(SystemTime::now(), &packet)?;
+        }
+        Ok(())
+    }
+}
+
+impl<L: EthernetListener> EthernetListener for FaultyListener<L> {
+    fn recv(&mut self, time: SystemTime, packet: &EthernetPacket) -> RxResult {
+        let ether_type = packet.get_ethertype();
+        let buffer = packet.packet().to_vec();
+        if let Some(buffer) = self.state.process(ether_type, buffer) {
+            let mut owned = MutableEthernetPacket::owned(buffer).unwrap();
+            let packet = owned.to_immutable();
+            self.inner.recv(time, &packet)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn ether_type(&self) -> EtherType {
+        self.inner.ether_type()
+    }
+}
@@ -0,0 +1,298 @@
+//! A diagnostic layer that decodes every frame librips sends or receives
+//! into a human-readable, multi-line dump and logs it through the `log`
+//! crate. Turns the scattered `debug!` breadcrumbs (e.g. the one in
+//! `VersionedTx::inc`) into a coherent, per-packet trace, which makes
+//! debugging the half-finished fragmentation/reassembly paths tractable.
+//!
+//! Built the same way as `pcap`'s capture middleware: a `Display`-producing
+//! type (`PrettyPrinter`) plus a sender/receiver wrapper around it that sits
+//! directly on the `pnet::datalink` channel, below `EthernetTx`/`ArpTx`/
+//! `Ipv4Tx`/`UdpTx`, so it composes with the pcap and fault-injection
+//! wrappers without any of them knowing about each other.
+
+use pnet::datalink::{EthernetDataLinkChannelIterator, EthernetDataLinkReceiver,
+                     EthernetDataLinkSender};
+use pnet::packet::Packet;
+use pnet::packet::arp::{ArpOperations, ArpPacket};
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::icmp::{IcmpPacket, IcmpTypes};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::udp::UdpPacket;
+
+use std::fmt;
+use std::io;
+
+use EthernetChannel;
+
+/// Decodes a raw Ethernet frame into a multi-line `Display`, peeling off one
+/// header at a time (Ethernet -> Arp/Ipv4 -> Udp/Icmp) for as far down as
+/// the crate already knows how to parse. Anything it does not recognize is
+/// left as a byte count rather than causing the whole dump to fail.
+pub struct PrettyPrinter<'p> {
+    frame: &'p [u8],
+}
+
+impl<'p> PrettyPrinter<'p> {
+    pub fn new(frame: &'p [u8]) -> Self {
+        PrettyPrinter { frame: frame }
+    }
+}
+
+impl<'p> fmt::Display for PrettyPrinter<'p> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let eth_pkg = match EthernetPacket::new(self.frame) {
+            Some(pkg) => pkg,
+            None => return write!(f, "Ethernet: truncated frame, {} bytes", self.frame.len()),
+        };
+        writeln!(f,
+                 "Ethernet: {} -> {} ({:?})",
+                 eth_pkg.get_source(),
+                 eth_pkg.get_destination(),
+                 eth_pkg.get_ethertype())?;
+        match eth_pkg.get_ethertype() {
+            EtherTypes::Arp => fmt_arp(eth_pkg.payload(), f),
+            EtherTypes::Ipv4 => fmt_ipv4(eth_pkg.payload(), f),
+            other => write!(f, "  <{:?}, {} bytes>", other, eth_pkg.payload().len()),
+        }
+    }
+}
+
+fn fmt_arp(payload: &[u8], f: &mut fmt::Formatter) -> fmt::Result {
+    match ArpPacket::new(payload) {
+        Some(pkg) => {
+            write!(f,
+                   "  Arp: {} {} ({}) -> {} ({})",
+                   if pkg.get_operation() == ArpOperations::Request {
+                       "Request".to_owned()
+                   } else if pkg.get_operation() == ArpOperations::Reply {
+                       "Reply".to_owned()
+                   } else {
+                       format!("{:?}", pkg.get_operation())
+                   },
+                   pkg.get_sender_proto_addr(),
+                   pkg.get_sender_hw_addr(),
+                   pkg.get_target_proto_addr(),
+                   if pkg.get_operation() == ArpOperations::Request {
+                       "requesting".to_owned()
+                   } else {
+                       pkg.get_target_hw_addr().to_string()
+                   })
+        }
+        None => write!(f, "  Arp: truncated, {} bytes", payload.len()),
+    }
+}
+
+fn fmt_ipv4(payload: &[u8], f: &mut fmt::Formatter) -> fmt::Result {
+    match Ipv4Packet::new(payload) {
+        Some(pkg) => {
+            writeln!(f,
+                     "  Ipv4: {} -> {} ({:?}, ttl {})",
+                     pkg.get_source(),
+                     pkg.get_destination(),
+                     pkg.get_next_level_protocol(),
+                     pkg.get_ttl())?;
+            match pkg.get_next_level_protocol() {
+                IpNextHeaderProtocols::Udp => fmt_udp(pkg.payload(), f),
+                IpNextHeaderProtocols::Icmp => fmt_icmp(pkg.payload(), f),
+                other => write!(f, "    <{:?}, {} bytes>", other, pkg.payload().len()),
+            }
+        }
+        None => write!(f, "  Ipv4: truncated, {} bytes", payload.len()),
+    }
+}
+
+fn fmt_udp(payload: &[u8], f: &mut fmt::Formatter) -> fmt::Result {
+    match UdpPacket::new(payload) {
+        Some(pkg) => {
+            write!(f,
+                   "    Udp: port {} -> {}, {} bytes payload",
+                   pkg.get_source(),
+                   pkg.get_destination(),
+                   pkg.payload().len())
+        }
+        None => write!(f, "    Udp: truncated, {} bytes", payload.len()),
+    }
+}
+
+fn fmt_icmp(payload: &[u8], f: &mut fmt::Formatter) -> fmt::Result {
+    match IcmpPacket::new(payload) {
+        Some(pkg) => {
+            write!(f,
+                   "    Icmp: {:?} ({:?})",
+                   pkg.get_icmp_type(),
+                   pkg.get_icmp_code())
+        }
+        None => write!(f, "    Icmp: truncated, {} bytes", payload.len()),
+    }
+}
+
+/// `EthernetDataLinkSender` wrapping another sender, logging a
+/// `PrettyPrinter` dump of every frame passed through `build_and_send`
+/// before handing it on unchanged. Mirrors `pcap::PcapSender`: the frame is
+/// first built into a buffer of our own so it can be decoded, then replayed
+/// into the inner sender's buffer.
+pub struct TracingSender {
+    inner: Box<EthernetDataLinkSender>,
+}
+
+impl TracingSender {
+    pub fn new(inner: Box<EthernetDataLinkSender>) -> Self {
+        TracingSender { inner: inner }
+    }
+}
+
+impl EthernetDataLinkSender for TracingSender {
+    fn build_and_send(&mut self,
+                       num_packets: usize,
+                       packet_size: usize,
+                       func: &mut FnMut(MutableEthernetPacket))
+                       -> Option<io::Result<()>> {
+        let mut frames = Vec::with_capacity(num_packets);
+        for _ in 0..num_packets {
+            let mut buffer = vec![0; packet_size];
+            {
+                let packet = MutableEthernetPacket::new(&mut buffer).unwrap();
+                func(packet);
+            }
+            trace!("Sending frame:\n{}", PrettyPrinter::new(&buffer));
+            frames.push(buffer);
+        }
+        let mut frames = frames.into_iter();
+        let mut relay = |mut packet: MutableEthernetPacket| {
+            if let Some(frame) = frames.next() {
+                packet.packet_mut().copy_from_slice(&frame);
+            }
+        };
+        self.inner.build_and_send(num_packets, packet_size, &mut relay)
+    }
+}
+
+/// `EthernetDataLinkReceiver` wrapping another receiver, logging a
+/// `PrettyPrinter` dump of every frame read through its iterator before
+/// handing it on unchanged.
+pub struct TracingReceiver {
+    inner: Box<EthernetDataLinkReceiver>,
+}
+
+impl TracingReceiver {
+    pub fn new(inner: Box<EthernetDataLinkReceiver>) -> Self {
+        TracingReceiver { inner: inner }
+    }
+}
+
+impl EthernetDataLinkReceiver for TracingReceiver {
+    fn iter<'a>(&'a mut self) -> Box<EthernetDataLinkChannelIterator<'a> + 'a> {
+        Box::new(TracingIter { inner: self.inner.iter() })
+    }
+}
+
+struct TracingIter<'a> {
+    inner: Box<EthernetDataLinkChannelIterator<'a> + 'a>,
+}
+
+impl<'a> EthernetDataLinkChannelIterator<'a> for TracingIter<'a> {
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        let packet = self.inner.next()?;
+        trace!("Received frame:\n{}", PrettyPrinter::new(packet.packet()));
+        Ok(packet)
+    }
+}
+
+/// Wraps both halves of `channel` in `TracingSender`/`TracingReceiver` so
+/// every frame it sends or receives gets a `PrettyPrinter` dump logged at
+/// `trace` level. Composes with `pcap::capture_channel` and
+/// `testing::fault_injector`'s wrappers the same way they compose with each
+/// other, since all three only ever wrap the plain `EthernetChannel`.
+pub fn trace_channel(channel: EthernetChannel) -> EthernetChannel {
+    let EthernetChannel(tx, rx) = channel;
+    let tx = Box::new(TracingSender::new(tx));
+    let rx = Box::new(TracingReceiver::new(rx));
+    EthernetChannel(tx, rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pnet::packet::MutablePacket;
+    use pnet::packet::arp::{ArpHardwareTypes, ArpOperations, MutableArpPacket};
+    use pnet::packet::ethernet::EtherTypes;
+    use pnet::util::MacAddr;
+
+    use std::net::Ipv4Addr;
+    use std::sync::{Arc, Mutex};
+
+    fn arp_request_frame() -> Vec<u8> {
+        let mut buffer = vec![0; EthernetPacket::minimum_packet_size() +
+                                  ArpPacket::minimum_packet_size()];
+        {
+            let mut eth_pkg = MutableEthernetPacket::new(&mut buffer).unwrap();
+            eth_pkg.set_source(MacAddr::new(0, 0, 0, 0, 0, 1));
+            eth_pkg.set_destination(MacAddr::broadcast());
+            eth_pkg.set_ethertype(EtherTypes::Arp);
+            let mut arp_pkg = MutableArpPacket::new(eth_pkg.payload_mut()).unwrap();
+            arp_pkg.set_hardware_type(ArpHardwareTypes::Ethernet);
+            arp_pkg.set_protocol_type(EtherTypes::Ipv4);
+            arp_pkg.set_hw_addr_len(6);
+            arp_pkg.set_proto_addr_len(4);
+            arp_pkg.set_operation(ArpOperations::Request);
+            arp_pkg.set_sender_hw_addr(MacAddr::new(0, 0, 0, 0, 0, 1));
+            arp_pkg.set_sender_proto_addr(Ipv4Addr::new(10, 0, 0, 1));
+            arp_pkg.set_target_proto_addr(Ipv4Addr::new(10, 0, 0, 2));
+        }
+        buffer
+    }
+
+    #[test]
+    fn pretty_printer_decodes_ethernet_and_arp_headers() {
+        let buffer = arp_request_frame();
+        let output = format!("{}", PrettyPrinter::new(&buffer));
+        assert!(output.contains("Ethernet:"));
+        assert!(output.contains("Arp: Request"));
+        assert!(output.contains("10.0.0.1"));
+        assert!(output.contains("10.0.0.2"));
+    }
+
+    #[test]
+    fn pretty_printer_reports_truncated_frames_instead_of_panicking() {
+        let output = format!("{}", PrettyPrinter::new(&[0; 4]));
+        assert!(output.contains("truncated"));
+    }
+
+    struct RecordingSender {
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl EthernetDataLinkSender for RecordingSender {
+        fn build_and_send(&mut self,
+                           num_packets: usize,
+                           packet_size: usize,
+                           func: &mut FnMut(MutableEthernetPacket))
+                           -> Option<io::Result<()>> {
+            for _ in 0..num_packets {
+                let mut buffer = vec![0; packet_size];
+                {
+                    let packet = MutableEthernetPacket::new(&mut buffer).unwrap();
+                    func(packet);
+                }
+                self.sent.lock().unwrap().push(buffer);
+            }
+            Some(Ok(()))
+        }
+    }
+
+    #[test]
+    fn tracing_sender_still_forwards_frames_unchanged() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let inner = Box::new(RecordingSender { sent: sent.clone() });
+        let mut sender = TracingSender::new(inner);
+
+        sender.build_and_send(1, 14, &mut |mut packet: MutableEthernetPacket| {
+            packet.packet_mut()[0] = 0xab;
+        });
+
+        assert_eq!(1, sent.lock().unwrap().len());
+        assert_eq!(0xab, sent.lock().unwrap()[0][0]);
+    }
+}
@@ -8,6 +8,12 @@ use {TxError, TxResult};
 use {NetworkStack, StackError, StackResult};
 
 use util;
+#[cfg(not(feature = "unit-tests"))]
+use ethernet::EthernetTxImpl;
+#[cfg(not(feature = "unit-tests"))]
+use ipv4::Ipv4TxImpl;
+#[cfg(not(feature = "unit-tests"))]
+use tx::TxImpl;
 
 mod udp_rx;
 mod udp_tx;
@@ -17,11 +23,18 @@ pub use self::udp_tx::{UdpTx, UdpBuilder};
 
 use self::udp_rx::UdpSocketReader;
 
+/// Concrete `UdpTx` type handed out by `NetworkStack::udp_tx`. Named here so
+/// `UdpSocket` does not have to spell out the full `Ipv4Tx`/`EthernetTx`
+/// stack every time, the same way `raw::RawSocket` caches a concrete
+/// `RawTx`.
+#[cfg(not(feature = "unit-tests"))]
+type StackUdpTx = UdpTx<Ipv4TxImpl<EthernetTxImpl<TxImpl>>>;
+
 #[cfg(not(feature = "unit-tests"))]
 pub struct UdpSocket {
     socket_addr: SocketAddr,
     stack: Arc<Mutex<NetworkStack>>,
-    tx_cache: HashMap<SocketAddrV4, UdpTx>,
+    tx_cache: HashMap<SocketAddrV4, StackUdpTx>,
     rx: Option<UdpSocketReader>,
 }
 
@@ -54,10 +67,7 @@ impl UdpSocket {
                     .map(|_| buf.len())
                     .map_err(|e| e.into())
             }
-            SocketAddr::V6(_dst) => {
-                Err(io::Error::new(io::ErrorKind::InvalidInput,
-                                   "Rips does not support IPv6 yet".to_owned()))
-            }
+            SocketAddr::V6(_dst) => Err(util::unsupported_ipv6()),
         }
     }
 
@@ -1,29 +1,60 @@
+use arc_swap::ArcSwap;
+
 use {RxError, RxResult};
+use checksum::ChecksumCapabilities;
+use icmp::IcmpError;
 use ipv4::Ipv4Listener;
+use stack::StackInterfaceMsg;
 
 use pnet::packet::Packet;
 use pnet::packet::ipv4::Ipv4Packet;
-use pnet::packet::udp::UdpPacket;
+use pnet::packet::udp::{UdpPacket, ipv4_checksum};
 
 use std::collections::HashMap;
 use std::io;
 use std::net::{SocketAddr, SocketAddrV4};
 use std::sync::{Arc, Mutex, mpsc};
+use std::sync::mpsc::Sender;
 use std::time::SystemTime;
 
 pub trait UdpListener: Send {
     fn recv(&mut self, time: SystemTime, packet: &Ipv4Packet) -> (RxResult, bool);
 }
 
-pub type UdpListenerLookup = HashMap<u16, Box<UdpListener>>;
+/// Each listener is individually wrapped in its own `Mutex` so that
+/// `UdpRx::recv` can dispatch via a lock-free `ArcSwap::load` and only ever
+/// has to take a fine grained, almost never contended, per-listener lock.
+pub type UdpListenerLookup = HashMap<u16, Arc<Mutex<Box<UdpListener>>>>;
 
+/// Listener and parser of Udp datagrams. There is no catch-all socket, so a
+/// datagram addressed to a port nothing is bound to is reported to the
+/// owning `StackInterfaceThread` so it can reply with an Icmp Destination
+/// Unreachable (Port Unreachable), the same way `TcpRx` asks for a RST when
+/// nothing claims a segment.
 pub struct UdpRx {
-    listeners: Arc<Mutex<UdpListenerLookup>>,
+    listeners: Arc<ArcSwap<UdpListenerLookup>>,
+    stack_tx: Sender<StackInterfaceMsg>,
+    checksums: ChecksumCapabilities,
 }
 
 impl UdpRx {
-    pub fn new(listeners: Arc<Mutex<UdpListenerLookup>>) -> UdpRx {
-        UdpRx { listeners: listeners }
+    pub fn new(listeners: Arc<ArcSwap<UdpListenerLookup>>,
+               stack_tx: Sender<StackInterfaceMsg>)
+               -> UdpRx {
+        Self::with_checksums(listeners, stack_tx, ChecksumCapabilities::default())
+    }
+
+    /// Creates a new `UdpRx`, skipping checksum verification according to
+    /// `checksums` when the underlying NIC already did it in hardware.
+    pub fn with_checksums(listeners: Arc<ArcSwap<UdpListenerLookup>>,
+                           stack_tx: Sender<StackInterfaceMsg>,
+                           checksums: ChecksumCapabilities)
+                           -> UdpRx {
+        UdpRx {
+            listeners: listeners,
+            stack_tx: stack_tx,
+            checksums: checksums,
+        }
     }
 
     fn get_port(pkg: &Ipv4Packet) -> Result<u16, RxError> {
@@ -41,17 +72,48 @@ impl UdpRx {
             Ok(port)
         }
     }
+
+    /// Verifies the Udp checksum of `pkg`, unless `self.checksums.udp` says
+    /// the NIC already did so in hardware. Assumes `get_port` has already
+    /// validated the payload is at least as long as a Udp header.
+    fn verify_checksum(&self, pkg: &Ipv4Packet) -> RxResult {
+        if self.checksums.udp.rx() {
+            let udp_pkg = UdpPacket::new(pkg.payload()).ok_or(RxError::InvalidContent)?;
+            let expected = ipv4_checksum(&udp_pkg, pkg.get_source(), pkg.get_destination());
+            if udp_pkg.get_checksum() != expected {
+                return Err(RxError::InvalidContent);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells the owning `StackInterfaceThread` that `ip_pkg` carried a Udp
+    /// datagram for a port nothing is listening on, so it can reply with an
+    /// Icmp Destination Unreachable (Port Unreachable), the same way
+    /// smoltcp's `process_udpv4` answers an unbound port. The thread itself
+    /// is responsible for suppressing this when error replies are disabled,
+    /// when the datagram's destination was a broadcast/multicast address,
+    /// or when `ip_pkg` was itself an Icmp packet.
+    fn report_port_unreachable(&self, ip_pkg: &Ipv4Packet) {
+        let error = IcmpError::port_unreachable();
+        let src = ip_pkg.get_destination();
+        let dst = ip_pkg.get_source();
+        let msg = StackInterfaceMsg::IcmpUnreachable(error, src, dst, ip_pkg.packet().to_vec());
+        self.stack_tx.send(msg).unwrap_or(());
+    }
 }
 
 impl Ipv4Listener for UdpRx {
     fn recv(&mut self, time: SystemTime, ip_pkg: Ipv4Packet) -> RxResult {
         let port = try!(Self::get_port(&ip_pkg));
-        let mut listeners = self.listeners.lock().unwrap();
-        if let Some(listener) = listeners.get_mut(&port) {
-            let (result, _resume) = listener.recv(time, &ip_pkg);
+        try!(self.verify_checksum(&ip_pkg));
+        let listeners = self.listeners.load();
+        if let Some(listener) = listeners.get(&port) {
+            let (result, _resume) = listener.lock().unwrap().recv(time, &ip_pkg);
             result
             // TODO: When resume turns false, remove this socket.
         } else {
+            self.report_port_unreachable(&ip_pkg);
             Err(RxError::NoListener(format!("Udp, no listener for port {:?}", port)))
         }
     }
@@ -104,3 +166,111 @@ impl UdpSocketReader {
         self.chan.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::Checksum;
+
+    use pnet::packet::MutablePacket;
+    use pnet::packet::icmp::destination_unreachable::IcmpCodes as DestinationUnreachableCodes;
+    use pnet::packet::ipv4::MutableIpv4Packet;
+    use pnet::packet::udp::MutableUdpPacket;
+
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+    use std::sync::mpsc;
+
+    #[test]
+    fn verify_checksum_skips_verification_when_offloaded() {
+        let src_ip = Ipv4Addr::new(10, 0, 0, 1);
+        let dst_ip = Ipv4Addr::new(10, 0, 0, 2);
+
+        let size = Ipv4Packet::minimum_packet_size() + UdpPacket::minimum_packet_size();
+        let mut buffer = vec![0u8; size];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(size as u16);
+            ip_pkg.set_source(src_ip);
+            ip_pkg.set_destination(dst_ip);
+            let mut udp_pkg = MutableUdpPacket::new(ip_pkg.payload_mut()).unwrap();
+            udp_pkg.set_length(UdpPacket::minimum_packet_size() as u16);
+            // Deliberately wrong checksum, as if the NIC hadn't verified it.
+            udp_pkg.set_checksum(0);
+        }
+        let ip_pkg = Ipv4Packet::new(&buffer).unwrap();
+
+        let (stack_tx, _stack_rx) = mpsc::channel();
+        let mut checksums = ChecksumCapabilities::default();
+        checksums.udp = Checksum::Tx;
+        let udp_rx = UdpRx::with_checksums(Arc::new(ArcSwap::new(Arc::new(HashMap::new()))),
+                                            stack_tx,
+                                            checksums);
+
+        assert!(udp_rx.verify_checksum(&ip_pkg).is_ok());
+    }
+
+    #[test]
+    fn verify_checksum_rejects_bad_checksum_by_default() {
+        let src_ip = Ipv4Addr::new(10, 0, 0, 1);
+        let dst_ip = Ipv4Addr::new(10, 0, 0, 2);
+
+        let size = Ipv4Packet::minimum_packet_size() + UdpPacket::minimum_packet_size();
+        let mut buffer = vec![0u8; size];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(size as u16);
+            ip_pkg.set_source(src_ip);
+            ip_pkg.set_destination(dst_ip);
+            let mut udp_pkg = MutableUdpPacket::new(ip_pkg.payload_mut()).unwrap();
+            udp_pkg.set_length(UdpPacket::minimum_packet_size() as u16);
+            udp_pkg.set_checksum(0);
+        }
+        let ip_pkg = Ipv4Packet::new(&buffer).unwrap();
+
+        let (stack_tx, _stack_rx) = mpsc::channel();
+        let udp_rx = UdpRx::new(Arc::new(ArcSwap::new(Arc::new(HashMap::new()))), stack_tx);
+
+        assert!(udp_rx.verify_checksum(&ip_pkg).is_err());
+    }
+
+    #[test]
+    fn recv_reports_port_unreachable_when_no_listener_is_bound() {
+        let src_ip = Ipv4Addr::new(10, 0, 0, 1);
+        let dst_ip = Ipv4Addr::new(10, 0, 0, 2);
+
+        let size = Ipv4Packet::minimum_packet_size() + UdpPacket::minimum_packet_size();
+        let mut buffer = vec![0u8; size];
+        {
+            let mut ip_pkg = MutableIpv4Packet::new(&mut buffer).unwrap();
+            ip_pkg.set_header_length(5);
+            ip_pkg.set_total_length(size as u16);
+            ip_pkg.set_source(src_ip);
+            ip_pkg.set_destination(dst_ip);
+            let mut udp_pkg = MutableUdpPacket::new(ip_pkg.payload_mut()).unwrap();
+            udp_pkg.set_destination(12345);
+            udp_pkg.set_length(UdpPacket::minimum_packet_size() as u16);
+        }
+        let ip_pkg = Ipv4Packet::owned(buffer).unwrap();
+
+        let (stack_tx, stack_rx) = mpsc::channel();
+        let mut checksums = ChecksumCapabilities::default();
+        checksums.udp = Checksum::Tx;
+        let mut udp_rx = UdpRx::with_checksums(Arc::new(ArcSwap::new(Arc::new(HashMap::new()))),
+                                                stack_tx,
+                                                checksums);
+
+        assert!(udp_rx.recv(SystemTime::now(), ip_pkg).is_err());
+
+        match stack_rx.try_recv() {
+            Ok(StackInterfaceMsg::IcmpUnreachable(IcmpError::DestinationUnreachable(code), src, dst, _)) => {
+                assert_eq!(DestinationUnreachableCodes::DestinationPortUnreachable, code);
+                assert_eq!(dst_ip, src);
+                assert_eq!(src_ip, dst);
+            }
+            other => panic!("Expected an IcmpUnreachable message, got {:?}", other),
+        }
+    }
+}
@@ -3,30 +3,37 @@ use std::net::Ipv4Addr;
 use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
 use pnet::packet::udp::{MutableUdpPacket, UdpPacket, ipv4_checksum_adv};
 
-use {Protocol, TxResult};
+use {Payload, TxResult};
 
-use ipv4::{Ipv4Protocol, Ipv4Tx};
+use checksum::Checksum;
+use ipv4::{Ipv4Payload, Ipv4Tx};
 
-pub struct UdpTx {
+/// Udp packet sender struct.
+pub struct UdpTx<T: Ipv4Tx> {
     src: u16,
     dst: u16,
-    ipv4: Ipv4Tx,
+    checksum: Checksum,
+    ipv4: T,
 }
 
-impl UdpTx {
-    pub fn new(ipv4: Ipv4Tx, src: u16, dst: u16) -> UdpTx {
+impl<T: Ipv4Tx> UdpTx<T> {
+    /// Creates a new `UdpTx` based on `ipv4`, sending from `src` to `dst`.
+    /// Inherits its checksum offload capability from `ipv4`.
+    pub fn new(ipv4: T, src: u16, dst: u16) -> Self {
+        let checksum = ipv4.checksums().udp;
         UdpTx {
             src: src,
             dst: dst,
+            checksum: checksum,
             ipv4: ipv4,
         }
     }
 
     pub fn send(&mut self, payload: &[u8]) -> TxResult {
         let (src_port, dst_port) = (self.src, self.dst);
-        let src_ip = self.ipv4.src;
-        let dst_ip = self.ipv4.dst;
-        let builder = UdpBuilder::new(src_ip, dst_ip, src_port, dst_port, payload);
+        let src_ip = self.ipv4.src();
+        let dst_ip = self.ipv4.dst();
+        let builder = UdpBuilder::new(src_ip, dst_ip, src_port, dst_port, self.checksum, payload);
         self.ipv4.send(builder)
     }
 }
@@ -36,6 +43,7 @@ pub struct UdpBuilder<'a> {
     dst_ip: Ipv4Addr,
     src: u16,
     dst: u16,
+    checksum: Checksum,
     offset: usize,
     payload: &'a [u8],
 }
@@ -45,6 +53,7 @@ impl<'a> UdpBuilder<'a> {
                dst_ip: Ipv4Addr,
                src_port: u16,
                dst_port: u16,
+               checksum: Checksum,
                payload: &'a [u8])
                -> UdpBuilder<'a> {
         UdpBuilder {
@@ -52,19 +61,20 @@ impl<'a> UdpBuilder<'a> {
             dst_ip: dst_ip,
             src: src_port,
             dst: dst_port,
+            checksum: checksum,
             offset: 0,
             payload: payload,
         }
     }
 }
 
-impl<'a> Ipv4Protocol for UdpBuilder<'a> {
+impl<'a> Ipv4Payload for UdpBuilder<'a> {
     fn next_level_protocol(&self) -> IpNextHeaderProtocol {
         IpNextHeaderProtocols::Udp
     }
 }
 
-impl<'a> Protocol for UdpBuilder<'a> {
+impl<'a> Payload for UdpBuilder<'a> {
     fn len(&self) -> usize {
         UdpPacket::minimum_packet_size() + self.payload.len()
     }
@@ -77,9 +87,11 @@ impl<'a> Protocol for UdpBuilder<'a> {
                 pkg.set_source(self.src);
                 pkg.set_destination(self.dst);
                 pkg.set_length(self.len() as u16);
-                let checksum =
-                    ipv4_checksum_adv(&pkg.to_immutable(), self.payload, self.src_ip, self.dst_ip);
-                pkg.set_checksum(checksum);
+                if self.checksum.tx() {
+                    let checksum =
+                        ipv4_checksum_adv(&pkg.to_immutable(), self.payload, self.src_ip, self.dst_ip);
+                    pkg.set_checksum(checksum);
+                }
             }
             &mut buffer[UdpPacket::minimum_packet_size()..]
         } else {
@@ -1,33 +1,82 @@
+use std::cmp;
 use std::ops::{Deref, DerefMut};
 
-/// Structure used to reassemble data arriving in fragments.
-/// Supposed to handle out of order arrival, but does not at the moment.
+/// Structure used to reassemble data arriving in fragments, possibly out of
+/// order.
 pub struct Buffer {
     data: Vec<u8>,
-    lowest_missing: usize,
+    /// Sorted, non-overlapping, non-adjacent byte ranges that have been
+    /// filled in `data` so far, as `(start, end)` with `end` exclusive.
+    filled: Vec<(usize, usize)>,
+    /// Length of the contiguous valid data at the start of `data`. This is
+    /// the `end` of `filled[0]` when that range starts at zero, `0`
+    /// otherwise.
+    valid_prefix: usize,
 }
 
 impl Buffer {
     pub fn new(capacity: usize) -> Buffer {
         Buffer {
             data: vec![0; capacity],
-            lowest_missing: 0,
+            filled: Vec::new(),
+            valid_prefix: 0,
         }
     }
 
-    /// Push new data to this `Buffer`. Returns the lowest index of missing
-    /// data on success.
-    /// This is equivalent to the length of the valid data at the start of the
-    /// buffer. Will fail if the given data offset is not valid.
-    // TODO: Support out of order data
+    /// Push new data to this `Buffer` at `offset`. Returns the length of the
+    /// contiguous valid data at the start of the buffer after this push.
+    /// Data is allowed to arrive out of order and is coalesced with any
+    /// ranges already received. Pushing data that overlaps an already
+    /// filled range is tolerated, the new bytes simply overwrite the old
+    /// ones there. Fails if `offset + data.len()` does not fit inside the
+    /// buffer's capacity.
     pub fn push(&mut self, offset: usize, data: &[u8]) -> Result<usize, ()> {
-        if offset == self.lowest_missing {
-            self.lowest_missing += data.len();
-        } else {
+        if data.is_empty() {
+            return Ok(self.valid_prefix);
+        }
+        let end = offset + data.len();
+        if end > self.data.len() {
             return Err(());
         }
-        self.data[offset..offset + data.len()].copy_from_slice(data);
-        Ok(self.lowest_missing)
+        self.data[offset..end].copy_from_slice(data);
+        self.insert_range(offset, end);
+        self.valid_prefix = match self.filled.first() {
+            Some(&(start, end)) if start == 0 => end,
+            _ => 0,
+        };
+        Ok(self.valid_prefix)
+    }
+
+    /// Returns `true` if every byte up to (but not including) `total_len`
+    /// has been received. Used by the IP fragment reassembly layer to know
+    /// when a datagram is complete and can be delivered.
+    pub fn is_complete(&self, total_len: usize) -> bool {
+        self.valid_prefix >= total_len
+    }
+
+    /// Inserts the interval `[start, end)` into `self.filled`, keeping it
+    /// sorted, and coalesces it with any overlapping or directly adjacent
+    /// neighbours.
+    fn insert_range(&mut self, start: usize, end: usize) {
+        let i = self.filled.iter().position(|&(s, _)| s > start).unwrap_or(self.filled.len());
+        self.filled.insert(i, (start, end));
+        if i > 0 && self.filled[i - 1].1 >= self.filled[i].0 {
+            self.filled[i - 1].1 = cmp::max(self.filled[i - 1].1, self.filled[i].1);
+            self.filled.remove(i);
+            self.merge_right(i - 1);
+        } else {
+            self.merge_right(i);
+        }
+    }
+
+    /// Merges `self.filled[i]` with any following intervals it now
+    /// overlaps or touches.
+    fn merge_right(&mut self, i: usize) {
+        while i + 1 < self.filled.len() && self.filled[i + 1].0 <= self.filled[i].1 {
+            let next_end = self.filled[i + 1].1;
+            self.filled[i].1 = cmp::max(self.filled[i].1, next_end);
+            self.filled.remove(i + 1);
+        }
     }
 
     /// Consumes the `Buffer` and returns the data in an owned slice
@@ -40,18 +89,81 @@ impl Deref for Buffer {
     type Target = [u8];
 
     fn deref(&self) -> &[u8] {
-        &self.data[..self.lowest_missing]
+        &self.data[..self.valid_prefix]
     }
 }
 
 impl DerefMut for Buffer {
     fn deref_mut(&mut self) -> &mut [u8] {
-        &mut self.data[..self.lowest_missing]
+        &mut self.data[..self.valid_prefix]
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    // TODO: Write unit tests
+    use super::*;
+
+    #[test]
+    fn in_order_push() {
+        let mut buffer = Buffer::new(10);
+        assert_eq!(Ok(3), buffer.push(0, &[1, 2, 3]));
+        assert_eq!(Ok(6), buffer.push(3, &[4, 5, 6]));
+        assert_eq!(&[1, 2, 3, 4, 5, 6], &*buffer);
+    }
+
+    #[test]
+    fn out_of_order_push_fills_gap() {
+        let mut buffer = Buffer::new(10);
+        assert_eq!(Ok(0), buffer.push(3, &[4, 5, 6]));
+        assert_eq!(Ok(6), buffer.push(0, &[1, 2, 3]));
+        assert_eq!(&[1, 2, 3, 4, 5, 6], &*buffer);
+    }
+
+    #[test]
+    fn disjoint_ranges_do_not_advance_prefix() {
+        let mut buffer = Buffer::new(10);
+        assert_eq!(Ok(0), buffer.push(5, &[6, 7]));
+        assert_eq!(Ok(0), buffer.push(2, &[3, 4]));
+        assert_eq!(Ok(2), buffer.push(0, &[1, 2]));
+    }
+
+    #[test]
+    fn adjacent_ranges_coalesce() {
+        let mut buffer = Buffer::new(10);
+        assert_eq!(Ok(0), buffer.push(2, &[3, 4]));
+        assert_eq!(Ok(0), buffer.push(4, &[5, 6]));
+        assert_eq!(Ok(6), buffer.push(0, &[1, 2]));
+    }
+
+    #[test]
+    fn overlapping_push_is_tolerated() {
+        let mut buffer = Buffer::new(10);
+        assert_eq!(Ok(4), buffer.push(0, &[1, 2, 3, 9]));
+        assert_eq!(Ok(6), buffer.push(3, &[4, 5, 6]));
+        assert_eq!(&[1, 2, 3, 4, 5, 6], &*buffer);
+    }
+
+    #[test]
+    fn push_beyond_capacity_fails() {
+        let mut buffer = Buffer::new(4);
+        assert_eq!(Err(()), buffer.push(2, &[1, 2, 3]));
+    }
+
+    #[test]
+    fn zero_length_push_is_a_no_op() {
+        let mut buffer = Buffer::new(10);
+        assert_eq!(Ok(0), buffer.push(0, &[]));
+        assert_eq!(Ok(3), buffer.push(0, &[1, 2, 3]));
+        assert_eq!(Ok(3), buffer.push(5, &[]));
+    }
+
+    #[test]
+    fn is_complete_reflects_known_total_length() {
+        let mut buffer = Buffer::new(10);
+        buffer.push(0, &[1, 2, 3]);
+        assert!(!buffer.is_complete(6));
+        buffer.push(3, &[4, 5, 6]);
+        assert!(buffer.is_complete(6));
+    }
 }
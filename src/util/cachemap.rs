@@ -1,20 +1,72 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
 use std::hash::Hash;
 use std::time::{Duration, Instant};
 
+/// A `HashMap` entry together with the instant it expires at. Ordered only
+/// by `expires_at`, so a `BinaryHeap` of these can be used as a min-heap
+/// finding the next entry to expire without caring which key it is.
+struct Expiry<K> {
+    expires_at: Instant,
+    key: K,
+}
+
+impl<K> PartialEq for Expiry<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.expires_at == other.expires_at
+    }
+}
+
+impl<K> Eq for Expiry<K> {}
+
+impl<K> PartialOrd for Expiry<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K> Ord for Expiry<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.expires_at.cmp(&other.expires_at)
+    }
+}
+
+/// A `HashMap` where every entry expires `timeout` after it was inserted.
+/// Expired entries are hidden at once by `get`/`get_mut`, and removed on
+/// every `insert` (or whenever the caller calls `purge`), so entries
+/// that are never looked up again do not live forever. Optionally bounded
+/// to a maximum number of entries, evicting whichever live entry expires
+/// soonest to make room for a new key once full.
 pub struct CacheMap<K, V> {
     map: HashMap<K, (Instant, V)>,
+    expiries: BinaryHeap<Reverse<Expiry<K>>>,
     timeout: Duration,
+    capacity: Option<usize>,
 }
 
 impl<K, V> CacheMap<K, V>
-    where K: Hash + Eq
+    where K: Hash + Eq + Clone
 {
+    /// Creates an unbounded `CacheMap` where entries live for `timeout`.
     pub fn new(timeout: Duration) -> CacheMap<K, V> {
         CacheMap {
             map: HashMap::new(),
+            expiries: BinaryHeap::new(),
+            timeout: timeout,
+            capacity: None,
+        }
+    }
+
+    /// Creates a `CacheMap` holding at most `capacity` entries, each living
+    /// for `timeout`. Once full, `insert`ing a new key evicts whichever
+    /// live entry is closest to expiring.
+    pub fn with_capacity(capacity: usize, timeout: Duration) -> CacheMap<K, V> {
+        CacheMap {
+            map: HashMap::new(),
+            expiries: BinaryHeap::new(),
             timeout: timeout,
+            capacity: Some(capacity),
         }
     }
 
@@ -22,9 +74,10 @@ impl<K, V> CacheMap<K, V>
         where K: Borrow<Q>,
               Q: Hash + Eq
     {
-        if let Some(&(ref i, ref v)) = self.map.get(k) {
-            if i.elapsed() < self.timeout {
-                Some(&v)
+        let now = Instant::now();
+        if let Some(&(expires_at, ref v)) = self.map.get(k) {
+            if expires_at > now {
+                Some(v)
             } else {
                 None
             }
@@ -37,8 +90,9 @@ impl<K, V> CacheMap<K, V>
         where K: Borrow<Q>,
               Q: Hash + Eq
     {
-        if let Some(&mut (ref i, ref mut v)) = self.map.get_mut(k) {
-            if i.elapsed() < self.timeout {
+        let now = Instant::now();
+        if let Some(&mut (expires_at, ref mut v)) = self.map.get_mut(k) {
+            if expires_at > now {
                 Some(v)
             } else {
                 None
@@ -48,8 +102,110 @@ impl<K, V> CacheMap<K, V>
         }
     }
 
+    /// Returns whether `k` has a live (not yet expired) entry.
+    pub fn contains_key<Q: ?Sized>(&mut self, k: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: Hash + Eq
+    {
+        self.get(k).is_some()
+    }
+
+    /// The number of entries currently stored, including any that have
+    /// expired but have not been removed by `insert` or `purge` yet.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Removes and returns the value for `k`, if it had a live entry.
+    pub fn remove<Q: ?Sized>(&mut self, k: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              Q: Hash + Eq
+    {
+        let now = Instant::now();
+        match self.map.remove(k) {
+            Some((expires_at, v)) if expires_at > now => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Inserts `v` for `k`, valid until `timeout` from now. Purges every
+    /// expired entry, then, if this is a new key and the map is already at
+    /// capacity, evicts the live entry closest to expiring to make room.
     pub fn insert(&mut self, k: K, v: V) {
-        self.map.insert(k, (Instant::now(), v));
+        self.purge();
+        if let Some(capacity) = self.capacity {
+            if !self.map.contains_key(&k) && self.map.len() >= capacity {
+                self.evict_oldest();
+            }
+        }
+        let expires_at = Instant::now() + self.timeout;
+        self.map.insert(k.clone(), (expires_at, v));
+        self.expiries.push(Reverse(Expiry { expires_at: expires_at, key: k }));
+    }
+
+    /// Removes every entry whose `timeout` has elapsed. Called on every
+    /// `insert`, but also exposed so a long lived `CacheMap` with
+    /// infrequent inserts can still be kept tidy by calling this
+    /// periodically, the same way `ArpTable::flush_expired` must be.
+    pub fn purge(&mut self) {
+        let now = Instant::now();
+        while let Some(&Reverse(Expiry { expires_at, .. })) = self.expiries.peek() {
+            if expires_at > now {
+                break;
+            }
+            let Reverse(Expiry { expires_at, key }) = self.expiries.pop().unwrap();
+            // The key may have been reinserted since this heap entry was
+            // pushed, in which case the map holds a newer expiry for it and
+            // this entry is stale and must not remove the live one.
+            let is_live = match self.map.get(&key) {
+                Some(&(current_expires_at, _)) => current_expires_at == expires_at,
+                None => false,
+            };
+            if is_live {
+                self.map.remove(&key);
+            }
+        }
+    }
+
+    /// Removes and returns every entry whose `timeout` has elapsed, the
+    /// same entries `purge` would otherwise silently drop. Useful when the
+    /// caller needs to act on what expired, e.g. replying with an Icmp
+    /// error for a reassembly that never completed.
+    pub fn take_expired(&mut self) -> Vec<(K, V)> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        while let Some(&Reverse(Expiry { expires_at, .. })) = self.expiries.peek() {
+            if expires_at > now {
+                break;
+            }
+            let Reverse(Expiry { expires_at, key }) = self.expiries.pop().unwrap();
+            let is_live = match self.map.get(&key) {
+                Some(&(current_expires_at, _)) => current_expires_at == expires_at,
+                None => false,
+            };
+            if is_live {
+                if let Some((_, v)) = self.map.remove(&key) {
+                    expired.push((key, v));
+                }
+            }
+        }
+        expired
+    }
+
+    /// Evicts whichever live entry is closest to expiring, to make room for
+    /// a new key in a map that is already at capacity. Also drops every
+    /// stale heap entry found along the way.
+    fn evict_oldest(&mut self) {
+        while let Some(Reverse(Expiry { expires_at, key })) = self.expiries.pop() {
+            let is_live = match self.map.get(&key) {
+                Some(&(current_expires_at, _)) => current_expires_at == expires_at,
+                None => false,
+            };
+            if is_live {
+                self.map.remove(&key);
+                return;
+            }
+        }
     }
 }
 
@@ -91,4 +247,66 @@ mod tests {
         assert!(testee.get(&0).is_none());
         assert!(testee.get(&1).is_none());
     }
+
+    #[test]
+    fn purge_actively_removes_expired_entries_without_a_get() {
+        let mut testee = CacheMap::new(Duration::new(0, 10_000_000));
+        testee.insert(0, "a");
+        sleep(Duration::new(0, 20_000_000));
+        testee.purge();
+        // `len` counts every stored entry, expired or not, so this shows the
+        // expired entry was actually removed, not just hidden by `get`.
+        assert_eq!(0, testee.len());
+
+        testee.insert(1, "b");
+        assert_eq!(testee.get(&1), Some(&"b"));
+    }
+
+    #[test]
+    fn refreshing_a_key_is_not_evicted_by_a_stale_heap_entry() {
+        let mut testee = CacheMap::new(Duration::new(1, 0));
+        testee.insert(0, "first");
+        testee.insert(0, "second");
+        testee.purge();
+
+        assert_eq!(testee.get(&0), Some(&"second"));
+    }
+
+    #[test]
+    fn with_capacity_evicts_the_closest_to_expiring_entry_when_full() {
+        let mut testee = CacheMap::with_capacity(2, Duration::new(60, 0));
+        testee.insert(0, "a");
+        testee.insert(1, "b");
+        testee.insert(2, "c");
+
+        assert!(testee.get(&0).is_none());
+        assert_eq!(testee.get(&1), Some(&"b"));
+        assert_eq!(testee.get(&2), Some(&"c"));
+    }
+
+    #[test]
+    fn with_capacity_does_not_evict_when_reinserting_an_existing_key() {
+        let mut testee = CacheMap::with_capacity(2, Duration::new(60, 0));
+        testee.insert(0, "a");
+        testee.insert(1, "b");
+        testee.insert(0, "updated");
+
+        assert_eq!(testee.get(&0), Some(&"updated"));
+        assert_eq!(testee.get(&1), Some(&"b"));
+    }
+
+    #[test]
+    fn take_expired_returns_expired_entries_and_removes_them() {
+        let mut testee = CacheMap::new(Duration::new(0, 10_000_000));
+        testee.insert(0, "a");
+        testee.insert(1, "b");
+        sleep(Duration::new(0, 20_000_000));
+
+        let mut expired = testee.take_expired();
+        expired.sort_by_key(|&(k, _)| k);
+        assert_eq!(vec![(0, "a"), (1, "b")], expired);
+        assert!(testee.get(&0).is_none());
+        assert!(testee.get(&1).is_none());
+        assert!(testee.take_expired().is_empty());
+    }
 }
@@ -1,8 +1,8 @@
 use std::io;
 use std::net::{SocketAddr, ToSocketAddrs};
 
-// mod cachemap;
-// pub use util::cachemap::CacheMap;
+mod cachemap;
+pub use util::cachemap::CacheMap;
 
 mod buffer;
 
@@ -16,3 +16,14 @@ pub fn first_socket_addr<A: ToSocketAddrs>(addr: A) -> io::Result<SocketAddr> {
                            "Given ToSocketAddrs did not yield any address".to_owned()))
     }
 }
+
+/// Standard error for every `SocketAddr::V6` given to an API that only
+/// handles `Ipv4Addr` today. `Ipv4Tx`/`Ipv4Rx` (and everything built on
+/// them: Udp, Tcp, Icmp, Arp) are hard-wired to `Ipv4Addr` throughout the
+/// stack, so dual-stack support is a cross-cutting rewrite rather than a
+/// single addressable change; this at least gives callers one consistent
+/// message instead of a slightly different string at each call site.
+pub fn unsupported_ipv6() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput,
+                   "Rips does not support IPv6 yet".to_owned())
+}
@@ -3,7 +3,7 @@ use pnet::packet::arp::{ArpPacket, MutableArpPacket};
 use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
 use pnet::util::MacAddr;
 
-use rips::arp::{ArpTable, ArpTx};
+use rips::arp::{ArpTable, ArpTx, Miss};
 use rips::ethernet::{EthernetRx, EthernetTx, EthernetTxImpl};
 use rips::testing;
 use rips::rx;
@@ -50,7 +50,8 @@ fn arp_locking() {
         spawn(move || {
             let mac = match thread_arp_table.get(dst) {
                 Ok(mac) => mac,
-                Err(rx) => rx.recv().unwrap(),
+                Err(Miss::Unresolved(rx)) => rx.recv().unwrap(),
+                Err(Miss::Pending(rx)) => rx.recv().unwrap(),
             };
             arp_thread_tx.send(mac).expect("Unable to send mac to channel");
         });
@@ -10,7 +10,7 @@ use pnet::packet::ethernet::{MutableEthernetPacket, EthernetPacket};
 use pnet::util::{NetworkInterface, MacAddr};
 use pnet::packet::Packet;
 
-use rips::ethernet::{EthernetListener, EthernetProvider};
+use rips::ethernet::{EthernetDevice, EthernetListener, EthernetProvider, RxToken, TxToken};
 
 pub fn dummy_iface(i: u8) -> NetworkInterface {
     NetworkInterface {
@@ -154,3 +154,81 @@ impl EthernetListener for MockEthernetListener {
         self.tx.send(packet.packet().to_vec()).unwrap();
     }
 }
+
+/// `RxToken` handed out by `MockEthernetDevice`, wrapping a single
+/// already-received frame.
+pub struct MockRxToken {
+    time: time::SystemTime,
+    data: Box<[u8]>,
+}
+
+impl RxToken for MockRxToken {
+    fn consume<R, F>(self, f: F) -> io::Result<R>
+        where F: FnOnce(time::SystemTime, &[u8]) -> io::Result<R>
+    {
+        f(self.time, &self.data)
+    }
+}
+
+/// `TxToken` handed out by `MockEthernetDevice`. Borrows the device's
+/// reused scratch buffer instead of allocating a fresh one for every frame.
+pub struct MockTxToken<'a> {
+    scratch: &'a mut [u8],
+    out_channel: &'a Sender<Vec<u8>>,
+}
+
+impl<'a> TxToken for MockTxToken<'a> {
+    fn consume<R, F>(self, f: F) -> io::Result<R>
+        where F: FnOnce(&mut [u8]) -> io::Result<R>
+    {
+        let result = f(self.scratch);
+        self.out_channel.send(self.scratch.to_vec()).unwrap();
+        result
+    }
+}
+
+/// Token-based replacement for `MockEthernetDataLinkSender`/
+/// `MockEthernetDataLinkReceiver`. Unlike `MockPnet::channel`, which stores
+/// its channels as `Option`s so they can be `take()`n out of `&mut self`
+/// once, this is constructed directly from owned channels, and transmitted
+/// frames are built straight into a scratch buffer reused across calls to
+/// `transmit` instead of allocating a fresh `Vec` per packet.
+pub struct MockEthernetDevice {
+    in_packets: Receiver<io::Result<Box<[u8]>>>,
+    out_channel: Sender<Vec<u8>>,
+    scratch: Vec<u8>,
+}
+
+impl MockEthernetDevice {
+    pub fn new(in_packets: Receiver<io::Result<Box<[u8]>>>,
+               out_channel: Sender<Vec<u8>>)
+               -> MockEthernetDevice {
+        MockEthernetDevice {
+            in_packets: in_packets,
+            out_channel: out_channel,
+            scratch: vec![],
+        }
+    }
+}
+
+impl<'a> EthernetDevice<'a> for MockEthernetDevice {
+    type RxToken = MockRxToken;
+    type TxToken = MockTxToken<'a>;
+
+    fn receive(&'a mut self) -> Option<Self::RxToken> {
+        match self.in_packets.try_recv() {
+            Ok(Ok(data)) => Some(MockRxToken { time: time::SystemTime::now(), data: data }),
+            Ok(Err(_)) | Err(_) => None,
+        }
+    }
+
+    fn transmit(&'a mut self, len: usize) -> Option<Self::TxToken> {
+        if self.scratch.len() < len {
+            self.scratch.resize(len, 0);
+        }
+        Some(MockTxToken {
+            scratch: &mut self.scratch[..len],
+            out_channel: &self.out_channel,
+        })
+    }
+}